@@ -0,0 +1,139 @@
+// Formatting throughput over large synthetic iRules, run with `cargo bench`,
+// or `cargo bench -- emitter` for the cases whose names contain `emitter`.
+// The crate builds without registry access, so instead of criterion this is
+// a small harness of its own: each case is warmed up, then timed over about
+// a second of runs, and the median, fastest and spread of those runs are
+// reported along with the input size per second the median amounts to.
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use tcl_formatter::emitter::Emitter;
+use tcl_formatter::formatter::{Formatter, FormatterOptions};
+
+const WARM_UP: Duration = Duration::from_millis(300);
+const BUDGET: Duration = Duration::from_secs(1);
+const MIN_RUNS: usize = 10;
+
+// `when` blocks of mostly comments
+fn comment_heavy(events: usize) -> Vec<u8> {
+    let mut source = String::new();
+    for event in 0..events {
+        source.push_str(&format!("# handler {event}\n# checks the host header\n"));
+        source.push_str("when HTTP_REQUEST {\n");
+        for line in 0..20 {
+            source.push_str(&format!(
+                "  # step {line}: look the client up in the table\n"
+            ));
+            source.push_str(&format!("  set key_{line} [HTTP::host] ;# the host\n"));
+        }
+        source.push_str("}\n\n");
+    }
+    source.into_bytes()
+}
+
+// procs and `when` blocks of plain statements, with the odd list and branch
+fn statement_heavy(events: usize) -> Vec<u8> {
+    let mut source = String::new();
+    for event in 0..events {
+        source.push_str(&format!("proc helper_{event} {{a b}} {{\n"));
+        source.push_str("set total [expr {$a + $b}]\nreturn $total\n}\n");
+        source.push_str("when HTTP_REQUEST {\n");
+        for line in 0..30 {
+            source.push_str(&format!("set value_{line} [string tolower [HTTP::uri]]\n"));
+            source.push_str(&format!(
+                "log local0. \"request {line} for [HTTP::host]\"\n"
+            ));
+        }
+        source.push_str("set methods [list GET HEAD POST PUT DELETE]\n");
+        source.push_str(
+            "if {[HTTP::method] eq \"POST\"} {\npool post_pool\n} else {\npool default_pool\n}\n",
+        );
+        source.push_str("}\n");
+    }
+    source.into_bytes()
+}
+
+// the times of the runs of `f` after warming up
+struct Timing {
+    median: Duration,
+    fastest: Duration,
+    deviation: Duration, // standard deviation of the runs
+    runs: usize,
+}
+
+fn measure(mut f: impl FnMut()) -> Timing {
+    let start = Instant::now();
+    while start.elapsed() < WARM_UP {
+        f();
+    }
+    let start = Instant::now();
+    let mut times = Vec::new();
+    while times.len() < MIN_RUNS || start.elapsed() < BUDGET {
+        let run = Instant::now();
+        f();
+        times.push(run.elapsed().as_secs_f64());
+    }
+    times.sort_by(f64::total_cmp);
+    let mean = times.iter().sum::<f64>() / times.len() as f64;
+    let variance = times.iter().map(|t| (t - mean).powi(2)).sum::<f64>() / times.len() as f64;
+    Timing {
+        median: Duration::from_secs_f64(times[times.len() / 2]),
+        fastest: Duration::from_secs_f64(times[0]),
+        deviation: Duration::from_secs_f64(variance.sqrt()),
+        runs: times.len(),
+    }
+}
+
+fn report(name: &str, bytes: usize, timing: Timing) {
+    let throughput = bytes as f64 / timing.median.as_secs_f64() / (1024.0 * 1024.0);
+    println!(
+        "{name:<24} median {:>10.3?} fastest {:>10.3?} ± {:>9.3?} {throughput:>8.1} MiB/s ({} runs)",
+        timing.median, timing.fastest, timing.deviation, timing.runs
+    );
+}
+
+fn main() {
+    // `cargo bench` passes `--bench`; any other argument selects cases
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with("--"));
+    let selected = |name: &str| filter.as_deref().is_none_or(|filter| name.contains(filter));
+
+    for (name, source) in [
+        ("format/comment-heavy", comment_heavy(2000)),
+        ("format/statement-heavy", statement_heavy(1000)),
+    ] {
+        if !selected(name) {
+            continue;
+        }
+        let timing = measure(|| {
+            let formatter = Formatter::with_options(FormatterOptions::default());
+            black_box(formatter.try_format(black_box(&source)).expect("formats"));
+        });
+        report(name, source.len(), timing);
+    }
+
+    // the emitter on its own, as the formatter drives it for a statement
+    if !selected("emitter/statements") {
+        return;
+    }
+    let lines = 200_000;
+    let timing = measure(|| {
+        let mut out = Emitter::new();
+        for line in 0..lines {
+            out.indent();
+            out.word(b"set");
+            out.space();
+            out.word(b"value");
+            out.space();
+            out.word(if line % 2 == 0 { b"[HTTP::uri]" } else { b"{}" });
+            out.newline();
+            if line % 50 == 0 {
+                out.open_block();
+            } else if line % 50 == 49 {
+                out.close_block();
+                out.newline();
+            }
+        }
+        black_box(out.finish());
+    });
+    report("emitter/statements", lines * 24, timing);
+}
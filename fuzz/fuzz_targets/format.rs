@@ -0,0 +1,9 @@
+// Any input must come back as formatted output or an error, never a panic
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tcl_formatter::formatter::Formatter;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Formatter::new().try_format(data);
+});
@@ -1,45 +1,372 @@
-// Assume all Vec<u8> in ASTs are l-stripped and r-stripped of '\s\t' | ';'
-pub enum Ast {
-    Block(Vec<Ast>),  // list of ASTs
-    Comment(Vec<u8>), // text after #
+use std::borrow::Cow;
+
+// Source text of a node, borrowed from the input when parsed and owned when
+// built by hand or rewritten by a pass
+pub type Text<'src> = Cow<'src, [u8]>;
+
+// An `if`/`elseif` condition: `{ expr }`, or a bare word such as `$x`,
+// `[cmd]` or `"$a == $b"` that Tcl substitutes before evaluating it
+pub struct Condition<'src> {
+    pub text: Text<'src>, // inside the braces, or the word as written
+    pub braced: bool,
+}
+
+impl<'src> Condition<'src> {
+    pub fn braced(text: Text<'src>) -> Self {
+        Self { text, braced: true }
+    }
+
+    pub fn into_owned(self) -> Condition<'static> {
+        Condition {
+            text: owned(self.text),
+            braced: self.braced,
+        }
+    }
+}
+
+impl std::ops::Deref for Condition<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.text
+    }
+}
+
+// An AST that doesn't borrow from any input
+pub type OwnedAst = Ast<'static>;
+
+// Assume all Text in ASTs is l-stripped and r-stripped of '\s\t' | ';'
+pub enum Ast<'src> {
+    Block(Vec<Ast<'src>>),       // list of ASTs
+    Comment(Text<'src>),         // text after #
+    TrailingComment(Text<'src>), // text after `;#` ending the line of the statement before
+    Verbatim(Text<'src>),        // a shebang or editor modeline, `#` included, kept as written
+    Semicolon,                   // `;` between the commands before and after it on one line
     Procedure {
-        name: Vec<u8>,
-        parameters: Vec<Vec<u8>>,
-        body: Box<Ast>,
+        name: Text<'src>,
+        parameters: Vec<Text<'src>>,
+        body: Box<Ast<'src>>,
     },
     If {
-        condition_body_clauses: Vec<(Vec<u8>, Ast)>,
-        maybe_block_if_false: Option<Box<Ast>>,
+        condition_body_clauses: Vec<(Condition<'src>, Ast<'src>)>,
+        maybe_block_if_false: Option<Box<Ast<'src>>>,
     },
     Switch {
-        condition: Vec<u8>,
-        value_block_or_fallthrough_vec: Vec<(Vec<u8>, Option<Ast>)>,
+        condition: Text<'src>,
+        value_block_or_fallthrough_vec: Vec<(Text<'src>, Option<Ast<'src>>)>,
     },
     When {
-        event_name: Vec<u8>,
-        body: Box<Ast>,
+        event_name: Text<'src>,
+        body: Box<Ast<'src>>,
+    },
+    // `eval`, `uplevel` or `after` with a braced script; dynamic scripts are
+    // Statement::Other
+    Script {
+        command: Text<'src>,
+        arguments: Vec<Text<'src>>, // words before the script, like the uplevel level or delay
+        body: Box<Ast<'src>>,
     },
-    Statement(Statement),
+    Statement(Statement<'src>),
     EmptyLine,
     // TODO: GTP/UDP func calls
 }
 
-pub enum Statement {
-    Set { identifier: Vec<u8>, value: Vec<u8> },
-    Log { bucket: Vec<u8>, value: Vec<u8> },
-    Snat { ip_address: Vec<u8>, port: Vec<u8> },
-    Node { ip_address: Vec<u8>, port: Vec<u8> },
-    Pool { identifier: Vec<u8> },
-    SnatPool { identifier: Vec<u8> },
-    Return { value: Option<Vec<u8>> },
-    Other { data: Vec<u8> },
+pub enum Statement<'src> {
+    Set {
+        identifier: Text<'src>,
+        value: Text<'src>,
+    },
+    Log {
+        options: Vec<Text<'src>>,        // like `-noname`
+        destination: Option<Text<'src>>, // remote syslog server, like `10.0.0.1:514`
+        target: Option<Text<'src>>,      // `<facility>.<level>`, like `local0.warning`
+        message: Vec<Text<'src>>,
+    },
+    Snat {
+        ip_address: Text<'src>,
+        port: Text<'src>,
+    },
+    Node {
+        ip_address: Text<'src>,
+        port: Text<'src>,
+    },
+    Pool {
+        identifier: Text<'src>,
+    },
+    SnatPool {
+        identifier: Text<'src>,
+    },
+    Return {
+        options: Vec<(Text<'src>, Text<'src>)>, // `-code error`, `-level 0`, ...
+        value: Option<Text<'src>>,
+    },
+    PackageRequire {
+        arguments: Text<'src>, // package name, optionally version and `-exact`
+    },
+    Source {
+        path: Text<'src>,
+    },
+    NamespaceImport {
+        patterns: Text<'src>,
+    },
+    // `class match`, `class search` or `class lookup` on a data group
+    Class {
+        subcommand: Text<'src>,
+        options: Vec<Text<'src>>, // `-value`, `-nocase`, ..., and `--` if written
+        arguments: Vec<Text<'src>>,
+    },
+    // `table set`, `table lookup`, ... on the session table
+    Table {
+        subcommand: Text<'src>,
+        options: Vec<Text<'src>>, // `-notouch`, `-subtable <name>`, ..., and `--` if written
+        arguments: Vec<Text<'src>>, // key, value, timeout and lifetime as applicable
+    },
+    Other {
+        data: Text<'src>,
+    },
+}
+
+impl Ast<'_> {
+    // detaches the tree from the input it was parsed from
+    pub fn into_owned(self) -> OwnedAst {
+        match self {
+            Ast::Block(trees) => Ast::Block(trees.into_iter().map(Ast::into_owned).collect()),
+            Ast::Comment(text) => Ast::Comment(owned(text)),
+            Ast::TrailingComment(text) => Ast::TrailingComment(owned(text)),
+            Ast::Verbatim(text) => Ast::Verbatim(owned(text)),
+            Ast::Semicolon => Ast::Semicolon,
+            Ast::Procedure {
+                name,
+                parameters,
+                body,
+            } => Ast::Procedure {
+                name: owned(name),
+                parameters: parameters.into_iter().map(owned).collect(),
+                body: Box::new(body.into_owned()),
+            },
+            Ast::If {
+                condition_body_clauses,
+                maybe_block_if_false,
+            } => Ast::If {
+                condition_body_clauses: condition_body_clauses
+                    .into_iter()
+                    .map(|(condition, body)| (condition.into_owned(), body.into_owned()))
+                    .collect(),
+                maybe_block_if_false: maybe_block_if_false.map(|body| Box::new(body.into_owned())),
+            },
+            Ast::Switch {
+                condition,
+                value_block_or_fallthrough_vec,
+            } => Ast::Switch {
+                condition: owned(condition),
+                value_block_or_fallthrough_vec: value_block_or_fallthrough_vec
+                    .into_iter()
+                    .map(|(value, body)| (owned(value), body.map(Ast::into_owned)))
+                    .collect(),
+            },
+            Ast::When { event_name, body } => Ast::When {
+                event_name: owned(event_name),
+                body: Box::new(body.into_owned()),
+            },
+            Ast::Script {
+                command,
+                arguments,
+                body,
+            } => Ast::Script {
+                command: owned(command),
+                arguments: arguments.into_iter().map(owned).collect(),
+                body: Box::new(body.into_owned()),
+            },
+            Ast::Statement(s) => Ast::Statement(s.into_owned()),
+            Ast::EmptyLine => Ast::EmptyLine,
+        }
+    }
+}
+
+impl Statement<'_> {
+    pub fn into_owned(self) -> Statement<'static> {
+        match self {
+            Statement::Set { identifier, value } => Statement::Set {
+                identifier: owned(identifier),
+                value: owned(value),
+            },
+            Statement::Log {
+                options,
+                destination,
+                target,
+                message,
+            } => Statement::Log {
+                options: options.into_iter().map(owned).collect(),
+                destination: destination.map(owned),
+                target: target.map(owned),
+                message: message.into_iter().map(owned).collect(),
+            },
+            Statement::Snat { ip_address, port } => Statement::Snat {
+                ip_address: owned(ip_address),
+                port: owned(port),
+            },
+            Statement::Node { ip_address, port } => Statement::Node {
+                ip_address: owned(ip_address),
+                port: owned(port),
+            },
+            Statement::Pool { identifier } => Statement::Pool {
+                identifier: owned(identifier),
+            },
+            Statement::SnatPool { identifier } => Statement::SnatPool {
+                identifier: owned(identifier),
+            },
+            Statement::Return { options, value } => Statement::Return {
+                options: options
+                    .into_iter()
+                    .map(|(name, value)| (owned(name), owned(value)))
+                    .collect(),
+                value: value.map(owned),
+            },
+            Statement::PackageRequire { arguments } => Statement::PackageRequire {
+                arguments: owned(arguments),
+            },
+            Statement::Source { path } => Statement::Source { path: owned(path) },
+            Statement::NamespaceImport { patterns } => Statement::NamespaceImport {
+                patterns: owned(patterns),
+            },
+            Statement::Class {
+                subcommand,
+                options,
+                arguments,
+            } => Statement::Class {
+                subcommand: owned(subcommand),
+                options: options.into_iter().map(owned).collect(),
+                arguments: arguments.into_iter().map(owned).collect(),
+            },
+            Statement::Table {
+                subcommand,
+                options,
+                arguments,
+            } => Statement::Table {
+                subcommand: owned(subcommand),
+                options: options.into_iter().map(owned).collect(),
+                arguments: arguments.into_iter().map(owned).collect(),
+            },
+            Statement::Other { data } => Statement::Other { data: owned(data) },
+        }
+    }
+
+    // the command word followed by its arguments, as written on one line
+    pub fn words(&self) -> Vec<&[u8]> {
+        match self {
+            Statement::Set { identifier, value } => vec![b"set", identifier, value],
+            Statement::Log {
+                options,
+                destination,
+                target,
+                message,
+            } => std::iter::once(&b"log"[..])
+                .chain(options.iter().map(|word| &word[..]))
+                .chain(destination.as_deref())
+                .chain(target.as_deref())
+                .chain(message.iter().map(|word| &word[..]))
+                .collect(),
+            Statement::Snat { ip_address, port } => vec![b"snat", ip_address, port],
+            Statement::Node { ip_address, port } => vec![b"node", ip_address, port],
+            Statement::Pool { identifier } => vec![b"pool", identifier],
+            Statement::SnatPool { identifier } => vec![b"snatpool", identifier],
+            Statement::Return { options, value } => std::iter::once(&b"return"[..])
+                .chain(
+                    options
+                        .iter()
+                        .flat_map(|(name, value)| [&name[..], &value[..]]),
+                )
+                .chain(value.as_deref())
+                .collect(),
+            Statement::PackageRequire { arguments } => vec![b"package", b"require", arguments],
+            Statement::Source { path } => vec![b"source", path],
+            Statement::NamespaceImport { patterns } => vec![b"namespace", b"import", patterns],
+            Statement::Class {
+                subcommand,
+                options,
+                arguments,
+            } => [&b"class"[..], subcommand]
+                .into_iter()
+                .chain(options.iter().chain(arguments).map(|word| &word[..]))
+                .collect(),
+            Statement::Table {
+                subcommand,
+                options,
+                arguments,
+            } => [&b"table"[..], subcommand]
+                .into_iter()
+                .chain(options.iter().chain(arguments).map(|word| &word[..]))
+                .collect(),
+            Statement::Other { data } => vec![data],
+        }
+    }
+}
+
+// The facility and level of a `log` target: `local0` and `warning` for
+// `local0.warning`. The level is empty for `local0.`, and None for a target
+// without a dot.
+pub fn facility_and_level(target: &[u8]) -> (&[u8], Option<&[u8]>) {
+    match target.iter().position(|&c| c == b'.') {
+        Some(dot) => (&target[..dot], Some(&target[dot + 1..])),
+        None => (target, None),
+    }
+}
+
+// the words of a command, split at whitespace outside of quotes, braces and
+// brackets
+pub fn split_words(command: &[u8]) -> Vec<&[u8]> {
+    let mut words = Vec::new();
+    let mut idx = 0;
+    while idx < command.len() {
+        if command[idx].is_ascii_whitespace() {
+            idx += 1;
+            continue;
+        }
+        let start = idx;
+        while idx < command.len() && !command[idx].is_ascii_whitespace() {
+            let rest = &command[idx..];
+            idx += match rest[0] {
+                b'"' => word_len(rest, b'"', b'"'),
+                b'[' => word_len(rest, b'[', b']'),
+                b'{' => word_len(rest, b'{', b'}'),
+                b'\\' => 2.min(rest.len()),
+                _ => 1,
+            };
+        }
+        words.push(&command[start..idx]);
+    }
+    words
 }
 
-impl std::fmt::Debug for Ast {
+// length of a delimited word starting at `data[0]`, or all of `data` if unterminated
+pub fn word_len(data: &[u8], open: u8, close: u8) -> usize {
+    let mut depth = 0;
+    let mut escaped = false;
+    for (idx, &c) in data.iter().enumerate() {
+        match c {
+            _ if escaped => escaped = false,
+            b'\\' => escaped = true,
+            c if c == close && (depth == 1 || open == close) && idx > 0 => return idx + 1,
+            c if c == open => depth += 1,
+            c if c == close => depth -= 1,
+            _ => {}
+        }
+    }
+    data.len()
+}
+
+fn owned(text: Text<'_>) -> Text<'static> {
+    Cow::Owned(text.into_owned())
+}
+
+impl std::fmt::Debug for Ast<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Block(trees) => write!(f, "Ast::Block of {} trees", trees.len()),
             Self::Comment(_) => write!(f, "Ast::Comment"),
+            Self::TrailingComment(_) => write!(f, "Ast::TrailingComment"),
+            Self::Verbatim(_) => write!(f, "Ast::Verbatim"),
+            Self::Semicolon => write!(f, "Ast::Semicolon"),
             Self::Procedure { parameters, .. } => {
                 write!(f, "Ast::Procedure with {} parameters", parameters.len())
             }
@@ -55,6 +382,9 @@ impl std::fmt::Debug for Ast {
             Self::Switch { condition, .. } => {
                 write!(f, "Ast::Switch with {} conditions", condition.len())
             }
+            Self::Script { command, .. } => {
+                write!(f, "Ast::Script ({})", String::from_utf8_lossy(command))
+            }
             Self::Statement(s) => match s {
                 Statement::Set { .. } => write!(f, "Ast::Statement::Set"),
                 Statement::Log { .. } => write!(f, "Ast::Statement::Log"),
@@ -62,16 +392,96 @@ impl std::fmt::Debug for Ast {
                 Statement::Node { .. } => write!(f, "Ast::Statement::Node"),
                 Statement::Pool { .. } => write!(f, "Ast::Statement::Pool"),
                 Statement::SnatPool { .. } => write!(f, "Ast::Statement::SnatPool"),
-                Statement::Return { value } if value.is_some() => {
+                Statement::Return { value, .. } if value.is_some() => {
                     write!(f, "Ast::Statement::Return with value")
                 }
                 Statement::Return { .. } => write!(f, "Ast::Statement::Return"),
+                Statement::PackageRequire { .. } => write!(f, "Ast::Statement::PackageRequire"),
+                Statement::Source { .. } => write!(f, "Ast::Statement::Source"),
+                Statement::NamespaceImport { .. } => write!(f, "Ast::Statement::NamespaceImport"),
+                Statement::Class { subcommand, .. } => write!(
+                    f,
+                    "Ast::Statement::Class ({})",
+                    String::from_utf8_lossy(subcommand)
+                ),
+                Statement::Table { subcommand, .. } => write!(
+                    f,
+                    "Ast::Statement::Table ({})",
+                    String::from_utf8_lossy(subcommand)
+                ),
                 Statement::Other { data } => {
                     write!(f, "Ast::Statement::Other with length {}", data.len())
                 }
             },
             Self::EmptyLine => write!(f, "Ast::EmptyLine"),
-            Self::When { event_name, .. } => write!(f, "Ast::When ({})", String::from_utf8_lossy(event_name)),
+            Self::When { event_name, .. } => {
+                write!(f, "Ast::When ({})", String::from_utf8_lossy(event_name))
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::{split_words, word_len, Ast, OwnedAst, Statement};
+    use crate::{formatter::Formatter, lexer::Lexer, parser::Parser};
+
+    fn parse(source: &[u8]) -> Ast<'_> {
+        let (tokens, spans) = Lexer::new().lex_with_spans(source).unwrap();
+        Parser::new(source, &tokens, &spans).parse().unwrap()
+    }
+
+    #[test]
+    fn borrows_text_from_the_input() {
+        let source = b"proc greet {name} {\n    set greeting \"hello $name\"\n}\n";
+        let ast = parse(source);
+        let Ast::Block(trees) = &ast else {
+            panic!("expected a block");
+        };
+        let Ast::Procedure { name, body, .. } = &trees[0] else {
+            panic!("expected a proc");
+        };
+        assert!(matches!(name, Cow::Borrowed(b"greet")));
+        let Ast::Block(statements) = &**body else {
+            panic!("expected a block");
+        };
+        let Ast::Statement(Statement::Set { identifier, value }) = &statements[0] else {
+            panic!("expected a set");
+        };
+        assert!(matches!(identifier, Cow::Borrowed(b"greeting")));
+        let Cow::Borrowed(value) = value else {
+            panic!("expected borrowed text");
+        };
+        assert!(source.as_ptr_range().contains(&value.as_ptr()));
+    }
+
+    #[test]
+    fn owned_trees_outlive_the_input() {
+        let source = b"when HTTP_REQUEST {\n    if {$a} {\n        pool p1\n    } else {\n        log local0. \"no\"\n    }\n}\n".to_vec();
+        let expected = Formatter::new().format(parse(&source));
+        let owned: OwnedAst = parse(&source).into_owned();
+        drop(source);
+        assert_eq!(Formatter::new().format(owned), expected);
+    }
+
+    #[test]
+    fn splits_words_outside_delimiters() {
+        let words = split_words(b"  set a \"b c\" {d {e f}} [g h] i\\ j\t");
+        assert_eq!(
+            words,
+            [
+                &b"set"[..],
+                b"a",
+                b"\"b c\"",
+                b"{d {e f}}",
+                b"[g h]",
+                b"i\\ j"
+            ]
+        );
+        assert_eq!(word_len(b"{a {b} c} d", b'{', b'}'), 9);
+        assert_eq!(word_len(b"\"a\\\"b\" c", b'"', b'"'), 6);
+        assert_eq!(word_len(b"[unterminated", b'[', b']'), 13);
+    }
+}
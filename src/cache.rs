@@ -0,0 +1,219 @@
+// On-disk record of inputs already known to be formatted, so repeat runs
+// over a large tree only lex and parse the files that changed.
+//
+// Entries are keyed by a hash of the file content together with a
+// fingerprint of everything that affects the output (see `Fingerprint`), so
+// changing the configuration invalidates them.
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::encoding::Encoding;
+use crate::formatter::FormatterOptions;
+use crate::lint::LintOptions;
+
+pub const CACHE_FILE_NAME: &str = ".tclfmtcache";
+
+// Written as the first line; a cache from another version is discarded
+const HEADER: &str = concat!("tcl-formatter ", env!("CARGO_PKG_VERSION"));
+
+pub struct Cache {
+    path: PathBuf,
+    entries: HashSet<u64>,
+    dirty: bool,
+}
+
+impl Cache {
+    // A missing, unreadable or outdated cache file starts an empty cache
+    pub fn load(path: &Path) -> Self {
+        let mut entries = HashSet::new();
+        if let Ok(text) = std::fs::read_to_string(path) {
+            let mut lines = text.lines();
+            if lines.next() == Some(HEADER) {
+                entries.extend(lines.filter_map(|line| u64::from_str_radix(line, 16).ok()));
+            }
+        }
+        Self {
+            path: path.to_path_buf(),
+            entries,
+            dirty: false,
+        }
+    }
+
+    pub fn contains(&self, key: u64) -> bool {
+        self.entries.contains(&key)
+    }
+
+    pub fn insert(&mut self, key: u64) {
+        self.dirty |= self.entries.insert(key);
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort();
+        let mut text = format!("{HEADER}\n");
+        for entry in entries {
+            text.push_str(&format!("{entry:016x}\n"));
+        }
+        std::fs::write(&self.path, text)
+    }
+}
+
+// Every setting besides the input that decides what formatting a file writes
+// and reports. It is taken from the whole `Config`, so a new option has to
+// be sorted into those that matter here and those folded into `options`.
+#[derive(Debug)]
+pub struct Fingerprint<'a> {
+    pub options: &'a FormatterOptions, // after the style, .editorconfig and directives
+    pub passes: &'a [String],
+    pub lint: &'a LintOptions,
+    pub fix: bool, // `--fix`
+    pub max_depth: usize,
+    pub encoding: Encoding,
+    pub keep_bom: bool,
+}
+
+impl<'a> Fingerprint<'a> {
+    pub fn new(config: &'a Config, options: &'a FormatterOptions, fix: bool) -> Self {
+        let Config {
+            passes,
+            lint,
+            max_depth,
+            encoding,
+            keep_bom,
+            // folded into `options`
+            style: _,
+            indent_width: _,
+            use_tabs: _,
+            cuddle_else: _,
+            pad_braces: _,
+            max_empty_lines: _,
+            line_ending: _,
+            final_newline: _,
+            align_trailing_comments: _,
+            align_switch_arms: _,
+            blank_line_between_definitions: _,
+            compact_empty_blocks: _,
+            max_width: _,
+            one_line_bodies: _,
+            keep_semicolons: _,
+            editorconfig: _,
+            modelines: _,
+            // resolved before the file is read, or unused for Tcl files
+            exclude: _,
+            overrides: _,
+            embed_paths: _,
+        } = config;
+        Self {
+            options,
+            passes,
+            lint,
+            fix,
+            max_depth: *max_depth,
+            encoding: *encoding,
+            keep_bom: *keep_bom,
+        }
+    }
+}
+
+impl std::fmt::Display for Fingerprint<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+// Cache key of `content` formatted under the settings described by `fingerprint`
+pub fn key(content: &[u8], fingerprint: &str) -> u64 {
+    let mut hash = fnv1a(FNV_OFFSET_BASIS, fingerprint.as_bytes());
+    hash = fnv1a(hash, &[0]);
+    fnv1a(hash, content)
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+// 64-bit FNV-1a, stable across runs and Rust versions unlike `DefaultHasher`
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fnv1a, key, Cache, Fingerprint, FNV_OFFSET_BASIS, HEADER};
+    use crate::{config::Config, encoding::Encoding, formatter::FormatterOptions};
+
+    #[test]
+    fn hashes_with_fnv1a() {
+        // reference values of 64-bit FNV-1a
+        assert_eq!(fnv1a(FNV_OFFSET_BASIS, b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(FNV_OFFSET_BASIS, b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(FNV_OFFSET_BASIS, b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn keys_depend_on_content_and_settings() {
+        assert_eq!(key(b"set a 1\n", "indent=4"), key(b"set a 1\n", "indent=4"));
+        assert_ne!(key(b"set a 1\n", "indent=4"), key(b"set a 1\n", "indent=2"));
+        assert_ne!(key(b"set a 1\n", "indent=4"), key(b"set a 2\n", "indent=4"));
+        // the separator keeps the fingerprint from running into the content
+        assert_ne!(key(b"bc", "a"), key(b"c", "ab"));
+    }
+
+    #[test]
+    fn output_settings_change_the_key() {
+        let options = FormatterOptions::default();
+        let key_under = |config: &Config, fix: bool| -> u64 {
+            key(
+                b"set a 1\n",
+                &Fingerprint::new(config, &options, fix).to_string(),
+            )
+        };
+        let config = Config::default();
+        let base = key_under(&config, false);
+        assert_eq!(base, key_under(&Config::default(), false));
+        assert_ne!(base, key_under(&config, true));
+        let changes: [fn(&mut Config); 5] = [
+            |config| config.keep_bom = !config.keep_bom,
+            |config| config.encoding = Encoding::Latin1,
+            |config| config.max_depth = 3,
+            |config| config.passes = vec!["collapse-elseif".to_string()],
+            |config| config.lint.max_complexity = Some(2),
+        ];
+        for change in changes {
+            let mut changed = Config::default();
+            change(&mut changed);
+            assert_ne!(base, key_under(&changed, false), "{changed:?}");
+        }
+    }
+
+    #[test]
+    fn round_trips_through_the_cache_file() {
+        let path = std::env::temp_dir().join(format!("tclfmt-cache-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut cache = Cache::load(&path);
+        assert!(!cache.contains(1));
+        cache.insert(1);
+        cache.insert(u64::MAX);
+        cache.save().unwrap();
+
+        let cache = Cache::load(&path);
+        assert!(cache.contains(1) && cache.contains(u64::MAX));
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("{HEADER}\n0000000000000001\nffffffffffffffff\n")
+        );
+
+        // a cache written by another version is discarded
+        std::fs::write(&path, "tcl-formatter 0.0.0\n0000000000000001\n").unwrap();
+        assert!(!Cache::load(&path).contains(1));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,467 @@
+use std::path::PathBuf;
+
+use tcl_formatter::cache::CACHE_FILE_NAME;
+use tcl_formatter::formatter::FormatterOptions;
+use tcl_formatter::highlight::Style;
+
+pub const USAGE: &str = "\
+usage: tcl-formatter [options] <path>...
+       tcl-formatter [options] --stdin-filepath <path> < input.tcl
+       tcl-formatter [options] --staged
+       tcl-formatter [options] --watch <dir>
+       tcl-formatter --hook install
+
+options:
+    --check             don't write files, report those that would be reformatted
+    --diff              don't write files, print a unified diff of the changes
+                        formatting would make
+    --word-diff         like --diff, marking the words that differ within
+                        changed lines
+    --list-different    don't write files, print the paths of those that would be
+                        reformatted, one per line
+    --fix               apply the fixes lint rules attach to their findings
+                        before formatting
+    --verify            refuse to write output whose tokens differ from the input
+                        beyond layout
+    --emit <mode>       formatted (default): rewrite files in place
+                        minified: print size-reduced source to stdout
+                        highlighted: print colorized formatted source to stdout
+                        tmsh: print a `tmsh create ltm rule` command to stdout
+                        as3: print an AS3 iRule declaration to stdout
+    --stdin-filepath <path>
+                        format stdin to stdout; <path> locates .tclfmt.toml,
+                        .editorconfig and .tclfmtignore (ignored input is
+                        echoed unchanged)
+    --staged            format the Tcl files staged in git, as they are in the
+                        index, and stage the result
+    --watch <dir>       reformat Tcl files under <dir> as they are saved,
+                        after formatting any <path> given
+    --hook install      write a git pre-commit hook running `--staged`
+    --cache             skip files recorded as formatted in .tclfmtcache
+    --cache-location <path>
+                        cache file to use, implies --cache
+    --style <preset>    f5-irule | tcl-std, overrides `style` in .tclfmt.toml
+    --keep-comments     keep comments when minifying
+    --highlight-format <ansi|html>
+                        markup used by `--emit highlighted` (default: ansi)
+    --color <auto|always|never>
+                        colorize diagnostics and diffs (default: auto, when
+                        stderr, or stdout for diffs, is a terminal and NO_COLOR
+                        is unset)
+    --message-format <human|json|sarif>
+                        diagnostics as text, newline-delimited JSON on stdout
+                        or a SARIF 2.1.0 document on stdout
+    --fail-on <classes> comma-separated failures that set the exit status:
+                        reformat (1), parse (2), io (3), or none
+                        (default: reformat,parse,io)
+    --report html <path>
+                        also write a self-contained HTML report to <path>:
+                        summary counts, findings grouped by rule and the diff
+                        of each file formatting changes
+    --stats             print code metrics instead of formatting
+    --stats-format <table|json>
+                        layout of the `--stats` report (default: table)
+";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Emit {
+    Formatted,
+    Minified,
+    Tmsh, // formatted, wrapped in a tmsh command
+    As3,  // formatted, embedded in an AS3 declaration
+    Highlighted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatsFormat {
+    Table,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageFormat {
+    Human, // stderr
+    Json,  // stdout, one object per line
+    Sarif, // stdout, one document once all files are processed
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Color {
+    Auto,
+    Always,
+    Never,
+}
+
+// What a run can fail on; the exit status is that of the worst one `--fail-on`
+// selects, 0 if there is none
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Failure {
+    Reformat = 1, // a file would be reformatted, or was listed or diffed
+    Parse = 2,    // a file did not lex, parse or verify, or has error diagnostics
+    Io = 3,       // a file or config could not be read or written, or bad arguments
+}
+
+impl Failure {
+    pub const ALL: &[Failure] = &[Failure::Reformat, Failure::Parse, Failure::Io];
+
+    pub fn exit_code(self) -> i32 {
+        self as i32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportFormat {
+    Html,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hook {
+    Install,
+}
+
+#[derive(Debug)]
+pub enum ArgsFail {
+    UnknownFlag(String),
+    MissingValue(String), // flag given without its value
+    InvalidValue { flag: String, value: String },
+    NoPaths,
+}
+type Result<T> = std::result::Result<T, ArgsFail>;
+
+impl std::fmt::Display for ArgsFail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownFlag(flag) => write!(f, "unknown flag `{flag}`"),
+            Self::MissingValue(flag) => write!(f, "`{flag}` expects a value"),
+            Self::InvalidValue { flag, value } => write!(f, "invalid value `{value}` for `{flag}`"),
+            Self::NoPaths => write!(f, "no input paths given"),
+        }
+    }
+}
+
+pub struct Args {
+    pub check: bool,
+    pub list_different: bool, // with `check`, list files instead of reporting them
+    pub diff: bool,           // with `check`, print diffs instead of reporting files
+    pub word_diff: bool,
+    pub fix: bool,    // apply the fixes of lint findings
+    pub verify: bool, // compare the tokens of input and output before writing
+    pub emit: Emit,
+    pub style: Option<String>, // formatting preset
+    pub keep_comments: bool,
+    pub highlight_style: Style,
+    pub stats: Option<StatsFormat>, // report metrics instead of formatting
+    pub message_format: MessageFormat,
+    pub fail_on: Vec<Failure>, // failures that set the exit status
+    pub report: Option<(ReportFormat, PathBuf)>, // written once all files are processed
+    pub color: Color,          // of human-readable diagnostics
+    pub paths: Vec<PathBuf>,
+    pub stdin_filepath: Option<PathBuf>, // format stdin as if it was this file
+    pub staged: bool,                    // format the git index instead of paths
+    pub hook: Option<Hook>,
+    pub watch: Option<PathBuf>, // directory to keep reformatting
+    pub cache: Option<PathBuf>, // file recording already formatted inputs
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut parsed = Args {
+            check: false,
+            list_different: false,
+            diff: false,
+            word_diff: false,
+            fix: false,
+            verify: false,
+            emit: Emit::Formatted,
+            style: None,
+            keep_comments: false,
+            highlight_style: Style::Ansi,
+            stats: None,
+            message_format: MessageFormat::Human,
+            fail_on: Failure::ALL.to_vec(),
+            report: None,
+            color: Color::Auto,
+            paths: Vec::new(),
+            stdin_filepath: None,
+            staged: false,
+            hook: None,
+            watch: None,
+            cache: None,
+        };
+        // `--flag=value` is `--flag value`
+        let mut args = args.into_iter().flat_map(|arg| match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => {
+                vec![flag.to_string(), value.to_string()]
+            }
+            _ => vec![arg],
+        });
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--check" => parsed.check = true,
+                "--diff" | "--word-diff" => {
+                    parsed.check = true;
+                    parsed.diff = true;
+                    parsed.word_diff |= arg == "--word-diff";
+                }
+                "--list-different" => {
+                    parsed.check = true;
+                    parsed.list_different = true;
+                }
+                "--fix" => parsed.fix = true,
+                "--verify" => parsed.verify = true,
+                "--emit" => {
+                    let value = args.next().ok_or(ArgsFail::MissingValue(arg.clone()))?;
+                    parsed.emit = match value.as_str() {
+                        "formatted" => Emit::Formatted,
+                        "minified" => Emit::Minified,
+                        "tmsh" => Emit::Tmsh,
+                        "as3" => Emit::As3,
+                        "highlighted" => Emit::Highlighted,
+                        _ => return Err(ArgsFail::InvalidValue { flag: arg, value }),
+                    };
+                }
+                "--style" => {
+                    let value = args.next().ok_or(ArgsFail::MissingValue(arg.clone()))?;
+                    if !FormatterOptions::PRESETS.contains(&value.as_str()) {
+                        return Err(ArgsFail::InvalidValue { flag: arg, value });
+                    }
+                    parsed.style = Some(value);
+                }
+                "--keep-comments" => parsed.keep_comments = true,
+                "--stdin-filepath" => {
+                    let value = args.next().ok_or(ArgsFail::MissingValue(arg.clone()))?;
+                    parsed.stdin_filepath = Some(PathBuf::from(value));
+                }
+                "--staged" => parsed.staged = true,
+                "--hook" => {
+                    let value = args.next().ok_or(ArgsFail::MissingValue(arg.clone()))?;
+                    parsed.hook = match value.as_str() {
+                        "install" => Some(Hook::Install),
+                        _ => return Err(ArgsFail::InvalidValue { flag: arg, value }),
+                    };
+                }
+                "--watch" => {
+                    let value = args.next().ok_or(ArgsFail::MissingValue(arg.clone()))?;
+                    parsed.watch = Some(PathBuf::from(value));
+                }
+                "--cache" => {
+                    parsed
+                        .cache
+                        .get_or_insert_with(|| PathBuf::from(CACHE_FILE_NAME));
+                }
+                "--cache-location" => {
+                    let value = args.next().ok_or(ArgsFail::MissingValue(arg.clone()))?;
+                    parsed.cache = Some(PathBuf::from(value));
+                }
+                "--highlight-format" => {
+                    let value = args.next().ok_or(ArgsFail::MissingValue(arg.clone()))?;
+                    parsed.highlight_style = match value.as_str() {
+                        "ansi" => Style::Ansi,
+                        "html" => Style::Html,
+                        _ => return Err(ArgsFail::InvalidValue { flag: arg, value }),
+                    };
+                }
+                "--message-format" => {
+                    let value = args.next().ok_or(ArgsFail::MissingValue(arg.clone()))?;
+                    parsed.message_format = match value.as_str() {
+                        "human" => MessageFormat::Human,
+                        "json" => MessageFormat::Json,
+                        "sarif" => MessageFormat::Sarif,
+                        _ => return Err(ArgsFail::InvalidValue { flag: arg, value }),
+                    };
+                }
+                "--fail-on" => {
+                    let value = args.next().ok_or(ArgsFail::MissingValue(arg.clone()))?;
+                    let classes: Option<Vec<Failure>> = value
+                        .split(',')
+                        .filter(|class| *class != "none")
+                        .map(|class| match class {
+                            "reformat" => Some(Failure::Reformat),
+                            "parse" => Some(Failure::Parse),
+                            "io" => Some(Failure::Io),
+                            _ => None,
+                        })
+                        .collect();
+                    parsed.fail_on = classes.ok_or(ArgsFail::InvalidValue { flag: arg, value })?;
+                }
+                "--report" => {
+                    let value = args.next().ok_or(ArgsFail::MissingValue(arg.clone()))?;
+                    let format = match value.as_str() {
+                        "html" => ReportFormat::Html,
+                        _ => return Err(ArgsFail::InvalidValue { flag: arg, value }),
+                    };
+                    let path = args.next().ok_or(ArgsFail::MissingValue(arg.clone()))?;
+                    parsed.report = Some((format, PathBuf::from(path)));
+                }
+                "--color" => {
+                    let value = args.next().ok_or(ArgsFail::MissingValue(arg.clone()))?;
+                    parsed.color = match value.as_str() {
+                        "auto" => Color::Auto,
+                        "always" => Color::Always,
+                        "never" => Color::Never,
+                        _ => return Err(ArgsFail::InvalidValue { flag: arg, value }),
+                    };
+                }
+                "--stats" => {
+                    parsed.stats.get_or_insert(StatsFormat::Table);
+                }
+                "--stats-format" => {
+                    let value = args.next().ok_or(ArgsFail::MissingValue(arg.clone()))?;
+                    parsed.stats = Some(match value.as_str() {
+                        "table" => StatsFormat::Table,
+                        "json" => StatsFormat::Json,
+                        _ => return Err(ArgsFail::InvalidValue { flag: arg, value }),
+                    });
+                }
+                flag if flag.starts_with("--") => return Err(ArgsFail::UnknownFlag(arg)),
+                _ => parsed.paths.push(PathBuf::from(arg)),
+            }
+        }
+        if parsed.paths.is_empty()
+            && parsed.stdin_filepath.is_none()
+            && !parsed.staged
+            && parsed.hook.is_none()
+            && parsed.watch.is_none()
+        {
+            return Err(ArgsFail::NoPaths);
+        }
+        Ok(parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{
+        Args, ArgsFail, Color, Emit, Hook, MessageFormat, ReportFormat, Result, CACHE_FILE_NAME,
+    };
+
+    fn parse(args: &[&str]) -> Result<Args> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn stdin_filepath_stands_in_for_paths() {
+        let args = parse(&["--stdin-filepath", "lib/a.tcl"]).unwrap();
+        assert_eq!(args.stdin_filepath, Some(PathBuf::from("lib/a.tcl")));
+        assert!(args.paths.is_empty());
+        let args = parse(&["--stdin-filepath=b.tcl"]).unwrap();
+        assert_eq!(args.stdin_filepath, Some(PathBuf::from("b.tcl")));
+        assert!(matches!(
+            parse(&["--stdin-filepath"]),
+            Err(ArgsFail::MissingValue(_))
+        ));
+        assert!(matches!(parse(&[]), Err(ArgsFail::NoPaths)));
+    }
+
+    #[test]
+    fn check_and_message_format() {
+        let args = parse(&["--check", "--message-format", "json", "a.tcl"]).unwrap();
+        assert!(args.check);
+        assert_eq!(args.message_format, MessageFormat::Json);
+        assert_eq!(args.paths, [PathBuf::from("a.tcl")]);
+        assert!(matches!(
+            parse(&["--message-format=xml", "a.tcl"]),
+            Err(ArgsFail::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            parse(&["--chek", "a.tcl"]),
+            Err(ArgsFail::UnknownFlag(_))
+        ));
+    }
+
+    #[test]
+    fn staged_and_hook_need_no_paths() {
+        assert!(parse(&["--staged"]).unwrap().staged);
+        assert_eq!(
+            parse(&["--hook", "install"]).unwrap().hook,
+            Some(Hook::Install)
+        );
+        assert!(matches!(
+            parse(&["--hook", "remove"]),
+            Err(ArgsFail::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn cache_location_defaults() {
+        let args = parse(&["--cache", "a.tcl"]).unwrap();
+        assert_eq!(args.cache, Some(PathBuf::from(CACHE_FILE_NAME)));
+        let args = parse(&["--cache-location", "/tmp/c", "--cache", "a.tcl"]).unwrap();
+        assert_eq!(args.cache, Some(PathBuf::from("/tmp/c")));
+        assert_eq!(parse(&["a.tcl"]).unwrap().cache, None);
+    }
+
+    #[test]
+    fn color_choices() {
+        assert_eq!(parse(&["a.tcl"]).unwrap().color, Color::Auto);
+        assert_eq!(
+            parse(&["--color=never", "a.tcl"]).unwrap().color,
+            Color::Never
+        );
+        assert_eq!(
+            parse(&["--color", "always", "a.tcl"]).unwrap().color,
+            Color::Always
+        );
+        assert!(matches!(
+            parse(&["--color", "yes", "a.tcl"]),
+            Err(ArgsFail::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn emit_modes() {
+        assert!(matches!(
+            parse(&["--emit", "tmsh", "a.tcl"]).unwrap().emit,
+            Emit::Tmsh
+        ));
+        assert!(matches!(
+            parse(&["--emit=as3", "a.tcl"]).unwrap().emit,
+            Emit::As3
+        ));
+        assert!(matches!(
+            parse(&["--emit", "xml", "a.tcl"]),
+            Err(ArgsFail::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn list_different_implies_check() {
+        let args = parse(&["--list-different", "a.tcl"]).unwrap();
+        assert!(args.check && args.list_different && !args.diff);
+    }
+
+    #[test]
+    fn diff_flags_imply_check() {
+        let args = parse(&["--diff", "a.tcl"]).unwrap();
+        assert!(args.check && args.diff && !args.word_diff);
+        let args = parse(&["--word-diff", "a.tcl"]).unwrap();
+        assert!(args.check && args.diff && args.word_diff);
+    }
+
+    #[test]
+    fn fix_is_off_by_default() {
+        assert!(!parse(&["a.tcl"]).unwrap().fix);
+        let args = parse(&["--fix", "a.tcl"]).unwrap();
+        assert!(args.fix && !args.check);
+    }
+
+    #[test]
+    fn report_takes_a_format_and_a_path() {
+        let args = parse(&["--report", "html", "out.html", "a.tcl"]).unwrap();
+        assert_eq!(
+            args.report,
+            Some((ReportFormat::Html, PathBuf::from("out.html")))
+        );
+        assert_eq!(args.paths, [PathBuf::from("a.tcl")]);
+        assert!(matches!(
+            parse(&["--report", "pdf", "out.pdf", "a.tcl"]),
+            Err(ArgsFail::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            parse(&["--report", "html"]),
+            Err(ArgsFail::MissingValue(_))
+        ));
+    }
+}
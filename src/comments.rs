@@ -0,0 +1,132 @@
+// Comment attachment. Comments are trees of their own in a block, so code
+// that moves trees around groups each tree with the comments documenting it
+// first: the comment lines right above it, without an empty line between,
+// and the `;#` comment ending its line.
+use crate::ast::Ast;
+
+pub struct Attached<'src> {
+    pub leading: Vec<Ast<'src>>, // `Ast::Comment`s, in source order
+    pub node: Ast<'src>,
+    pub trailing: Option<Ast<'src>>, // an `Ast::TrailingComment`
+}
+
+impl<'src> Attached<'src> {
+    fn new(leading: Vec<Ast<'src>>, node: Ast<'src>) -> Self {
+        Attached {
+            leading,
+            node,
+            trailing: None,
+        }
+    }
+
+    // a comment that documents nothing below it is a node of its own
+    pub fn is_comment(&self) -> bool {
+        matches!(self.node, Ast::Comment(_))
+    }
+
+    pub fn has_comments(&self) -> bool {
+        !self.leading.is_empty() || self.trailing.is_some()
+    }
+
+    pub fn into_trees(self) -> impl Iterator<Item = Ast<'src>> {
+        self.leading
+            .into_iter()
+            .chain(std::iter::once(self.node))
+            .chain(self.trailing)
+    }
+}
+
+pub fn attach(trees: Vec<Ast>) -> Vec<Attached> {
+    let mut groups: Vec<Attached> = Vec::new();
+    let mut leading = Vec::new();
+    for tree in trees {
+        match tree {
+            Ast::Comment(_) => leading.push(tree),
+            Ast::TrailingComment(_) if leading.is_empty() => match groups.last_mut() {
+                Some(group) if group.trailing.is_none() && !group.is_comment() => {
+                    group.trailing = Some(tree)
+                }
+                _ => groups.push(Attached::new(Vec::new(), tree)),
+            },
+            Ast::EmptyLine | Ast::TrailingComment(_) => {
+                groups.extend(leading.drain(..).map(|c| Attached::new(Vec::new(), c)));
+                groups.push(Attached::new(Vec::new(), tree));
+            }
+            tree => groups.push(Attached::new(std::mem::take(&mut leading), tree)),
+        }
+    }
+    groups.extend(leading.into_iter().map(|c| Attached::new(Vec::new(), c)));
+    groups
+}
+
+// The number of comment lines at the start of `trees` that document the
+// tree after them
+pub fn leading_comments(trees: &[Ast]) -> usize {
+    let comments = trees
+        .iter()
+        .take_while(|tree| matches!(tree, Ast::Comment(_)))
+        .count();
+    match trees.get(comments) {
+        Some(Ast::EmptyLine | Ast::TrailingComment(_)) | None => 0,
+        Some(_) => comments,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{attach, leading_comments, Attached};
+    use crate::{ast::Ast, lexer::Lexer, parser::Parser};
+
+    fn trees(source: &str) -> Vec<Ast<'_>> {
+        let (tokens, spans) = Lexer::new().lex_with_spans(source.as_bytes()).unwrap();
+        match Parser::new(source.as_bytes(), &tokens, &spans).parse() {
+            Ok(Ast::Block(trees)) => trees,
+            _ => panic!("expected a block"),
+        }
+    }
+
+    // (leading comments, node kind, trailing comment) of each group
+    fn shape(groups: &[Attached]) -> Vec<(usize, &'static str, bool)> {
+        groups
+            .iter()
+            .map(|group| {
+                let kind = match group.node {
+                    Ast::Comment(_) => "comment",
+                    Ast::EmptyLine => "empty",
+                    Ast::Statement(_) => "statement",
+                    _ => "other",
+                };
+                (group.leading.len(), kind, group.trailing.is_some())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn attaches_comments_to_the_tree_below() {
+        let source =
+            "# a\n# a2\nset a 1 ;# a3\n# loose\n\nset b 1\nproc p {} {\n}\n# trailing the file\n";
+        let groups = attach(trees(source));
+        assert_eq!(
+            shape(&groups),
+            [
+                (2, "statement", true),
+                (0, "comment", false),
+                (0, "empty", false),
+                (0, "statement", false),
+                (0, "other", false),
+                (0, "comment", false),
+            ]
+        );
+        assert!(groups[0].has_comments() && !groups[3].has_comments());
+        let count: usize = groups.into_iter().map(|g| g.into_trees().count()).sum();
+        assert_eq!(count, trees(source).len());
+    }
+
+    #[test]
+    fn counts_leading_comments() {
+        assert_eq!(leading_comments(&trees("# a\n# b\nset a 1\n")), 2);
+        assert_eq!(leading_comments(&trees("# a\n\nset a 1\n")), 0);
+        assert_eq!(leading_comments(&trees("# a\n")), 0);
+        assert_eq!(leading_comments(&trees("set a 1\n")), 0);
+    }
+}
@@ -0,0 +1,126 @@
+// Cyclomatic complexity of each proc and `when` block: one path through the
+// body, plus one per `if`/`elseif` condition and per non-default switch arm.
+// Loops and `catch` are not modelled by the parser yet, so they don't count.
+use crate::{
+    ast::{Ast, Condition, Text},
+    visit::{walk_if, walk_switch, Visit},
+};
+
+// ("proc name" | "when EVENT", complexity) in source order
+pub fn complexity(ast: &Ast) -> Vec<(String, usize)> {
+    let mut counter = Counter {
+        results: Vec::new(),
+        branches: 0,
+    };
+    counter.visit_ast(ast);
+    counter.results
+}
+
+struct Counter {
+    results: Vec<(String, usize)>,
+    branches: usize,
+}
+
+impl Counter {
+    fn measure(&mut self, label: String, body: &Ast) {
+        let outer = std::mem::replace(&mut self.branches, 0);
+        self.visit_ast(body);
+        self.results.push((label, self.branches + 1));
+        self.branches = outer;
+    }
+}
+
+impl Visit for Counter {
+    fn visit_proc(&mut self, name: &[u8], _parameters: &[Text], body: &Ast) {
+        self.measure(format!("proc {}", String::from_utf8_lossy(name)), body);
+    }
+
+    fn visit_when(&mut self, event_name: &[u8], body: &Ast) {
+        self.measure(
+            format!("when {}", String::from_utf8_lossy(event_name)),
+            body,
+        );
+    }
+
+    fn visit_if(
+        &mut self,
+        condition_body_clauses: &[(Condition, Ast)],
+        block_if_false: Option<&Ast>,
+    ) {
+        self.branches += condition_body_clauses.len();
+        walk_if(self, condition_body_clauses, block_if_false);
+    }
+
+    fn visit_switch(&mut self, condition: &[u8], arms: &[(Text, Option<Ast>)]) {
+        self.branches += arms
+            .iter()
+            .filter(|(value, body)| body.is_some() && **value != *b"default")
+            .count();
+        walk_switch(self, condition, arms);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::complexity;
+    use crate::{ast::Ast, lexer::Lexer, parser::Parser};
+
+    fn parse(source: &[u8]) -> Ast<'_> {
+        let (tokens, spans) = Lexer::new().lex_with_spans(source).unwrap();
+        Parser::new(source, &tokens, &spans).parse().unwrap()
+    }
+
+    #[test]
+    fn counts_branches_per_definition() {
+        let source = b"proc straight {} {
+    set a 1
+}
+proc branchy {a} {
+    if {$a == 1} {
+        set b 1
+    } elseif {$a == 2} {
+        set b 2
+    } else {
+        switch $a {
+            x -
+            y {
+                set b 3
+            }
+            z {
+                set b 4
+            }
+            default {
+                set b 5
+            }
+        }
+    }
+}
+when HTTP_REQUEST {
+    if {$a} {
+        set b 1
+    }
+}
+";
+        let expected = [
+            ("proc straight", 1),
+            ("proc branchy", 5),
+            ("when HTTP_REQUEST", 2),
+        ];
+        let expected: Vec<(String, usize)> =
+            expected.iter().map(|&(n, c)| (n.to_string(), c)).collect();
+        assert_eq!(complexity(&parse(source)), expected);
+    }
+
+    #[test]
+    fn measures_nested_definitions_on_their_own() {
+        let source = b"when HTTP_REQUEST {\n    if {$a} {\n        proc inner {} {\n            if {$b} {\n                set c 1\n            }\n        }\n    }\n}\n";
+        let results = complexity(&parse(source));
+        assert_eq!(
+            results,
+            [
+                ("proc inner".to_string(), 2),
+                ("when HTTP_REQUEST".to_string(), 2)
+            ]
+        );
+    }
+}
@@ -0,0 +1,822 @@
+// `.tclfmt.toml` loading. Only the subset of TOML the formatter needs is
+// understood: `key = value` pairs with string, integer, boolean and array
+// values, plus `[section]` headers. Unknown keys and invalid values are
+// errors naming the line, with the closest known key for typos.
+use std::path::{Path, PathBuf};
+
+use crate::{
+    editorconfig::EditorConfig,
+    encoding::Encoding,
+    formatter::{FormatterOptions, LineEnding},
+    glob,
+    lint::{Level, LintOptions},
+    parser::DEFAULT_MAX_DEPTH,
+    passes,
+};
+
+pub const CONFIG_FILE_NAME: &str = ".tclfmt.toml";
+
+#[derive(Debug)]
+pub enum ConfigFail {
+    Io(std::io::Error),
+    Syntax {
+        line: usize,
+    },
+    InvalidValue {
+        line: usize,
+        key: String,            // `section.key` for keys in a section
+        expected: &'static str, // what the value should be, for the message
+    },
+    UnknownKey {
+        line: usize,
+        key: String,
+        suggestion: Option<&'static str>, // the closest known key, if any is close
+    },
+    UnknownStyle(String), // no preset of this name
+    UnknownPass {
+        line: usize,
+        name: String,
+        suggestion: Option<&'static str>, // the closest registered pass, if any is close
+    },
+    // a known key a `# tclfmt:` directive can't set where it is
+    Misplaced {
+        line: usize,
+        key: String,
+        reason: &'static str,
+    },
+}
+type Result<T> = std::result::Result<T, ConfigFail>;
+
+impl ConfigFail {
+    // the line of the config file the error is on
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            ConfigFail::Syntax { line }
+            | ConfigFail::InvalidValue { line, .. }
+            | ConfigFail::UnknownKey { line, .. }
+            | ConfigFail::UnknownPass { line, .. }
+            | ConfigFail::Misplaced { line, .. } => Some(*line),
+            ConfigFail::Io(_) | ConfigFail::UnknownStyle(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ConfigFail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Syntax { .. } => write!(f, "expected `key = value` or `[section]`"),
+            Self::InvalidValue { key, expected, .. } => write!(f, "`{key}` expects {expected}"),
+            Self::UnknownKey {
+                key,
+                suggestion: Some(suggestion),
+                ..
+            } => write!(f, "unknown key `{key}`, did you mean `{suggestion}`?"),
+            Self::UnknownKey { key, .. } => write!(f, "unknown key `{key}`"),
+            Self::UnknownStyle(name) => write!(f, "unknown style `{name}`"),
+            Self::UnknownPass {
+                name,
+                suggestion: Some(suggestion),
+                ..
+            } => write!(f, "unknown pass `{name}`, did you mean `{suggestion}`?"),
+            Self::UnknownPass { name, .. } => write!(f, "unknown pass `{name}`"),
+            Self::Misplaced { key, reason, .. } => write!(f, "`{key}` {reason}"),
+        }
+    }
+}
+
+// every key the config understands, as `section.key` for those in a section
+pub const KEYS: &[&str] = &[
+    "passes",
+    "style",
+    "indent_width",
+    "use_tabs",
+    "cuddle_else",
+    "pad_braces",
+    "max_empty_lines",
+    "line_ending",
+    "final_newline",
+    "align_trailing_comments",
+    "align_switch_arms",
+    "blank_line_between_definitions",
+    "compact_empty_blocks",
+    "max_width",
+    "one_line_bodies",
+    "keep_semicolons",
+    "editorconfig",
+    "modelines",
+    "max_depth",
+    "encoding",
+    "keep_bom",
+    "lint.max_complexity",
+    "lint.unbraced_expr",
+    "embed.paths",
+];
+
+impl From<std::io::Error> for ConfigFail {
+    fn from(e: std::io::Error) -> Self {
+        ConfigFail::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub passes: Vec<String>, // transformation passes, run in this order
+    pub lint: LintOptions,
+    pub style: Option<String>, // named preset the options below are applied on top of
+    pub indent_width: Option<usize>,
+    pub use_tabs: Option<bool>,
+    pub cuddle_else: Option<bool>,
+    pub pad_braces: Option<bool>,
+    pub max_empty_lines: Option<usize>,
+    pub line_ending: Option<LineEnding>,
+    pub final_newline: Option<bool>,
+    pub align_trailing_comments: Option<usize>,
+    pub align_switch_arms: Option<bool>,
+    pub blank_line_between_definitions: Option<bool>,
+    pub compact_empty_blocks: Option<bool>,
+    pub max_width: Option<usize>,
+    pub one_line_bodies: Option<bool>,
+    pub keep_semicolons: Option<bool>,
+    pub editorconfig: bool, // read .editorconfig for options not set here
+    pub modelines: bool,    // take indent options from vim modelines over .editorconfig
+    pub max_depth: usize,   // deepest nesting of blocks parsed
+    pub encoding: Encoding,
+    pub keep_bom: bool, // write back a UTF-8 byte order mark the input started with
+    pub embed_paths: Vec<String>, // selectors of Tcl fields in JSON and YAML files
+    pub exclude: bool,  // set by an override to leave its files alone
+    pub overrides: Vec<Override>, // applied by `for_path`, in file order
+}
+
+// `[override."legacy/**"]`: top-level options, or `exclude = true`, for the
+// files matching a glob relative to the config file's directory
+#[derive(Debug, Clone)]
+pub struct Override {
+    pub glob: String,
+    pub entries: Vec<Entry>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            passes: Vec::new(),
+            lint: LintOptions::default(),
+            style: None,
+            indent_width: None,
+            use_tabs: None,
+            cuddle_else: None,
+            pad_braces: None,
+            max_empty_lines: None,
+            line_ending: None,
+            final_newline: None,
+            align_trailing_comments: None,
+            align_switch_arms: None,
+            blank_line_between_definitions: None,
+            compact_empty_blocks: None,
+            max_width: None,
+            one_line_bodies: None,
+            keep_semicolons: None,
+            editorconfig: true,
+            modelines: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            encoding: Encoding::Utf8,
+            keep_bom: false,
+            embed_paths: Vec::new(),
+            exclude: false,
+            overrides: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Config::parse(&text)
+    }
+
+    // walks up from `dir` and loads the first config file found
+    pub fn discover(dir: &Path) -> Result<Option<(PathBuf, Self)>> {
+        match Config::find(dir) {
+            Some(path) => {
+                let config = Config::load(&path)?;
+                Ok(Some((path, config)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn find(dir: &Path) -> Option<PathBuf> {
+        dir.ancestors()
+            .map(|d| d.join(CONFIG_FILE_NAME))
+            .find(|p| p.is_file())
+    }
+
+    // the preset (`style`, or the config's own), then .editorconfig settings,
+    // then options set individually in the config file
+    pub fn formatter_options(
+        &self,
+        style: Option<&str>,
+        editorconfig: &EditorConfig,
+    ) -> Result<FormatterOptions> {
+        let mut options = match style.or(self.style.as_deref()) {
+            Some(name) => FormatterOptions::preset(name)
+                .ok_or_else(|| ConfigFail::UnknownStyle(name.to_string()))?,
+            None => FormatterOptions::default(),
+        };
+        if let Some(use_tabs) = editorconfig.use_tabs {
+            options.use_tabs = use_tabs;
+        }
+        if let Some(indent_width) = editorconfig.indent_width {
+            options.indent_width = indent_width;
+        }
+        if let Some(line_ending) = editorconfig.line_ending {
+            options.line_ending = line_ending;
+        }
+        if let Some(final_newline) = editorconfig.final_newline {
+            options.final_newline = final_newline;
+        }
+        self.apply_options(&mut options);
+        Ok(options)
+    }
+
+    // the options set individually, over `options`
+    pub(crate) fn apply_options(&self, options: &mut FormatterOptions) {
+        if let Some(indent_width) = self.indent_width {
+            options.indent_width = indent_width;
+        }
+        if let Some(use_tabs) = self.use_tabs {
+            options.use_tabs = use_tabs;
+        }
+        if let Some(cuddle_else) = self.cuddle_else {
+            options.cuddle_else = cuddle_else;
+        }
+        if let Some(pad_braces) = self.pad_braces {
+            options.pad_braces = pad_braces;
+        }
+        if let Some(max_empty_lines) = self.max_empty_lines {
+            options.max_empty_lines = max_empty_lines;
+        }
+        if let Some(line_ending) = self.line_ending {
+            options.line_ending = line_ending;
+        }
+        if let Some(final_newline) = self.final_newline {
+            options.final_newline = final_newline;
+        }
+        if let Some(column) = self.align_trailing_comments {
+            options.align_trailing_comments = Some(column);
+        }
+        if let Some(align_switch_arms) = self.align_switch_arms {
+            options.align_switch_arms = align_switch_arms;
+        }
+        if let Some(blank_line) = self.blank_line_between_definitions {
+            options.blank_line_between_definitions = blank_line;
+        }
+        if let Some(compact_empty_blocks) = self.compact_empty_blocks {
+            options.compact_empty_blocks = compact_empty_blocks;
+        }
+        if self.max_width.is_some() {
+            options.max_width = self.max_width;
+        }
+        if let Some(one_line_bodies) = self.one_line_bodies {
+            options.one_line_bodies = one_line_bodies;
+        }
+        if let Some(keep_semicolons) = self.keep_semicolons {
+            options.keep_semicolons = keep_semicolons;
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut config = Config::default();
+        for entry in parse_toml(text)? {
+            let Some(glob) = entry.section.as_deref().and_then(override_glob) else {
+                config.set(entry.section.as_deref(), &entry)?;
+                continue;
+            };
+            // checked now, applied by `for_path`
+            Config::default().set_override(&entry)?;
+            match config.overrides.last_mut() {
+                Some(last) if last.glob == glob => last.entries.push(entry),
+                _ => config.overrides.push(Override {
+                    glob: glob.to_string(),
+                    entries: vec![entry],
+                }),
+            }
+        }
+        Ok(config)
+    }
+
+    // The config for the file at `relative`, a `/`-separated path from the
+    // config file's directory, with the overrides matching it applied
+    pub fn for_path(&self, relative: &str) -> Config {
+        let mut config = self.clone();
+        for o in &self.overrides {
+            if glob::matches_relative(&o.glob, relative) {
+                for entry in &o.entries {
+                    config
+                        .set_override(entry)
+                        .expect("override entries are checked when parsed");
+                }
+            }
+        }
+        config
+    }
+
+    fn set_override(&mut self, entry: &Entry) -> Result<()> {
+        match entry.key.as_str() {
+            "exclude" => self.exclude = entry.boolean()?,
+            _ => self.set(None, entry)?,
+        }
+        Ok(())
+    }
+
+    // applies one entry, in `section` or at the top level
+    pub(crate) fn set(&mut self, section: Option<&str>, entry: &Entry) -> Result<()> {
+        match (section, entry.key.as_str()) {
+            (None, "passes") => {
+                let names = entry.string_array()?;
+                if let Some(name) = names.iter().find(|name| passes::lookup(name).is_none()) {
+                    return Err(entry.unknown_pass(name));
+                }
+                self.passes = names;
+            }
+            (None, "style") => {
+                let style = entry.string()?;
+                if !FormatterOptions::PRESETS.contains(&style.as_str()) {
+                    return Err(entry.invalid("`f5-irule` or `tcl-std`"));
+                }
+                self.style = Some(style);
+            }
+            (None, "indent_width") => self.indent_width = Some(entry.positive_integer()?),
+            (None, "use_tabs") => self.use_tabs = Some(entry.boolean()?),
+            (None, "cuddle_else") => self.cuddle_else = Some(entry.boolean()?),
+            (None, "pad_braces") => self.pad_braces = Some(entry.boolean()?),
+            (None, "max_empty_lines") => self.max_empty_lines = Some(entry.integer()?),
+            (None, "line_ending") => {
+                self.line_ending = Some(
+                    LineEnding::from_name(&entry.string()?)
+                        .ok_or_else(|| entry.invalid("`lf`, `crlf` or `cr`"))?,
+                )
+            }
+            (None, "final_newline") => self.final_newline = Some(entry.boolean()?),
+            (None, "align_trailing_comments") => {
+                self.align_trailing_comments = Some(entry.positive_integer()?)
+            }
+            (None, "align_switch_arms") => self.align_switch_arms = Some(entry.boolean()?),
+            (None, "blank_line_between_definitions") => {
+                self.blank_line_between_definitions = Some(entry.boolean()?)
+            }
+            (None, "compact_empty_blocks") => self.compact_empty_blocks = Some(entry.boolean()?),
+            (None, "max_width") => self.max_width = Some(entry.positive_integer()?),
+            (None, "one_line_bodies") => self.one_line_bodies = Some(entry.boolean()?),
+            (None, "keep_semicolons") => self.keep_semicolons = Some(entry.boolean()?),
+            (None, "editorconfig") => self.editorconfig = entry.boolean()?,
+            (None, "modelines") => self.modelines = entry.boolean()?,
+            (None, "max_depth") => self.max_depth = entry.positive_integer()?,
+            (None, "encoding") => {
+                self.encoding = Encoding::from_name(&entry.string()?)
+                    .ok_or_else(|| entry.invalid("`utf-8`, `latin-1` or `auto`"))?
+            }
+            (None, "keep_bom") => self.keep_bom = entry.boolean()?,
+            (Some("lint"), "max_complexity") => {
+                self.lint.max_complexity = Some(entry.positive_integer()?)
+            }
+            (Some("lint"), "unbraced_expr") => {
+                self.lint.unbraced_expr = match &entry.value {
+                    Value::Boolean(false) => Level::Off,
+                    Value::Boolean(true) => Level::Warn,
+                    Value::String(s) if s == "fix" => Level::Fix,
+                    _ => return Err(entry.invalid("`true`, `false` or `\"fix\"`")),
+                }
+            }
+            (Some("embed"), "paths") => self.embed_paths = entry.string_array()?,
+            _ => return Err(entry.unknown()),
+        }
+        Ok(())
+    }
+}
+
+// `override."legacy/**"` => `legacy/**`
+fn override_glob(section: &str) -> Option<&str> {
+    section.strip_prefix("override.").map(unquote)
+}
+
+// A `key = value` line of the config file
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub line: usize,
+    pub section: Option<String>,
+    pub key: String,
+    pub value: Value,
+}
+
+impl Entry {
+    // `section.key`, as in `KEYS`
+    fn qualified_key(&self) -> String {
+        match &self.section {
+            Some(section) => format!("{section}.{}", self.key),
+            None => self.key.clone(),
+        }
+    }
+
+    fn invalid(&self, expected: &'static str) -> ConfigFail {
+        ConfigFail::InvalidValue {
+            line: self.line,
+            key: self.qualified_key(),
+            expected,
+        }
+    }
+
+    fn unknown(&self) -> ConfigFail {
+        let key = self.qualified_key();
+        // a key in the wrong section is a typo of its name alone
+        let suggestion = KEYS
+            .iter()
+            .map(|&known| {
+                let name = known.rsplit('.').next().unwrap_or(known);
+                (distance(&key, known).min(distance(&self.key, name)), known)
+            })
+            .min()
+            .filter(|&(distance, _)| distance <= (self.key.len() / 3).max(2))
+            .map(|(_, known)| known);
+        ConfigFail::UnknownKey {
+            line: self.line,
+            key,
+            suggestion,
+        }
+    }
+
+    fn unknown_pass(&self, name: &str) -> ConfigFail {
+        let suggestion = passes::PASSES
+            .iter()
+            .map(|&(known, _)| (distance(name, known), known))
+            .min()
+            .filter(|&(distance, _)| distance <= (name.len() / 3).max(2))
+            .map(|(_, known)| known);
+        ConfigFail::UnknownPass {
+            line: self.line,
+            name: name.to_string(),
+            suggestion,
+        }
+    }
+
+    fn positive_integer(&self) -> Result<usize> {
+        match self.value {
+            Value::Integer(n) if n > 0 => Ok(n as usize),
+            _ => Err(self.invalid("a positive integer")),
+        }
+    }
+
+    fn integer(&self) -> Result<usize> {
+        match self.value {
+            Value::Integer(n) if n >= 0 => Ok(n as usize),
+            _ => Err(self.invalid("a non-negative integer")),
+        }
+    }
+
+    fn boolean(&self) -> Result<bool> {
+        match self.value {
+            Value::Boolean(b) => Ok(b),
+            _ => Err(self.invalid("`true` or `false`")),
+        }
+    }
+
+    fn string(&self) -> Result<String> {
+        match &self.value {
+            Value::String(s) => Ok(s.clone()),
+            _ => Err(self.invalid("a string")),
+        }
+    }
+
+    fn string_array(&self) -> Result<Vec<String>> {
+        let invalid = || self.invalid("an array of strings");
+        let Value::Array(items) = &self.value else {
+            return Err(invalid());
+        };
+        items
+            .iter()
+            .map(|item| match item {
+                Value::String(s) => Ok(s.clone()),
+                _ => Err(invalid()),
+            })
+            .collect()
+    }
+}
+
+// Levenshtein distance between `a` and `b`
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substituted = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+// returns the entries in file order
+pub fn parse_toml(text: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut section = None;
+    for (idx, line) in text.lines().enumerate() {
+        let line_no = idx + 1;
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            let header = header
+                .strip_suffix(']')
+                .ok_or(ConfigFail::Syntax { line: line_no })?;
+            section = Some(unquote(header.trim()).to_string());
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or(ConfigFail::Syntax { line: line_no })?;
+        let value = parse_value(value.trim()).ok_or(ConfigFail::Syntax { line: line_no })?;
+        entries.push(Entry {
+            line: line_no,
+            section: section.clone(),
+            key: unquote(key.trim()).to_string(),
+            value,
+        });
+    }
+    Ok(entries)
+}
+
+pub(crate) fn parse_value(text: &str) -> Option<Value> {
+    match text {
+        "true" => return Some(Value::Boolean(true)),
+        "false" => return Some(Value::Boolean(false)),
+        _ => {}
+    }
+    if let Some(inner) = text.strip_prefix('[') {
+        let inner = inner.strip_suffix(']')?;
+        return split_array(inner)
+            .into_iter()
+            .map(parse_value)
+            .collect::<Option<Vec<_>>>()
+            .map(Value::Array);
+    }
+    if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') {
+        return Some(Value::String(text[1..text.len() - 1].to_string()));
+    }
+    text.parse().ok().map(Value::Integer)
+}
+
+fn split_array(inner: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut in_string = false;
+    let mut start = 0;
+    for (idx, c) in inner.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                items.push(inner[start..idx].trim());
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(inner[start..].trim());
+    items.retain(|item| !item.is_empty()); // trailing comma
+    items
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (idx, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..idx],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn unquote(text: &str) -> &str {
+    text.strip_prefix('"')
+        .and_then(|t| t.strip_suffix('"'))
+        .unwrap_or(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, ConfigFail};
+    use crate::{
+        editorconfig::EditorConfig, encoding::Encoding, formatter::LineEnding, lint::Level,
+    };
+
+    #[test]
+    fn accepts_registered_passes() {
+        let config =
+            Config::parse("passes = [\"collapse-elseif\", \"sort-switch-arms\"]\n").unwrap();
+        assert_eq!(config.passes, ["collapse-elseif", "sort-switch-arms"]);
+    }
+
+    #[test]
+    fn rejects_unknown_passes() {
+        let e = Config::parse(
+            "style = \"f5-irule\"\npasses = [\"collapse-elseif\", \"colapse-elsif\"]\n",
+        )
+        .unwrap_err();
+        assert!(matches!(
+            &e,
+            ConfigFail::UnknownPass { line: 2, name, suggestion: Some("collapse-elseif") }
+                if name == "colapse-elsif"
+        ));
+        assert_eq!(
+            e.to_string(),
+            "unknown pass `colapse-elsif`, did you mean `collapse-elseif`?"
+        );
+        let e = Config::parse("passes = [\"minify\"]\n").unwrap_err();
+        assert_eq!(e.to_string(), "unknown pass `minify`");
+    }
+
+    #[test]
+    fn applies_matching_overrides_in_file_order() {
+        let config = Config::parse(
+            "indent_width = 4\n\
+             [override.\"legacy/**\"]\n\
+             indent_width = 2\n\
+             keep_semicolons = true\n\
+             [override.\"legacy/vendor/**\"]\n\
+             exclude = true\n\
+             [override.\"*.tcl\"]\n\
+             indent_width = 8\n",
+        )
+        .unwrap();
+        assert_eq!(config.overrides.len(), 3);
+        let other = config.for_path("src/a.irul");
+        assert_eq!(other.indent_width, Some(4));
+        assert!(!other.exclude && other.keep_semicolons.is_none());
+        let legacy = config.for_path("legacy/a.irul");
+        assert_eq!(legacy.indent_width, Some(2));
+        assert_eq!(legacy.keep_semicolons, Some(true));
+        assert!(!legacy.exclude);
+        assert!(config.for_path("legacy/vendor/a.irul").exclude);
+        // the later section wins
+        assert_eq!(config.for_path("legacy/a.tcl").indent_width, Some(8));
+    }
+
+    #[test]
+    fn checks_override_entries_when_parsed() {
+        let e = Config::parse("[override.\"*.tcl\"]\nindent_width = \"two\"\n").unwrap_err();
+        assert!(matches!(e, ConfigFail::InvalidValue { line: 2, .. }));
+        let e = Config::parse("[override.\"*.tcl\"]\nexclude = 1\n").unwrap_err();
+        assert!(matches!(e, ConfigFail::InvalidValue { line: 2, .. }));
+    }
+
+    #[test]
+    fn rejects_unknown_passes_in_overrides() {
+        let e = Config::parse("[override.\"*.tcl\"]\npasses = [\"minify\"]\n").unwrap_err();
+        assert!(matches!(
+            e,
+            ConfigFail::UnknownPass {
+                line: 2,
+                suggestion: None,
+                ..
+            }
+        ));
+        assert_eq!(e.to_string(), "unknown pass `minify`");
+    }
+
+    #[test]
+    fn suggests_known_keys() {
+        let e = Config::parse("indent_widht = 4\n").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "unknown key `indent_widht`, did you mean `indent_width`?"
+        );
+    }
+
+    #[test]
+    fn applies_single_options_over_the_style() {
+        let config = Config::parse("style = \"tcl-std\"\nindent_width = 2\n").unwrap();
+        let options = config
+            .formatter_options(None, &EditorConfig::default())
+            .unwrap();
+        assert!(!options.pad_braces && options.cuddle_else);
+        assert_eq!(options.indent_width, 2);
+        // `--style` replaces the configured preset
+        let options = config
+            .formatter_options(Some("f5-irule"), &EditorConfig::default())
+            .unwrap();
+        assert!(options.pad_braces);
+        assert_eq!(options.indent_width, 2);
+    }
+
+    #[test]
+    fn rejects_unknown_styles() {
+        let e = Config::parse("style = \"gnu\"\n").unwrap_err();
+        assert_eq!(e.to_string(), "`style` expects `f5-irule` or `tcl-std`");
+        let e = Config::default()
+            .formatter_options(Some("gnu"), &EditorConfig::default())
+            .unwrap_err();
+        assert!(matches!(e, ConfigFail::UnknownStyle(name) if name == "gnu"));
+    }
+
+    #[test]
+    fn config_options_override_editorconfig() {
+        let config = Config::parse("line_ending = \"crlf\"\nindent_width = 8\n").unwrap();
+        let editorconfig = EditorConfig {
+            use_tabs: Some(true),
+            indent_width: Some(2),
+            line_ending: Some(LineEnding::Cr),
+            final_newline: Some(false),
+        };
+        let options = config.formatter_options(None, &editorconfig).unwrap();
+        assert!(options.use_tabs && !options.final_newline);
+        assert_eq!(options.indent_width, 8);
+        assert_eq!(options.line_ending, LineEnding::CrLf);
+        assert!(Config::parse("editorconfig = false\n")
+            .map(|c| !c.editorconfig)
+            .unwrap());
+        let e = Config::parse("line_ending = \"nel\"\n").unwrap_err();
+        assert!(matches!(e, ConfigFail::InvalidValue { line: 1, .. }));
+    }
+
+    #[test]
+    fn reads_the_unbraced_expr_level() {
+        let level = |value: &str| {
+            Config::parse(&format!("[lint]\nunbraced_expr = {value}\n"))
+                .map(|config| config.lint.unbraced_expr)
+        };
+        assert_eq!(Config::parse("").unwrap().lint.unbraced_expr, Level::Off);
+        assert_eq!(level("true").unwrap(), Level::Warn);
+        assert_eq!(level("false").unwrap(), Level::Off);
+        assert_eq!(level("\"fix\"").unwrap(), Level::Fix);
+        let e = level("\"warn\"").unwrap_err();
+        assert!(matches!(e, ConfigFail::InvalidValue { line: 2, .. }));
+    }
+
+    #[test]
+    fn reads_the_encoding() {
+        let config = Config::parse("encoding = \"auto\"\nkeep_bom = true\n").unwrap();
+        assert_eq!(config.encoding, Encoding::Auto);
+        assert!(config.keep_bom);
+        assert!(Config::parse("encoding = \"utf-16\"\n").is_err());
+    }
+
+    #[test]
+    fn reads_alignment_options() {
+        let config =
+            Config::parse("align_trailing_comments = 41\nalign_switch_arms = true\n").unwrap();
+        let options = config
+            .formatter_options(None, &EditorConfig::default())
+            .unwrap();
+        assert_eq!(options.align_trailing_comments, Some(41));
+        assert!(options.align_switch_arms);
+        assert!(Config::parse("align_trailing_comments = 0\n").is_err());
+    }
+
+    #[test]
+    fn reads_compact_empty_blocks() {
+        let config = Config::parse("compact_empty_blocks = true\n").unwrap();
+        let options = config
+            .formatter_options(None, &EditorConfig::default())
+            .unwrap();
+        assert!(options.compact_empty_blocks);
+        assert!(Config::parse("compact_empty_blocks = 1\n").is_err());
+    }
+
+    #[test]
+    fn reads_embed_paths() {
+        let config =
+            Config::parse("[embed]\npaths = [\"declaration.**.iRule\", \"rules.*.script\"]\n")
+                .unwrap();
+        assert_eq!(
+            config.embed_paths,
+            ["declaration.**.iRule", "rules.*.script"]
+        );
+    }
+
+    #[test]
+    fn reads_one_line_bodies() {
+        let config = Config::parse("one_line_bodies = true\n").unwrap();
+        let options = config
+            .formatter_options(None, &EditorConfig::default())
+            .unwrap();
+        assert!(options.one_line_bodies);
+    }
+
+    #[test]
+    fn modelines_are_opt_in() {
+        assert!(!Config::parse("").unwrap().modelines);
+        assert!(Config::parse("modelines = true\n").unwrap().modelines);
+    }
+}
@@ -0,0 +1,250 @@
+// Findings reported to the user: parse errors, lint warnings and formatting
+// notices, rendered for humans or as newline-delimited JSON for tooling.
+use crate::{fix::Fix, json, width};
+
+// every rule id a diagnostic can carry, with a one-line description
+pub const RULES: &[(&str, &str)] = &[
+    ("lex-error", "The input could not be tokenized"),
+    ("parse-error", "The input could not be parsed"),
+    (
+        "invalid-config",
+        "The .tclfmt.toml file could not be loaded",
+    ),
+    (
+        "invalid-directive",
+        "A `# tclfmt:` comment sets an unknown or invalid option, or one it can't set there",
+    ),
+    ("would-reformat", "The file is not formatted"),
+    (
+        "verify-failed",
+        "Formatting would change the tokens of the file, so it was not written",
+    ),
+    (
+        "max-complexity",
+        "A proc or when block exceeds the configured cyclomatic complexity",
+    ),
+    (
+        "unbraced-expr",
+        "An if condition or expr argument is not braced, so it is substituted twice",
+    ),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+
+    fn color(self) -> &'static str {
+        match self {
+            Severity::Warning => "\x1b[1;33m",
+            Severity::Error => "\x1b[1;31m",
+        }
+    }
+}
+
+const GUTTER: &str = "\x1b[1;34m";
+const RESET: &str = "\x1b[0m";
+
+// 1-based, columns in display width (see `width::column`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    pub fn line(line: usize) -> Self {
+        Self {
+            start: Position { line, column: 1 },
+            end: Position { line, column: 1 },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>,      // None when the location is unknown
+    pub snippet: Option<String>, // the source line the span starts on, shown under the message
+    pub fix: Option<Fix>,        // applied with `--fix`
+}
+
+impl Diagnostic {
+    // severity[rule]: file:line:column: message, followed by the snippet with
+    // the span underlined if there is one
+    pub fn render_human(&self, file: &str, color: bool) -> String {
+        let location = match self.span {
+            Some(span) => format!("{file}:{}:{}", span.start.line, span.start.column),
+            None => file.to_string(),
+        };
+        let (severity, gutter, reset) = match color {
+            true => (self.severity.color(), GUTTER, RESET),
+            false => ("", "", ""),
+        };
+        let mut out = format!(
+            "{severity}{}[{}]{reset}: {location}: {}",
+            self.severity.as_str(),
+            self.rule,
+            self.message
+        );
+        if let (Some(span), Some(snippet)) = (self.span, &self.snippet) {
+            let number = span.start.line.to_string();
+            let blank = " ".repeat(number.len());
+            let carets = match span.end.line == span.start.line {
+                true => span.end.column.saturating_sub(span.start.column).max(1),
+                false => 1,
+            };
+            out.push_str(&format!(
+                "\n{gutter}{blank} |{reset}\n{gutter}{number} |{reset} {snippet}\n{gutter}{blank} |{reset} {}{severity}{}{reset}",
+                underline_indent(snippet, span.start.column),
+                "^".repeat(carets),
+            ));
+        }
+        out
+    }
+
+    // one JSON object, without a trailing newline
+    pub fn render_json(&self, file: &str) -> String {
+        let span = match self.span {
+            Some(Span { start, end }) => format!(
+                "{{\"start\":{{\"line\":{},\"column\":{}}},\"end\":{{\"line\":{},\"column\":{}}}}}",
+                start.line, start.column, end.line, end.column
+            ),
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"file\":{},\"severity\":\"{}\",\"rule\":{},\"message\":{},\"span\":{span},\"fixable\":{}}}",
+            json::string(file),
+            self.severity.as_str(),
+            json::string(self.rule),
+            json::string(&self.message),
+            self.fix.is_some(),
+        )
+    }
+}
+
+// Whitespace reaching `column` of `line`, keeping its tabs so that the
+// underline lines up however wide the terminal shows them
+fn underline_indent(line: &str, column: usize) -> String {
+    let mut indent = String::new();
+    let mut used = 1;
+    for c in line.chars() {
+        if used >= column {
+            break;
+        }
+        match c {
+            '\t' => indent.push('\t'),
+            c => indent.extend(std::iter::repeat_n(' ', width::char_width(c))),
+        }
+        used += width::char_width(c);
+    }
+    indent
+}
+
+// the first line on which `formatted` differs from `original`
+pub fn first_difference(original: &[u8], formatted: &[u8]) -> Option<usize> {
+    if original == formatted {
+        return None;
+    }
+    let mut original_lines = original.split(|&c| c == b'\n');
+    let mut formatted_lines = formatted.split(|&c| c == b'\n');
+    let mut line = 1;
+    loop {
+        match (original_lines.next(), formatted_lines.next()) {
+            (Some(a), Some(b)) if a == b => line += 1,
+            _ => return Some(line),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{first_difference, Diagnostic, Position, Severity, Span};
+
+    fn diagnostic(span: Option<Span>) -> Diagnostic {
+        Diagnostic {
+            rule: "would-reformat",
+            severity: Severity::Warning,
+            message: "file is \"not\" formatted".to_string(),
+            span,
+            snippet: None,
+            fix: None,
+        }
+    }
+
+    #[test]
+    fn renders_one_json_object() {
+        let span = Span {
+            start: Position { line: 2, column: 3 },
+            end: Position { line: 2, column: 7 },
+        };
+        assert_eq!(
+            diagnostic(Some(span)).render_json("dir\\a.tcl"),
+            r#"{"file":"dir\\a.tcl","severity":"warning","rule":"would-reformat","message":"file is \"not\" formatted","span":{"start":{"line":2,"column":3},"end":{"line":2,"column":7}},"fixable":false}"#
+        );
+        assert!(diagnostic(None)
+            .render_json("a.tcl")
+            .contains(r#""span":null,"#));
+    }
+
+    #[test]
+    fn renders_human_location() {
+        assert_eq!(
+            diagnostic(Some(Span::line(4))).render_human("a.tcl", false),
+            "warning[would-reformat]: a.tcl:4:1: file is \"not\" formatted"
+        );
+        assert_eq!(
+            diagnostic(None).render_human("a.tcl", false),
+            "warning[would-reformat]: a.tcl: file is \"not\" formatted"
+        );
+    }
+
+    #[test]
+    fn finds_first_differing_line() {
+        assert_eq!(first_difference(b"a\nb\n", b"a\nb\n"), None);
+        assert_eq!(first_difference(b"a\nb\nc\n", b"a\nB\nc\n"), Some(2));
+        assert_eq!(first_difference(b"a\n", b"a\nb\n"), Some(2));
+        assert_eq!(first_difference(b"a", b"a\n"), Some(2));
+    }
+
+    #[test]
+    fn underlines_the_span_under_the_snippet() {
+        let d = Diagnostic {
+            snippet: Some("\t日本 x {".to_string()),
+            span: Some(Span {
+                start: Position {
+                    line: 12,
+                    column: 7,
+                },
+                end: Position {
+                    line: 12,
+                    column: 8,
+                },
+            }),
+            ..diagnostic(None)
+        };
+        assert_eq!(
+            d.render_human("a.tcl", false),
+            "warning[would-reformat]: a.tcl:12:7: file is \"not\" formatted\n   |\n12 | \t日本 x {\n   | \t     ^"
+        );
+        let colored = d.render_human("a.tcl", true);
+        assert!(colored.starts_with("\x1b[1;33mwarning[would-reformat]\x1b[0m: "));
+    }
+}
@@ -0,0 +1,374 @@
+// Unified diffs of what formatting would change, for reviewing a run before
+// letting it write. Changed lines can also be compared word by word: the
+// words that differ are shown in reverse video with color, or as git's
+// `[-removed-]` and `{+added+}` without.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edit {
+    Equal,
+    Delete,
+    Insert,
+}
+
+const HEADER: &str = "\x1b[1m";
+const HUNK: &str = "\x1b[36m";
+const DELETED: &str = "\x1b[31m";
+const INSERTED: &str = "\x1b[32m";
+const MARK: &str = "\x1b[7m";
+const UNMARK: &str = "\x1b[27m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Clone)]
+pub struct UnifiedDiff {
+    context: usize, // unchanged lines around each change
+    word_level: bool,
+    color: bool,
+}
+
+impl Default for UnifiedDiff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UnifiedDiff {
+    pub fn new() -> Self {
+        Self {
+            context: 3,
+            word_level: false,
+            color: false,
+        }
+    }
+
+    pub fn context(mut self, context: usize) -> Self {
+        self.context = context;
+        self
+    }
+
+    pub fn word_level(mut self, word_level: bool) -> Self {
+        self.word_level = word_level;
+        self
+    }
+
+    pub fn color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    // the diff turning `original` into `formatted`, empty if they are equal
+    pub fn render(&self, name: &str, original: &[u8], formatted: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        if original == formatted {
+            return out;
+        }
+        let old: Vec<&[u8]> = original.split_inclusive(|&c| c == b'\n').collect();
+        let new: Vec<&[u8]> = formatted.split_inclusive(|&c| c == b'\n').collect();
+        // each edit with the line of `old` and of `new` it is at
+        let mut ops = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        for edit in edits(&old, &new) {
+            ops.push((edit, i, j));
+            match edit {
+                Edit::Equal => (i, j) = (i + 1, j + 1),
+                Edit::Delete => i += 1,
+                Edit::Insert => j += 1,
+            }
+        }
+
+        self.paint(&mut out, HEADER, format!("--- a/{name}").as_bytes());
+        self.paint(&mut out, HEADER, format!("+++ b/{name}").as_bytes());
+        let changes: Vec<usize> = (0..ops.len())
+            .filter(|&idx| ops[idx].0 != Edit::Equal)
+            .collect();
+        let mut idx = 0;
+        while idx < changes.len() {
+            let start = changes[idx].saturating_sub(self.context);
+            let mut last = changes[idx];
+            idx += 1;
+            // changes with little enough between them share a hunk
+            while idx < changes.len() && changes[idx] - last <= 2 * self.context + 1 {
+                last = changes[idx];
+                idx += 1;
+            }
+            let end = (last + 1 + self.context).min(ops.len());
+            self.hunk(&mut out, &ops[start..end], &old, &new);
+        }
+        out
+    }
+
+    fn hunk(&self, out: &mut Vec<u8>, ops: &[(Edit, usize, usize)], old: &[&[u8]], new: &[&[u8]]) {
+        let old_count = ops.iter().filter(|op| op.0 != Edit::Insert).count();
+        let new_count = ops.iter().filter(|op| op.0 != Edit::Delete).count();
+        // an empty range starts at the line before it
+        let (_, i, j) = ops[0];
+        let old_start = i + usize::from(old_count > 0);
+        let new_start = j + usize::from(new_count > 0);
+        let header = format!("@@ -{old_start},{old_count} +{new_start},{new_count} @@");
+        self.paint(out, HUNK, header.as_bytes());
+
+        let mut idx = 0;
+        while idx < ops.len() {
+            if ops[idx].0 == Edit::Equal {
+                self.line(out, b' ', old[ops[idx].1], None);
+                idx += 1;
+                continue;
+            }
+            let run = ops[idx..]
+                .iter()
+                .take_while(|op| op.0 != Edit::Equal)
+                .count();
+            let deleted: Vec<&[u8]> = ops[idx..idx + run]
+                .iter()
+                .filter(|op| op.0 == Edit::Delete)
+                .map(|op| old[op.1])
+                .collect();
+            let inserted: Vec<&[u8]> = ops[idx..idx + run]
+                .iter()
+                .filter(|op| op.0 == Edit::Insert)
+                .map(|op| new[op.2])
+                .collect();
+            idx += run;
+            // the n-th removed line is compared with the n-th added one
+            for (n, line) in deleted.iter().enumerate() {
+                let other = inserted.get(n).filter(|_| self.word_level);
+                self.line(out, b'-', line, other.map(|other| (*line, *other)));
+            }
+            for (n, line) in inserted.iter().enumerate() {
+                let other = deleted.get(n).filter(|_| self.word_level);
+                self.line(out, b'+', line, other.map(|other| (*other, *line)));
+            }
+        }
+    }
+
+    // One line of the diff. `pair` is the removed and the added line to mark
+    // the differing words of.
+    fn line(&self, out: &mut Vec<u8>, sign: u8, line: &[u8], pair: Option<(&[u8], &[u8])>) {
+        let (text, newline) = match line.strip_suffix(b"\n") {
+            Some(text) => (text, true),
+            None => (line, false),
+        };
+        let color = match sign {
+            b'-' => DELETED,
+            b'+' => INSERTED,
+            _ => "",
+        };
+        if self.color && !color.is_empty() {
+            out.extend_from_slice(color.as_bytes());
+        }
+        out.push(sign);
+        match pair {
+            None => out.extend_from_slice(text),
+            Some((removed, added)) => {
+                let (open, close): (&[u8], &[u8]) = match (self.color, sign) {
+                    (true, _) => (MARK.as_bytes(), UNMARK.as_bytes()),
+                    (false, b'-') => (b"[-", b"-]"),
+                    (false, _) => (b"{+", b"+}"),
+                };
+                let mut marking = false;
+                for (word, marked) in marked_words(removed, added, sign == b'+') {
+                    if marked != marking {
+                        out.extend_from_slice(if marked { open } else { close });
+                        marking = marked;
+                    }
+                    out.extend_from_slice(word);
+                }
+                if marking {
+                    out.extend_from_slice(close);
+                }
+            }
+        }
+        if self.color && !color.is_empty() {
+            out.extend_from_slice(RESET.as_bytes());
+        }
+        out.push(b'\n');
+        if !newline {
+            out.extend_from_slice(b"\\ No newline at end of file\n");
+        }
+    }
+
+    fn paint(&self, out: &mut Vec<u8>, color: &str, text: &[u8]) {
+        if self.color {
+            out.extend_from_slice(color.as_bytes());
+        }
+        out.extend_from_slice(text);
+        if self.color {
+            out.extend_from_slice(RESET.as_bytes());
+        }
+        out.push(b'\n');
+    }
+}
+
+// The words of the added line, or of the removed one, each with whether it
+// differs from the other line
+fn marked_words<'a>(removed: &'a [u8], added: &'a [u8], of_added: bool) -> Vec<(&'a [u8], bool)> {
+    let strip = |line: &'a [u8]| line.strip_suffix(b"\n").unwrap_or(line);
+    let removed = words(strip(removed));
+    let added = words(strip(added));
+    let (mut i, mut j) = (0, 0);
+    let mut marked = Vec::new();
+    for edit in edits(&removed, &added) {
+        match (edit, of_added) {
+            (Edit::Equal, false) => marked.push((removed[i], false)),
+            (Edit::Equal, true) => marked.push((added[j], false)),
+            (Edit::Delete, false) => marked.push((removed[i], true)),
+            (Edit::Insert, true) => marked.push((added[j], true)),
+            _ => {}
+        }
+        match edit {
+            Edit::Equal => (i, j) = (i + 1, j + 1),
+            Edit::Delete => i += 1,
+            Edit::Insert => j += 1,
+        }
+    }
+    marked
+}
+
+// runs of whitespace, runs of word characters and single punctuation bytes
+fn words(line: &[u8]) -> Vec<&[u8]> {
+    let class = |c: u8| match c {
+        b' ' | b'\t' => 0,
+        c if c.is_ascii_alphanumeric() || c == b'_' || c >= 0x80 => 1,
+        _ => 2,
+    };
+    let mut words = Vec::new();
+    let mut start = 0;
+    for idx in 1..=line.len() {
+        let boundary =
+            idx == line.len() || class(line[idx]) != class(line[start]) || class(line[start]) == 2;
+        if boundary {
+            words.push(&line[start..idx]);
+            start = idx;
+        }
+    }
+    words
+}
+
+// Myers' O(ND) algorithm: a shortest edit script turning `a` into `b`
+fn edits<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Edit> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (n + m) as usize;
+    let offset = max as isize + 1;
+    let mut v = vec![0isize; 2 * max + 3];
+    // v before each round d, over the diagonals -d-1..=d+1 the round reads
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    'search: for d in 0..=max as isize {
+        trace.push(v[(offset - d - 1) as usize..=(offset + d + 1) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let at = |k: isize| (offset + k) as usize;
+            let mut x = if k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)]) {
+                v[at(k + 1)]
+            } else {
+                v[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                (x, y) = (x + 1, y + 1);
+            }
+            v[at(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut script = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let at = |k: isize| (k + d + 1) as usize;
+        let k = x - y;
+        let previous_k = if k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let previous_x = v[at(previous_k)];
+        let previous_y = previous_x - previous_k;
+        while x > previous_x && y > previous_y {
+            script.push(Edit::Equal);
+            (x, y) = (x - 1, y - 1);
+        }
+        if d > 0 {
+            script.push(if x == previous_x {
+                Edit::Insert
+            } else {
+                Edit::Delete
+            });
+        }
+        (x, y) = (previous_x, previous_y);
+    }
+    script.reverse();
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{edits, Edit, UnifiedDiff};
+
+    fn render(diff: UnifiedDiff, original: &str, formatted: &str) -> String {
+        String::from_utf8(diff.render("a.tcl", original.as_bytes(), formatted.as_bytes())).unwrap()
+    }
+
+    #[test]
+    fn renders_nothing_for_equal_input() {
+        assert_eq!(render(UnifiedDiff::new(), "set a 1\n", "set a 1\n"), "");
+    }
+
+    #[test]
+    fn renders_hunks_with_context() {
+        let original = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let formatted = "1\n2\nthree\n4\n5\n6\n7\n8\nnine\n";
+        assert_eq!(
+            render(UnifiedDiff::new().context(1), original, formatted),
+            "--- a/a.tcl\n+++ b/a.tcl\n@@ -2,3 +2,3 @@\n 2\n-3\n+three\n 4\n@@ -8,2 +8,2 @@\n 8\n-9\n+nine\n"
+        );
+        // with more context the hunks merge
+        assert_eq!(
+            render(UnifiedDiff::new(), original, formatted)
+                .matches("@@ -")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn marks_changed_words() {
+        let diff = render(
+            UnifiedDiff::new().word_level(true),
+            "set a  1\n",
+            "set a 1\nset b 2\n",
+        );
+        // whitespace runs are words of their own
+        assert!(
+            diff.contains("\n-set a[-  -]1\n+set a{+ +}1\n+set b 2\n"),
+            "{diff}"
+        );
+        let diff = render(
+            UnifiedDiff::new().word_level(true),
+            "if {$a} {\n",
+            "if { $a } {\n",
+        );
+        assert!(
+            diff.ends_with("\n-if {$a} {\n+if {{+ +}$a{+ +}} {\n"),
+            "{diff}"
+        );
+    }
+
+    #[test]
+    fn colors_only_when_asked() {
+        let plain = render(UnifiedDiff::new(), "a\n", "b\n");
+        assert!(!plain.contains('\x1b'));
+        let colored = render(UnifiedDiff::new().color(true), "a\n", "b\n");
+        assert!(colored.contains("\x1b[31m-a"), "{colored:?}");
+    }
+
+    #[test]
+    fn finds_a_shortest_edit_script() {
+        use Edit::*;
+        assert_eq!(
+            edits(&[1, 2, 3, 4], &[1, 3, 4, 5]),
+            [Equal, Delete, Equal, Equal, Insert]
+        );
+        assert_eq!(edits::<u8>(&[], &[]), []);
+    }
+}
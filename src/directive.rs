@@ -0,0 +1,174 @@
+// `# tclfmt: max_width=120 one_line_bodies=true` comments, setting options
+// for part of a file without a config section for it. The options the
+// formatter reads as it goes apply from the comment to the end of the block
+// it is in, so a directive at the top level covers the rest of the file.
+// Those shaping the whole output apply to the whole file, and can only be
+// set before its first command.
+use crate::{
+    config::{parse_value, Config, ConfigFail, Entry, Value},
+    formatter::FormatterOptions,
+};
+
+type Result<T> = std::result::Result<T, ConfigFail>;
+
+// options a directive can set anywhere
+pub const SCOPED_KEYS: &[&str] = &[
+    "cuddle_else",
+    "pad_braces",
+    "max_empty_lines",
+    "align_switch_arms",
+    "compact_empty_blocks",
+    "max_width",
+    "one_line_bodies",
+];
+
+// options a directive can only set before the first command
+pub const FILE_KEYS: &[&str] = &[
+    "style",
+    "indent_width",
+    "use_tabs",
+    "line_ending",
+    "final_newline",
+    "align_trailing_comments",
+    "blank_line_between_definitions",
+    "keep_semicolons",
+];
+
+// The settings of a comment, given its text after `#`, or None if it isn't
+// an options directive. Values are as in .tclfmt.toml, but strings need no
+// quotes. `line` is where errors about them point.
+pub fn entries(comment: &[u8], line: usize) -> Option<Vec<Entry>> {
+    // checked first, as the formatter asks about every comment
+    if !comment.trim_ascii_start().starts_with(b"tclfmt:") {
+        return None;
+    }
+    let text = std::str::from_utf8(comment).ok()?.trim();
+    let settings: Vec<&str> = text.strip_prefix("tclfmt:")?.split_whitespace().collect();
+    if settings.is_empty() || !settings.iter().all(|setting| setting.contains('=')) {
+        return None;
+    }
+    let entries = settings
+        .into_iter()
+        .filter_map(|setting| setting.split_once('='))
+        .map(|(key, value)| Entry {
+            line,
+            section: None,
+            key: key.to_string(),
+            value: parse_value(value).unwrap_or_else(|| Value::String(value.to_string())),
+        })
+        .collect();
+    Some(entries)
+}
+
+// Checks the directives of `source` and sets those before its first command
+// on `config`. Comments are found line by line, so lines starting with `#`
+// within a braced word are taken for directives too.
+pub fn configure(config: &mut Config, source: &[u8]) -> Result<()> {
+    let mut header = true;
+    for (idx, line) in source.split(|&c| c == b'\n').enumerate() {
+        let line = line.trim_ascii();
+        let Some(comment) = line.strip_prefix(b"#") else {
+            header &= line.is_empty();
+            continue;
+        };
+        for entry in entries(comment, idx + 1).into_iter().flatten() {
+            let misplaced = |reason| ConfigFail::Misplaced {
+                line: entry.line,
+                key: entry.key.clone(),
+                reason,
+            };
+            Config::default().set(None, &entry)?;
+            if FILE_KEYS.contains(&entry.key.as_str()) {
+                if !header {
+                    return Err(misplaced(
+                        "applies to the whole file, set it before the first command",
+                    ));
+                }
+            } else if !SCOPED_KEYS.contains(&entry.key.as_str()) {
+                return Err(misplaced("can only be set in .tclfmt.toml"));
+            }
+            if header {
+                config.set(None, &entry)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// `options` with the settings of a directive met while formatting. Those it
+// can't change at this point are left to `configure`; an invalid value is an
+// error, and leaves the options of the entries after it unset.
+pub fn apply(options: &mut FormatterOptions, entries: &[Entry]) -> Result<()> {
+    let mut config = Config::default();
+    let set = entries
+        .iter()
+        .filter(|entry| SCOPED_KEYS.contains(&entry.key.as_str()))
+        .try_for_each(|entry| config.set(None, entry));
+    config.apply_options(options);
+    set
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, configure, entries};
+    use crate::{
+        config::{Config, ConfigFail},
+        formatter::FormatterOptions,
+    };
+
+    #[test]
+    fn reads_settings_from_comments() {
+        let settings = entries(b" tclfmt: max_width=80 style=tcl-std", 3).unwrap();
+        let keys: Vec<_> = settings.iter().map(|e| (e.key.as_str(), e.line)).collect();
+        assert_eq!(keys, [("max_width", 3), ("style", 3)]);
+        assert!(entries(b" tclfmt: off", 1).is_none());
+        assert!(entries(b" just a comment", 1).is_none());
+        // only the start of the comment is looked at
+        assert!(entries(b"\t tclfmt: indent_width=2 ", 1).is_some());
+        assert!(entries(b" see tclfmt: indent_width=2", 1).is_none());
+        assert!(entries(b" \xff tclfmt: indent_width=2", 1).is_none());
+    }
+
+    #[test]
+    fn applies_scoped_settings() {
+        let mut options = FormatterOptions::default();
+        let entries = entries(
+            b" tclfmt: max_width=80 one_line_bodies=true indent_width=2",
+            1,
+        );
+        apply(&mut options, &entries.unwrap()).unwrap();
+        assert_eq!(options.max_width, Some(80));
+        assert!(options.one_line_bodies);
+        assert_eq!(options.indent_width, 4);
+    }
+
+    #[test]
+    fn reports_invalid_values() {
+        let mut options = FormatterOptions::default();
+        let settings = entries(b" tclfmt: cuddle_else=true max_width=wide", 7).unwrap();
+        let e = apply(&mut options, &settings).unwrap_err();
+        assert!(matches!(e, ConfigFail::InvalidValue { line: 7, .. }));
+        assert!(options.cuddle_else);
+        assert_eq!(options.max_width, None);
+    }
+
+    #[test]
+    fn sets_file_options_from_the_header() {
+        let mut config = Config::default();
+        configure(&mut config, b"# tclfmt: indent_width=2\n\nset a 1\n").unwrap();
+        assert_eq!(config.indent_width, Some(2));
+    }
+
+    #[test]
+    fn rejects_misplaced_and_invalid_directives() {
+        let late = b"set a 1\n# tclfmt: use_tabs=true\n";
+        let e = configure(&mut Config::default(), late).unwrap_err();
+        assert!(matches!(e, ConfigFail::Misplaced { line: 2, .. }));
+        let config_only = b"# tclfmt: encoding=latin-1\n";
+        let e = configure(&mut Config::default(), config_only).unwrap_err();
+        assert!(matches!(e, ConfigFail::Misplaced { line: 1, .. }));
+        let invalid = b"when X {\n    # tclfmt: max_empty_lines=-1\n}\n";
+        let e = configure(&mut Config::default(), invalid).unwrap_err();
+        assert!(matches!(e, ConfigFail::InvalidValue { line: 2, .. }));
+    }
+}
@@ -0,0 +1,196 @@
+// `.editorconfig` support: indentation, line ending and final newline
+// settings for a file, resolved the way editors do. Files are read from the
+// file's directory upwards until one declares `root = true`; closer files and
+// later sections take precedence.
+use std::path::Path;
+
+use crate::{formatter::LineEnding, glob};
+
+pub const EDITORCONFIG_FILE_NAME: &str = ".editorconfig";
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct EditorConfig {
+    pub use_tabs: Option<bool>,
+    pub indent_width: Option<usize>,
+    pub line_ending: Option<LineEnding>,
+    pub final_newline: Option<bool>,
+}
+
+impl EditorConfig {
+    // `path` should be absolute, so that every ancestor directory is visited
+    pub fn resolve(path: &Path) -> std::io::Result<Self> {
+        let mut files = Vec::new();
+        for dir in path.ancestors().skip(1) {
+            let candidate = dir.join(EDITORCONFIG_FILE_NAME);
+            if !candidate.is_file() {
+                continue;
+            }
+            let text = std::fs::read_to_string(&candidate)?;
+            let root = is_root(&text);
+            files.push((dir, text));
+            if root {
+                break;
+            }
+        }
+
+        let mut properties = Properties::default();
+        for (dir, text) in files.iter().rev() {
+            let Ok(relative) = path.strip_prefix(dir) else {
+                continue;
+            };
+            let relative = relative.to_string_lossy().replace('\\', "/");
+            properties.apply(text, &relative);
+        }
+        Ok(properties.resolve())
+    }
+}
+
+#[derive(Default)]
+struct Properties {
+    indent_style: Option<String>,
+    indent_size: Option<String>,
+    tab_width: Option<String>,
+    end_of_line: Option<String>,
+    insert_final_newline: Option<String>,
+}
+
+impl Properties {
+    // applies the sections of one file matching `relative`, in file order
+    fn apply(&mut self, text: &str, relative: &str) {
+        let mut matching = false;
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                matching = glob::matches_relative(section, relative);
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            if !matching {
+                continue;
+            }
+            let value = Some(value.trim().to_ascii_lowercase());
+            match key.trim().to_ascii_lowercase().as_str() {
+                "indent_style" => self.indent_style = value,
+                "indent_size" => self.indent_size = value,
+                "tab_width" => self.tab_width = value,
+                "end_of_line" => self.end_of_line = value,
+                "insert_final_newline" => self.insert_final_newline = value,
+                _ => {}
+            }
+        }
+    }
+
+    // `unset` and unrecognised values leave the option to the formatter
+    fn resolve(self) -> EditorConfig {
+        let use_tabs = match self.indent_style.as_deref() {
+            Some("tab") => Some(true),
+            Some("space") => Some(false),
+            _ => None,
+        };
+        let indent_width = match self.indent_size.as_deref() {
+            Some("tab") => self.tab_width.as_deref().and_then(|w| w.parse().ok()),
+            Some(size) => size.parse().ok(),
+            None => None,
+        };
+        let line_ending = self.end_of_line.as_deref().and_then(LineEnding::from_name);
+        let final_newline = match self.insert_final_newline.as_deref() {
+            Some("true") => Some(true),
+            Some("false") => Some(false),
+            _ => None,
+        };
+        EditorConfig {
+            use_tabs,
+            indent_width: indent_width.filter(|&w| w > 0),
+            line_ending,
+            final_newline,
+        }
+    }
+}
+
+// root = true, before the first section
+fn is_root(text: &str) -> bool {
+    text.lines()
+        .map(str::trim)
+        .take_while(|line| !line.starts_with('['))
+        .filter_map(|line| line.split_once('='))
+        .any(|(key, value)| {
+            key.trim().eq_ignore_ascii_case("root") && value.trim().eq_ignore_ascii_case("true")
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_root, EditorConfig, Properties, EDITORCONFIG_FILE_NAME};
+    use crate::formatter::LineEnding;
+
+    fn resolve(text: &str, relative: &str) -> EditorConfig {
+        let mut properties = Properties::default();
+        properties.apply(text, relative);
+        properties.resolve()
+    }
+
+    #[test]
+    fn reads_matching_sections_in_order() {
+        let text = "root = true\n[*]\nindent_style = space\nindent_size = 4\n\n[*.tcl]\nindent_size = 2\nend_of_line = CRLF\ninsert_final_newline = false\n\n[*.md]\nindent_style = tab\n";
+        assert_eq!(
+            resolve(text, "lib/a.tcl"),
+            EditorConfig {
+                use_tabs: Some(false),
+                indent_width: Some(2),
+                line_ending: Some(LineEnding::CrLf),
+                final_newline: Some(false),
+            }
+        );
+        assert_eq!(resolve(text, "a.irule").indent_width, Some(4));
+        assert!(is_root(text));
+        assert!(!is_root("[*]\nroot = true\n"));
+    }
+
+    #[test]
+    fn leaves_unset_and_unknown_values_to_the_formatter() {
+        let text =
+            "[*]\nindent_style = unset\nindent_size = tab\ntab_width = 8\nend_of_line = nel\n";
+        assert_eq!(
+            resolve(text, "a.tcl"),
+            EditorConfig {
+                indent_width: Some(8),
+                ..EditorConfig::default()
+            }
+        );
+        assert_eq!(
+            resolve("[*]\nindent_size = 0\n", "a.tcl").indent_width,
+            None
+        );
+    }
+
+    #[test]
+    fn closer_files_take_precedence_up_to_the_root() {
+        let dir = std::env::temp_dir().join(format!("tclfmt-editorconfig-{}", std::process::id()));
+        let nested = dir.join("project").join("src");
+        std::fs::create_dir_all(&nested).unwrap();
+        let write = |path: std::path::PathBuf, text: &str| {
+            std::fs::write(path.join(EDITORCONFIG_FILE_NAME), text).unwrap()
+        };
+        write(dir.clone(), "[*]\nend_of_line = cr\n");
+        write(
+            dir.join("project"),
+            "root = true\n[*]\nindent_style = tab\nindent_size = 4\n",
+        );
+        write(nested.clone(), "[*.tcl]\nindent_size = 3\n");
+        let resolved = EditorConfig::resolve(&nested.join("a.tcl")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            resolved,
+            EditorConfig {
+                use_tabs: Some(true),
+                indent_width: Some(3),
+                ..EditorConfig::default()
+            }
+        );
+    }
+}
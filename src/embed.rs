@@ -0,0 +1,651 @@
+// Tcl embedded in other documents: ```tcl fenced blocks in Markdown, string
+// fields of JSON and YAML documents picked by path selectors, and the bodies
+// of `ltm rule <name> { ... }` objects in tmsh configuration. Each
+// script is extracted, formatted on its own and spliced back, leaving every
+// other byte of the host document as it was.
+//
+// A selector is a dotted path of keys, like `declaration.*.*.iRule`, where
+// `*` matches any one key or array index and `**` any number of them. In
+// YAML, only block scalars (`key: |`) are formatted, as other styles would
+// need the script re-quoted or re-folded.
+use std::borrow::Cow;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::json;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Host {
+    Markdown,
+    Json,
+    Yaml,
+    Tmsh, // bigip.conf and `tmsh list ltm rule` exports
+}
+
+impl Host {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "md" | "markdown" => Some(Host::Markdown),
+            "json" => Some(Host::Json),
+            "yaml" | "yml" => Some(Host::Yaml),
+            "conf" => Some(Host::Tmsh),
+            _ => None,
+        }
+    }
+
+    // `from_path`, or Tmsh for a file of any other name that starts with an
+    // `ltm rule` object, as exports bundling several iRules do
+    pub fn detect(path: &Path, doc: &[u8]) -> Option<Self> {
+        Self::from_path(path).or_else(|| {
+            let first = lines(doc)
+                .into_iter()
+                .map(|(_, line)| line)
+                .find(|line| !line.trim_ascii().is_empty())?;
+            rule_header(first).then_some(Host::Tmsh)
+        })
+    }
+}
+
+// One embedded script
+#[derive(Debug)]
+pub struct Region {
+    pub range: Range<usize>, // bytes of the host document it occupies
+    pub line: usize,         // 1-based line of the host the script starts on
+    embedding: Embedding,
+}
+
+#[derive(Debug)]
+enum Embedding {
+    // whole lines, each indented by at least `indent` spaces that aren't
+    // part of the script
+    Lines { indent: usize },
+    // a JSON string literal, quotes included
+    JsonString { final_newline: bool },
+}
+
+impl Region {
+    // whether line n of the script is line `self.line + n - 1` of the host
+    pub fn maps_lines(&self) -> bool {
+        matches!(self.embedding, Embedding::Lines { .. })
+    }
+
+    // the script as Tcl source, or None if it isn't validly encoded
+    pub fn script<'a>(&self, doc: &'a [u8]) -> Option<Cow<'a, [u8]>> {
+        let text = &doc[self.range.clone()];
+        match self.embedding {
+            Embedding::Lines { indent } => Some(Cow::Owned(
+                text.split_inclusive(|&c| c == b'\n')
+                    .flat_map(|line| {
+                        let spaces = line.iter().take(indent).take_while(|&&c| c == b' ');
+                        &line[spaces.count()..]
+                    })
+                    .copied()
+                    .collect(),
+            )),
+            Embedding::JsonString { .. } => json_unescape(text).map(Cow::Owned),
+        }
+    }
+
+    // `script` encoded to take the region's place in the host
+    pub fn embed(&self, script: &[u8]) -> Vec<u8> {
+        match self.embedding {
+            Embedding::Lines { indent } => {
+                let mut out = Vec::with_capacity(script.len());
+                for line in script.split_inclusive(|&c| c == b'\n') {
+                    if line != b"\n" && line != b"\r\n" {
+                        out.extend(std::iter::repeat_n(b' ', indent));
+                    }
+                    out.extend_from_slice(line);
+                }
+                if !out.ends_with(b"\n") {
+                    out.push(b'\n');
+                }
+                out
+            }
+            Embedding::JsonString { final_newline } => {
+                let script = match final_newline {
+                    true => script,
+                    false => script.strip_suffix(b"\n").unwrap_or(script),
+                };
+                json::string(&String::from_utf8_lossy(script)).into_bytes()
+            }
+        }
+    }
+}
+
+// Regions in document order; `paths` select JSON and YAML fields
+pub fn regions(host: Host, doc: &[u8], paths: &[String]) -> Vec<Region> {
+    let selectors: Vec<Vec<&str>> = paths.iter().map(|p| p.split('.').collect()).collect();
+    let mut regions = match host {
+        Host::Markdown => markdown(doc),
+        Host::Json => {
+            let mut walker = JsonWalker {
+                doc,
+                pos: 0,
+                path: Vec::new(),
+                selectors: &selectors,
+                regions: Vec::new(),
+            };
+            // a malformed document keeps the fields found before the error
+            let _ = walker.value();
+            walker.regions
+        }
+        Host::Yaml => yaml(doc, &selectors),
+        Host::Tmsh => tmsh(doc),
+    };
+    for region in &mut regions {
+        region.line = 1 + doc[..region.range.start]
+            .iter()
+            .filter(|&&c| c == b'\n')
+            .count();
+    }
+    regions
+}
+
+// `doc` with each region replaced by the matching replacement
+pub fn splice(doc: &[u8], regions: &[Region], replacements: Vec<Vec<u8>>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(doc.len());
+    let mut pos = 0;
+    for (region, replacement) in regions.iter().zip(replacements) {
+        out.extend_from_slice(&doc[pos..region.range.start]);
+        out.extend_from_slice(&replacement);
+        pos = region.range.end;
+    }
+    out.extend_from_slice(&doc[pos..]);
+    out
+}
+
+fn matches(selector: &[&str], path: &[String]) -> bool {
+    match (selector.first(), path.first()) {
+        (None, None) => true,
+        (Some(&"**"), _) => {
+            matches(&selector[1..], path) || (!path.is_empty() && matches(selector, &path[1..]))
+        }
+        (Some(&segment), Some(key)) => {
+            (segment == "*" || segment == key) && matches(&selector[1..], &path[1..])
+        }
+        _ => false,
+    }
+}
+
+// (offset, line without its line ending) for each line of `doc`
+fn lines(doc: &[u8]) -> Vec<(usize, &[u8])> {
+    let mut offset = 0;
+    doc.split_inclusive(|&c| c == b'\n')
+        .map(|line| {
+            let start = offset;
+            offset += line.len();
+            let line = line.strip_suffix(b"\n").unwrap_or(line);
+            (start, line.strip_suffix(b"\r").unwrap_or(line))
+        })
+        .collect()
+}
+
+fn indentation(line: &[u8]) -> usize {
+    line.iter().take_while(|&&c| c == b' ').count()
+}
+
+fn markdown(doc: &[u8]) -> Vec<Region> {
+    let lines = lines(doc);
+    let mut regions = Vec::new();
+    let mut idx = 0;
+    while idx < lines.len() {
+        let (_, line) = lines[idx];
+        idx += 1;
+        let indent = indentation(line);
+        let fence = &line[indent..];
+        let Some(&marker @ (b'`' | b'~')) = fence.first() else {
+            continue;
+        };
+        let len = fence.iter().take_while(|&&c| c == marker).count();
+        let info = String::from_utf8_lossy(&fence[len..]).to_lowercase();
+        let language = info.split_whitespace().next().unwrap_or("");
+        if len < 3 || !matches!(language, "tcl" | "irule") {
+            continue;
+        }
+        let Some(close) = lines[idx..].iter().position(|(_, line)| {
+            let line = line.trim_ascii();
+            line.len() >= len && line.iter().all(|&c| c == marker)
+        }) else {
+            break;
+        };
+        if close > 0 {
+            regions.push(Region {
+                range: lines[idx].0..lines[idx + close].0,
+                line: 0,
+                embedding: Embedding::Lines { indent },
+            });
+        }
+        idx += close + 1;
+    }
+    regions
+}
+
+fn yaml(doc: &[u8], selectors: &[Vec<&str>]) -> Vec<Region> {
+    let lines = lines(doc);
+    let mut regions = Vec::new();
+    let mut path: Vec<(usize, String)> = Vec::new(); // (indentation, key)
+    let mut idx = 0;
+    while idx < lines.len() {
+        let (_, line) = lines[idx];
+        idx += 1;
+        let mut indent = indentation(line);
+        let mut content = &line[indent..];
+        if content.is_empty() || content.starts_with(b"#") {
+            continue;
+        }
+        path.retain(|(i, _)| *i < indent);
+        // `- key: value` opens an item and a key within it
+        while let Some(rest) = content.strip_prefix(b"- ") {
+            path.push((indent, "-".to_string()));
+            indent += 2 + indentation(rest);
+            content = rest.trim_ascii_start();
+        }
+        let Some(colon) = content
+            .windows(2)
+            .position(|w| w == b": ")
+            .or_else(|| content.ends_with(b":").then(|| content.len() - 1))
+        else {
+            continue;
+        };
+        let key = String::from_utf8_lossy(&content[..colon]);
+        let key = key.trim().trim_matches(|c| c == '"' || c == '\'');
+        path.push((indent, key.to_string()));
+
+        let value = content[colon + 1..].trim_ascii();
+        let block = value.first() == Some(&b'|') && !value[1..].contains(&b'#');
+        let keys: Vec<String> = path.iter().map(|(_, key)| key.clone()).collect();
+        if !block || !selectors.iter().any(|s| matches(s, &keys)) {
+            continue;
+        }
+        // the scalar is every following line indented deeper, blank lines
+        // included, except blank lines at its end
+        let len = lines[idx..]
+            .iter()
+            .position(|(_, line)| !line.trim_ascii().is_empty() && indentation(line) <= indent)
+            .unwrap_or(lines.len() - idx);
+        let Some(last) = lines[idx..idx + len]
+            .iter()
+            .rposition(|(_, line)| !line.trim_ascii().is_empty())
+        else {
+            continue;
+        };
+        let content_indent = lines[idx..idx + len]
+            .iter()
+            .find(|(_, line)| !line.trim_ascii().is_empty())
+            .map_or(indent + 2, |(_, line)| indentation(line));
+        let end = lines
+            .get(idx + last + 1)
+            .map_or(doc.len(), |(start, _)| *start);
+        regions.push(Region {
+            range: lines[idx].0..end,
+            line: 0,
+            embedding: Embedding::Lines {
+                indent: content_indent,
+            },
+        });
+        idx += len;
+    }
+    regions
+}
+
+// `ltm rule <name> {` starting a line, the body on the lines that follow
+fn rule_header(line: &[u8]) -> bool {
+    let words: Vec<&[u8]> = line
+        .split(|c| c.is_ascii_whitespace())
+        .filter(|word| !word.is_empty())
+        .collect();
+    !line.starts_with(b" ")
+        && !line.starts_with(b"\t")
+        && matches!(words[..], [b"ltm", b"rule", _, b"{"])
+}
+
+// The body of each `ltm rule` object. tmsh keeps the body as it was given, so
+// its end is the brace closing it by the rules of a braced Tcl word. Other
+// objects are indented under their header and skipped line by line.
+fn tmsh(doc: &[u8]) -> Vec<Region> {
+    let lines = lines(doc);
+    let mut regions = Vec::new();
+    let mut idx = 0;
+    while idx < lines.len() {
+        let (start, line) = lines[idx];
+        idx += 1;
+        if !rule_header(line) {
+            continue;
+        }
+        let Some(end) = closing_brace(doc, start + line.len()) else {
+            break;
+        };
+        // the body is the whole lines up to a `}` on a line of its own
+        let close = idx
+            + lines[idx..]
+                .iter()
+                .take_while(|(offset, _)| *offset <= end)
+                .count()
+            - 1;
+        let (close_start, close_line) = lines[close];
+        let body = &lines[idx..close];
+        idx = close + 1;
+        if close_line.trim_ascii() != b"}" {
+            continue;
+        }
+        let Some(indent) = body
+            .iter()
+            .filter(|(_, line)| !line.trim_ascii().is_empty())
+            .map(|(_, line)| indentation(line))
+            .min()
+        else {
+            continue;
+        };
+        regions.push(Region {
+            range: body[0].0..close_start,
+            line: 0,
+            embedding: Embedding::Lines { indent },
+        });
+    }
+    regions
+}
+
+// the offset of the `}` matching the `{` that ends before `open`
+fn closing_brace(doc: &[u8], open: usize) -> Option<usize> {
+    let mut depth = 1;
+    let mut idx = open;
+    while idx < doc.len() {
+        match doc[idx] {
+            b'\\' => idx += 1,
+            b'{' => depth += 1,
+            b'}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(idx);
+                }
+            }
+            _ => {}
+        }
+        idx += 1;
+    }
+    None
+}
+
+struct JsonWalker<'a, 's> {
+    doc: &'a [u8],
+    pos: usize,
+    path: Vec<String>,
+    selectors: &'s [Vec<&'s str>],
+    regions: Vec<Region>,
+}
+
+impl JsonWalker<'_, '_> {
+    fn skip_whitespace(&mut self) {
+        while self
+            .doc
+            .get(self.pos)
+            .is_some_and(|c| c.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Option<()> {
+        self.skip_whitespace();
+        (self.doc.get(self.pos) == Some(&c)).then(|| self.pos += 1)
+    }
+
+    fn value(&mut self) -> Option<()> {
+        self.skip_whitespace();
+        match self.doc.get(self.pos)? {
+            b'{' => {
+                self.pos += 1;
+                self.skip_whitespace();
+                if self.doc.get(self.pos) == Some(&b'}') {
+                    self.pos += 1;
+                    return Some(());
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    let key = json_unescape(&self.doc[key])?;
+                    self.expect(b':')?;
+                    self.path.push(String::from_utf8_lossy(&key).into_owned());
+                    self.value()?;
+                    self.path.pop();
+                    self.skip_whitespace();
+                    match self.doc.get(self.pos)? {
+                        b',' => self.pos += 1,
+                        b'}' => break,
+                        _ => return None,
+                    }
+                }
+                self.pos += 1;
+            }
+            b'[' => {
+                self.pos += 1;
+                self.skip_whitespace();
+                if self.doc.get(self.pos) == Some(&b']') {
+                    self.pos += 1;
+                    return Some(());
+                }
+                for index in 0.. {
+                    self.path.push(index.to_string());
+                    self.value()?;
+                    self.path.pop();
+                    self.skip_whitespace();
+                    match self.doc.get(self.pos)? {
+                        b',' => self.pos += 1,
+                        b']' => break,
+                        _ => return None,
+                    }
+                }
+                self.pos += 1;
+            }
+            b'"' => {
+                let range = self.string()?;
+                if self.selectors.iter().any(|s| matches(s, &self.path)) {
+                    let final_newline = self.doc[range.clone()].ends_with(b"\\n\"");
+                    self.regions.push(Region {
+                        range,
+                        line: 0,
+                        embedding: Embedding::JsonString { final_newline },
+                    });
+                }
+            }
+            _ => {
+                // number, true, false or null
+                let len = self.doc[self.pos..]
+                    .iter()
+                    .take_while(|c| !matches!(c, b',' | b']' | b'}') && !c.is_ascii_whitespace())
+                    .count();
+                if len == 0 {
+                    return None;
+                }
+                self.pos += len;
+            }
+        }
+        Some(())
+    }
+
+    // the range of the string literal at the current position, quotes included
+    fn string(&mut self) -> Option<Range<usize>> {
+        let start = self.pos;
+        if self.doc.get(start) != Some(&b'"') {
+            return None;
+        }
+        let mut idx = start + 1;
+        loop {
+            match self.doc.get(idx)? {
+                b'\\' => idx += 2,
+                b'"' => break,
+                _ => idx += 1,
+            }
+        }
+        self.pos = idx + 1;
+        Some(start..self.pos)
+    }
+}
+
+// the value of a JSON string literal, quotes included
+fn json_unescape(literal: &[u8]) -> Option<Vec<u8>> {
+    let inner = literal.strip_prefix(b"\"")?.strip_suffix(b"\"")?;
+    let mut out = Vec::with_capacity(inner.len());
+    let mut idx = 0;
+    while idx < inner.len() {
+        if inner[idx] != b'\\' {
+            out.push(inner[idx]);
+            idx += 1;
+            continue;
+        }
+        let escaped = *inner.get(idx + 1)?;
+        idx += 2;
+        let c = match escaped {
+            b'"' => '"',
+            b'\\' => '\\',
+            b'/' => '/',
+            b'b' => '\u{8}',
+            b'f' => '\u{c}',
+            b'n' => '\n',
+            b'r' => '\r',
+            b't' => '\t',
+            b'u' => {
+                let mut unit = hex4(inner.get(idx..idx + 4)?)?;
+                idx += 4;
+                // a surrogate pair spells one character in two escapes
+                if (0xD800..0xDC00).contains(&unit) && inner.get(idx..idx + 2) == Some(b"\\u") {
+                    let low = hex4(inner.get(idx + 2..idx + 6)?)?;
+                    if (0xDC00..0xE000).contains(&low) {
+                        unit = 0x10000 + ((unit - 0xD800) << 10) + (low - 0xDC00);
+                        idx += 6;
+                    }
+                }
+                char::from_u32(unit)?
+            }
+            _ => return None,
+        };
+        let mut buf = [0; 4];
+        out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+    }
+    Some(out)
+}
+
+fn hex4(digits: &[u8]) -> Option<u32> {
+    u32::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{json_unescape, matches, regions, splice, Host};
+    use crate::formatter::Formatter;
+
+    // `doc` with every embedded script formatted with the default options
+    fn reformat(host: Host, doc: &str, paths: &[&str]) -> String {
+        let paths: Vec<String> = paths.iter().map(|p| p.to_string()).collect();
+        let regions = regions(host, doc.as_bytes(), &paths);
+        let replacements = regions
+            .iter()
+            .map(|region| {
+                let script = region.script(doc.as_bytes()).unwrap();
+                region.embed(&Formatter::new().try_format(&script).unwrap())
+            })
+            .collect();
+        String::from_utf8(splice(doc.as_bytes(), &regions, replacements)).unwrap()
+    }
+
+    #[test]
+    fn picks_hosts_by_extension() {
+        assert_eq!(
+            Host::from_path(Path::new("README.md")),
+            Some(Host::Markdown)
+        );
+        assert_eq!(Host::from_path(Path::new("as3.json")), Some(Host::Json));
+        assert_eq!(Host::from_path(Path::new("ci.yml")), Some(Host::Yaml));
+        assert_eq!(Host::from_path(Path::new("rule.tcl")), None);
+    }
+
+    #[test]
+    fn formats_markdown_tcl_fences() {
+        let doc = "# Rules\n\n```tcl\nset a  1\n```\n\n```sh\nset a  1\n```\n\n  ```tcl\n  if {$a} {\n  pool p\n  }\n  ```\n";
+        assert_eq!(
+            reformat(Host::Markdown, doc, &[]),
+            "# Rules\n\n```tcl\nset a 1\n```\n\n```sh\nset a  1\n```\n\n  ```tcl\n  if { $a } {\n      pool p\n  }\n  ```\n"
+        );
+        let regions = regions(Host::Markdown, doc.as_bytes(), &[]);
+        assert_eq!(regions.iter().map(|r| r.line).collect::<Vec<_>>(), [4, 12]);
+        assert!(regions.iter().all(|r| r.maps_lines()));
+    }
+
+    #[test]
+    fn formats_selected_json_strings() {
+        let doc = r#"{"a": {"r1": {"iRule": "set a  1\n"}, "r2": {"iRule": "set b  2", "other": "set c  3"}}}"#;
+        assert_eq!(
+            reformat(Host::Json, doc, &["a.*.iRule"]),
+            r#"{"a": {"r1": {"iRule": "set a 1\n"}, "r2": {"iRule": "set b 2", "other": "set c  3"}}}"#
+        );
+        assert_eq!(reformat(Host::Json, doc, &[]), doc);
+        assert!(!regions(Host::Json, doc.as_bytes(), &["**.iRule".to_string()])[0].maps_lines());
+    }
+
+    #[test]
+    fn formats_yaml_block_scalars() {
+        let doc = "rules:\n  - name: r1\n    script: |\n      set a  1\n\n      pool p\n  - name: r2\n    script: \"set b  2\"\n";
+        assert_eq!(
+            reformat(Host::Yaml, doc, &["rules.*.script"]),
+            "rules:\n  - name: r1\n    script: |\n      set a 1\n\n      pool p\n  - name: r2\n    script: \"set b  2\"\n"
+        );
+    }
+
+    #[test]
+    fn detects_tmsh_configuration() {
+        let rule = b"\nltm rule r1 {\nset a 1\n}\n";
+        assert_eq!(Host::detect(Path::new("bigip.conf"), b""), Some(Host::Tmsh));
+        assert_eq!(Host::detect(Path::new("rules.txt"), rule), Some(Host::Tmsh));
+        assert_eq!(Host::detect(Path::new("rule.tcl"), b"set a 1\n"), None);
+        assert_eq!(
+            Host::detect(Path::new("rule.tcl"), b"  ltm rule r1 {\n}\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn formats_ltm_rule_bodies() {
+        let doc = "ltm pool p {\n    members { }\n}\nltm rule r1 {\nwhen HTTP_REQUEST {\nif {$a} {\npool p\n}\n}\n}\nltm rule r2 {\n    set a  1\n    set b \"\\}\"\n}\n";
+        assert_eq!(
+            reformat(Host::Tmsh, doc, &[]),
+            "ltm pool p {\n    members { }\n}\nltm rule r1 {\nwhen HTTP_REQUEST {\n    if { $a } {\n        pool p\n    }\n}\n}\nltm rule r2 {\n    set a 1\n    set b \"\\}\"\n}\n"
+        );
+        let regions = regions(Host::Tmsh, doc.as_bytes(), &[]);
+        assert_eq!(regions.iter().map(|r| r.line).collect::<Vec<_>>(), [5, 12]);
+    }
+
+    #[test]
+    fn skips_unterminated_ltm_rules() {
+        let doc = "ltm rule r1 {\nset a  1\n";
+        assert!(regions(Host::Tmsh, doc.as_bytes(), &[]).is_empty());
+        // the body must end on a line of its own
+        let doc = "ltm rule r1 {\nset a  1 }\nltm rule r2 {\nset b  2\n}\n";
+        assert_eq!(
+            reformat(Host::Tmsh, doc, &[]),
+            "ltm rule r1 {\nset a  1 }\nltm rule r2 {\nset b 2\n}\n"
+        );
+    }
+
+    #[test]
+    fn matches_selectors() {
+        let path = |p: &str| p.split('.').map(str::to_string).collect::<Vec<_>>();
+        assert!(matches(&["a", "*", "c"], &path("a.b.c")));
+        assert!(!matches(&["a", "*", "c"], &path("a.b.b.c")));
+        assert!(matches(&["a", "**", "c"], &path("a.b.b.c")));
+        assert!(matches(&["**", "c"], &path("c")));
+        assert!(!matches(&["a"], &path("a.b")));
+    }
+
+    #[test]
+    fn unescapes_json_strings() {
+        assert_eq!(
+            json_unescape(r#""a\"\\\/\né\ud83d\ude80""#.as_bytes()).unwrap(),
+            "a\"\\/\né🚀".as_bytes()
+        );
+        assert_eq!(json_unescape(br#""\ud83d""#), None);
+        assert_eq!(json_unescape(br#""\x""#), None);
+    }
+}
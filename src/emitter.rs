@@ -0,0 +1,461 @@
+// The output side of the formatter: text goes in as words, spaces, line
+// endings and indentation, and the emitter keeps track of the column and
+// nesting depth. It is the one place that decides line endings and
+// indentation, strips trailing whitespace, and knows the width limit.
+use crate::{formatter::LineEnding, width::width};
+
+pub struct Emitter {
+    indent_width: usize,
+    use_tabs: bool,
+    line_ending: LineEnding,
+    max_width: Option<usize>,
+    final_newline: bool,
+    buf: Vec<u8>,
+    depth: usize,
+    column: usize, // of the end of `buf`, a tab of indentation counting as `indent_width`
+    lines: usize,  // line endings written so far
+    joined: bool,  // the next `indent` is skipped, after a `; `
+}
+
+// A position to `rewind` to, when a layout turns out not to fit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Checkpoint {
+    len: usize,
+    column: usize,
+    lines: usize,
+}
+
+impl Default for Emitter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Emitter {
+    pub fn new() -> Self {
+        Self {
+            indent_width: 4,
+            use_tabs: false,
+            line_ending: LineEnding::Lf,
+            max_width: None,
+            final_newline: true,
+            buf: Vec::new(),
+            depth: 0,
+            column: 0,
+            lines: 0,
+            joined: false,
+        }
+    }
+
+    // columns per nesting level, also what a tab of indentation counts as
+    pub fn indent_width(mut self, indent_width: usize) -> Self {
+        self.indent_width = indent_width;
+        self
+    }
+
+    pub fn use_tabs(mut self, use_tabs: bool) -> Self {
+        self.use_tabs = use_tabs;
+        self
+    }
+
+    pub fn line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    // the column lines should end at, see `fits`
+    pub fn max_width(mut self, max_width: Option<usize>) -> Self {
+        self.max_width = max_width;
+        self
+    }
+
+    // bytes to reserve for the output up front
+    pub fn capacity(mut self, bytes: usize) -> Self {
+        self.buf.reserve(bytes);
+        self
+    }
+
+    // whether `finish` keeps the line ending the output ends with
+    pub fn final_newline(mut self, final_newline: bool) -> Self {
+        self.final_newline = final_newline;
+        self
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        let line_ending = self.line_ending.as_bytes();
+        if !self.final_newline && self.buf.ends_with(line_ending) {
+            self.buf.truncate(self.buf.len() - line_ending.len());
+        }
+        self.buf
+    }
+
+    // Text as it is; after a line break in it, the column counts from there
+    pub fn word(&mut self, word: &[u8]) {
+        self.buf.extend_from_slice(word);
+        match word.iter().rposition(|&c| c == b'\n' || c == b'\r') {
+            Some(idx) => self.column = self.line_width(&word[idx + 1..]),
+            None => self.column += width(word),
+        }
+    }
+
+    pub fn space(&mut self) {
+        self.spaces(1);
+    }
+
+    pub fn spaces(&mut self, count: usize) {
+        self.buf.extend(std::iter::repeat_n(b' ', count));
+        self.column += count;
+    }
+
+    // every line ending goes through here, so no output line ends in
+    // whitespace, not even an indented empty line
+    pub fn newline(&mut self) {
+        let trailing = self
+            .buf
+            .iter()
+            .rev()
+            .take_while(|&&c| c == b' ' || c == b'\t')
+            .count();
+        self.buf.truncate(self.buf.len() - trailing);
+        self.buf.extend_from_slice(self.line_ending.as_bytes());
+        self.column = 0;
+        self.lines += 1;
+    }
+
+    // back onto the line just written
+    pub fn unwrite_line_ending(&mut self) {
+        let line_ending = self.line_ending.as_bytes();
+        if self.buf.ends_with(line_ending) {
+            self.buf.truncate(self.buf.len() - line_ending.len());
+            self.lines -= 1;
+            let start = self.line_start(self.buf.len());
+            self.column = self.line_width(&self.buf[start..]);
+        }
+    }
+
+    // the indentation of the current depth, unless `join` was called
+    pub fn indent(&mut self) {
+        if std::mem::take(&mut self.joined) {
+            return;
+        }
+        let columns = self.depth * self.indent_width;
+        match self.use_tabs {
+            true => self.buf.extend(std::iter::repeat_n(b'\t', self.depth)),
+            false => self.buf.extend(std::iter::repeat_n(b' ', columns)),
+        }
+        self.column += columns;
+    }
+
+    // the next command continues the current line instead of being indented
+    pub fn join(&mut self) {
+        self.joined = true;
+    }
+
+    // `{` ending the line, with what follows one level deeper
+    pub fn open_block(&mut self) {
+        self.word(b"{");
+        self.newline();
+        self.nest();
+    }
+
+    // back a level, and `}` on a line of its own
+    pub fn close_block(&mut self) {
+        self.unnest();
+        self.indent();
+        self.word(b"}");
+    }
+
+    pub fn nest(&mut self) {
+        self.depth += 1;
+    }
+
+    pub fn unnest(&mut self) {
+        self.depth -= 1;
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    pub fn lines(&self) -> usize {
+        self.lines
+    }
+
+    // bytes written so far
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn width_limit(&self) -> Option<usize> {
+        self.max_width
+    }
+
+    // whether `columns` more still end the line within `max_width`, always
+    // true without one
+    pub fn fits(&self, columns: usize) -> bool {
+        self.max_width
+            .is_none_or(|max_width| self.column + columns <= max_width)
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            len: self.buf.len(),
+            column: self.column,
+            lines: self.lines,
+        }
+    }
+
+    // drops what was written since `checkpoint`
+    pub fn rewind(&mut self, checkpoint: Checkpoint) {
+        self.buf.truncate(checkpoint.len);
+        self.column = checkpoint.column;
+        self.lines = checkpoint.lines;
+    }
+
+    // what was written so far
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    // `count` spaces at byte `offset`, for padding once everything is written
+    pub fn insert_spaces(&mut self, offset: usize, count: usize) {
+        self.buf
+            .splice(offset..offset, std::iter::repeat_n(b' ', count));
+        if offset >= self.line_start(self.buf.len()) {
+            self.column += count;
+        }
+    }
+
+    // the offset of the line `offset` is on
+    fn line_start(&self, offset: usize) -> usize {
+        let last = *self.line_ending.as_bytes().last().unwrap_or(&b'\n');
+        self.buf[..offset]
+            .iter()
+            .rposition(|&c| c == last)
+            .map_or(0, |idx| idx + 1)
+    }
+
+    // a tab of indentation counts as `indent_width`
+    fn line_width(&self, line: &[u8]) -> usize {
+        let tabs = line.iter().take_while(|&&c| c == b'\t').count();
+        tabs * self.indent_width + width(&line[tabs..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Emitter;
+    use crate::formatter::{Formatter, FormatterOptions, LineEnding};
+
+    // the line (or the line ending) in `output` that ends in whitespace
+    fn trailing_whitespace(output: &[u8]) -> Option<String> {
+        output
+            .split(|&c| c == b'\n' || c == b'\r')
+            .find(|line| matches!(line.last(), Some(b' ' | b'\t')))
+            .map(|line| String::from_utf8_lossy(line).into_owned())
+    }
+
+    #[test]
+    fn newline_strips_trailing_whitespace() {
+        for line_ending in [LineEnding::Lf, LineEnding::CrLf, LineEnding::Cr] {
+            let mut out = Emitter::new().use_tabs(true).line_ending(line_ending);
+            out.nest();
+            out.indent();
+            out.newline();
+            out.indent();
+            out.word(b"set a 1");
+            out.spaces(3);
+            out.newline();
+            out.word(b"x \t");
+            out.newline();
+            let ending = String::from_utf8(line_ending.as_bytes().to_vec()).unwrap();
+            let expected = format!("{ending}\tset a 1{ending}x{ending}");
+            assert_eq!(out.finish(), expected.as_bytes());
+        }
+    }
+
+    #[test]
+    fn tracks_the_column() {
+        let mut out = Emitter::new().use_tabs(true).indent_width(2);
+        out.nest();
+        out.nest();
+        out.indent();
+        assert_eq!(out.column(), 4);
+        out.word("set é".as_bytes());
+        out.space();
+        assert_eq!(out.column(), 10);
+        out.word(b"{a\n\tbc}");
+        assert_eq!((out.column(), out.lines()), (5, 0));
+        out.newline();
+        assert_eq!((out.column(), out.lines()), (0, 1));
+        out.unwrite_line_ending();
+        assert_eq!((out.column(), out.lines()), (5, 0));
+    }
+
+    #[test]
+    fn lays_out_blocks() {
+        let mut out = Emitter::new().indent_width(2);
+        out.word(b"if {$a}");
+        out.space();
+        out.open_block();
+        out.indent();
+        out.word(b"set b 1;");
+        out.space();
+        out.join();
+        out.indent();
+        out.word(b"set c 2");
+        out.newline();
+        out.close_block();
+        out.newline();
+        assert_eq!(out.depth(), 0);
+        assert_eq!(out.finish(), b"if {$a} {\n  set b 1; set c 2\n}\n");
+    }
+
+    #[test]
+    fn rewinds_layouts_that_do_not_fit() {
+        let mut out = Emitter::new().max_width(Some(10));
+        out.word(b"set a");
+        let checkpoint = out.checkpoint();
+        assert!(out.fits(5) && !out.fits(6));
+        out.word(b" [list x y]");
+        assert!(!out.fits(0));
+        out.rewind(checkpoint);
+        assert_eq!(out.checkpoint(), checkpoint);
+        assert_eq!(out.as_bytes(), b"set a");
+        assert!(Emitter::new().fits(usize::MAX));
+    }
+
+    #[test]
+    fn inserts_padding_on_the_current_line() {
+        let mut out = Emitter::new();
+        out.word(b"set a 1");
+        out.newline();
+        out.word(b"set b 2");
+        out.insert_spaces(3, 1);
+        assert_eq!(out.column(), 7);
+        out.insert_spaces(out.len() - 2, 2);
+        assert_eq!(out.column(), 9);
+        assert_eq!(out.as_bytes(), b"set  a 1\nset b   2");
+    }
+
+    #[test]
+    fn finish_drops_the_final_line_ending_when_asked() {
+        let mut out = Emitter::new()
+            .line_ending(LineEnding::CrLf)
+            .final_newline(false);
+        out.word(b"set a 1");
+        out.newline();
+        assert_eq!(out.finish(), b"set a 1");
+        let mut out = Emitter::new().line_ending(LineEnding::CrLf);
+        out.word(b"set a 1");
+        out.newline();
+        assert_eq!(out.finish(), b"set a 1\r\n");
+    }
+
+    // a small deterministic generator, as the crate has no dependencies
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    #[test]
+    fn no_emitted_line_ends_in_whitespace() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for case in 0..200 {
+            let line_ending = [LineEnding::Lf, LineEnding::CrLf, LineEnding::Cr][case % 3];
+            let mut out = Emitter::new()
+                .use_tabs(case % 2 == 0)
+                .line_ending(line_ending);
+            for _ in 0..50 {
+                match rng.below(8) {
+                    0 => out.word(b"word"),
+                    1 => out.word(b"a\t "),
+                    2 => out.spaces(rng.below(4)),
+                    3 => out.indent(),
+                    4 => out.nest(),
+                    5 if out.depth() > 0 => out.unnest(),
+                    6 => out.join(),
+                    _ => out.newline(),
+                }
+            }
+            out.newline();
+            let output = out.finish();
+            if let Some(line) = trailing_whitespace(&output) {
+                panic!("case {case}: {line:?} ends in whitespace");
+            }
+        }
+    }
+
+    fn script(rng: &mut Rng, depth: usize, out: &mut String) {
+        for _ in 0..1 + rng.below(4) {
+            let pad = ["", "  ", "\t", " \t "][rng.below(4)];
+            out.push_str(pad);
+            match rng.below(if depth < 3 { 9 } else { 4 }) {
+                0 => out.push_str("set a 1"),
+                1 => out.push_str("set b 2   ;#  trailing   "),
+                2 => out.push_str("# comment  \t"),
+                3 => out.push_str("   "),
+                4 => out.push_str("proc p {x} {}"),
+                5 | 6 => {
+                    let header = rng.below(3);
+                    out.push_str(["if {$a} {", "when HTTP_REQUEST {", "proc p {x y} {"][header]);
+                    out.push_str(["\n", "  \n", " "][rng.below(3)]);
+                    script(rng, depth + 1, out);
+                    out.push_str("}  ");
+                    if header == 0 && rng.below(2) == 0 {
+                        out.push_str(" else { }");
+                    }
+                }
+                7 => {
+                    out.push_str("switch $a {\n  x {\n");
+                    script(rng, depth + 1, out);
+                    out.push_str("}\n  default {}  \n}");
+                }
+                _ => out.push_str("set c [list a b]; set d 4 ;# after  "),
+            }
+            out.push_str(["\n", "\n\n", " \n  \n"][rng.below(3)]);
+        }
+    }
+
+    #[test]
+    fn no_output_line_ends_in_whitespace() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        let mut formatted = 0;
+        for case in 0..400 {
+            let mut source = String::new();
+            script(&mut rng, 0, &mut source);
+            let options = FormatterOptions {
+                use_tabs: case % 2 == 1,
+                line_ending: [LineEnding::Lf, LineEnding::CrLf, LineEnding::Cr][case / 2 % 3],
+                compact_empty_blocks: case / 6 % 2 == 1,
+                align_trailing_comments: [None, Some(40)][case / 12 % 2],
+                final_newline: case / 24 % 2 == 0,
+                one_line_bodies: case / 48 % 2 == 1,
+                max_width: Some(20),
+                ..FormatterOptions::default()
+            };
+            let Ok(output) = Formatter::with_options(options).try_format(source.as_bytes()) else {
+                continue;
+            };
+            formatted += 1;
+            if let Some(line) = trailing_whitespace(&output) {
+                panic!("case {case}: {line:?} ends in whitespace, formatting {source:?}");
+            }
+        }
+        assert!(formatted > 300, "only {formatted} cases parsed");
+    }
+}
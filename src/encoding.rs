@@ -0,0 +1,112 @@
+// Input encodings. The lexer works on UTF-8, so a byte order mark is split
+// off before lexing and Latin-1 input is transcoded, then the formatted text
+// is converted back so files keep the encoding they were written in.
+use std::borrow::Cow;
+
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Latin1,
+    Auto, // UTF-8 if the input is valid UTF-8, Latin-1 otherwise
+}
+
+impl Encoding {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "utf-8" => Some(Encoding::Utf8),
+            "latin-1" => Some(Encoding::Latin1),
+            "auto" => Some(Encoding::Auto),
+            _ => None,
+        }
+    }
+}
+
+// Input text as UTF-8, with what is needed to write it back the same way
+pub struct Decoded<'a> {
+    pub text: Cow<'a, [u8]>,
+    pub bom: bool,    // the input started with a UTF-8 byte order mark
+    pub latin1: bool, // `text` was transcoded from Latin-1
+}
+
+pub fn decode(input: &[u8], encoding: Encoding) -> Decoded<'_> {
+    let (bom, input) = match input.strip_prefix(BOM) {
+        Some(rest) => (true, rest),
+        None => (false, input),
+    };
+    let latin1 = match encoding {
+        Encoding::Utf8 => false,
+        Encoding::Latin1 => true,
+        Encoding::Auto => !bom && std::str::from_utf8(input).is_err(),
+    };
+    let text = if latin1 && !input.is_ascii() {
+        // every byte is the code point of the same value
+        Cow::Owned(
+            input
+                .iter()
+                .map(|&b| b as char)
+                .collect::<String>()
+                .into_bytes(),
+        )
+    } else {
+        Cow::Borrowed(input)
+    };
+    Decoded { text, bom, latin1 }
+}
+
+impl Decoded<'_> {
+    // `text` in the encoding of the input, starting with a byte order mark if
+    // the input had one and `keep_bom` is set
+    pub fn encode(&self, text: &[u8], keep_bom: bool) -> Vec<u8> {
+        let mut out = Vec::with_capacity(text.len() + BOM.len());
+        if self.bom && keep_bom {
+            out.extend_from_slice(BOM);
+        }
+        if self.latin1 && !text.is_ascii() {
+            // characters outside Latin-1 can't have come from the input
+            let text = String::from_utf8_lossy(text);
+            out.extend(text.chars().map(|c| u8::try_from(c).unwrap_or(b'?')));
+        } else {
+            out.extend_from_slice(text);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::{decode, Encoding};
+
+    #[test]
+    fn strips_and_restores_the_bom() {
+        let input = b"\xEF\xBB\xBFset a 1\n";
+        let decoded = decode(input, Encoding::Utf8);
+        assert!(decoded.bom && !decoded.latin1);
+        assert!(matches!(decoded.text, Cow::Borrowed(b"set a 1\n")));
+        assert_eq!(decoded.encode(b"set a 1\n", true), input);
+        assert_eq!(decoded.encode(b"set a 1\n", false), b"set a 1\n");
+    }
+
+    #[test]
+    fn round_trips_latin1() {
+        let input = b"set caf\xe9 \xa31\n";
+        let decoded = decode(input, Encoding::Latin1);
+        assert_eq!(&*decoded.text, "set café £1\n".as_bytes());
+        assert_eq!(decoded.encode(&decoded.text, true), input);
+        // characters Latin-1 can't hold
+        assert_eq!(decoded.encode("set a 日\n".as_bytes(), true), b"set a ?\n");
+    }
+
+    #[test]
+    fn detects_the_encoding() {
+        assert!(!decode("set café 1\n".as_bytes(), Encoding::Auto).latin1);
+        assert!(decode(b"set caf\xe9 1\n", Encoding::Auto).latin1);
+        // a BOM says UTF-8, whatever follows
+        assert!(!decode(b"\xEF\xBB\xBFcaf\xe9", Encoding::Auto).latin1);
+        assert_eq!(Encoding::from_name("latin-1"), Some(Encoding::Latin1));
+        assert_eq!(Encoding::from_name("latin1"), None);
+    }
+}
@@ -0,0 +1,71 @@
+// Output adapters wrapping a formatted iRule for deployment: a `tmsh` command
+// to paste into a BIG-IP shell, or an AS3 declaration fragment.
+use crate::json;
+
+// An iRule name from a file name: characters tmsh doesn't accept in object
+// names become `_`
+pub fn rule_name(stem: &str) -> String {
+    stem.chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.') => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+// `tmsh create ltm rule <name> '{ ... }'`, quoted for a POSIX shell. tmsh
+// reads `?` as a request for help anywhere on the line, so it is escaped.
+pub fn tmsh(name: &str, rule: &[u8]) -> Vec<u8> {
+    let mut out = format!("tmsh create ltm rule {name} '{{\n").into_bytes();
+    for &c in rule {
+        match c {
+            b'?' => out.extend_from_slice(b"\\?"),
+            b'\'' => out.extend_from_slice(b"'\\''"),
+            c => out.push(c),
+        }
+    }
+    if !out.ends_with(b"\n") {
+        out.push(b'\n');
+    }
+    out.extend_from_slice(b"}'\n");
+    out
+}
+
+// `{"<name>": {"class": "iRule", "iRule": "..."}}`, to merge into the
+// tenant's application in an AS3 declaration
+pub fn as3(name: &str, rule: &[u8]) -> String {
+    format!(
+        "{{\n  {}: {{\n    \"class\": \"iRule\",\n    \"iRule\": {}\n  }}\n}}\n",
+        json::string(name),
+        json::string(&String::from_utf8_lossy(rule)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{as3, rule_name, tmsh};
+
+    #[test]
+    fn names_rules_after_files() {
+        assert_eq!(rule_name("redirect-http_v1.2"), "redirect-http_v1.2");
+        assert_eq!(rule_name("my rule (old)"), "my_rule__old_");
+        assert_eq!(rule_name("größe"), "gr__e");
+    }
+
+    #[test]
+    fn quotes_tmsh_commands_for_the_shell() {
+        let rule = b"when HTTP_REQUEST {\n    log local0. \"it's [HTTP::uri]?\"\n}";
+        assert_eq!(
+            String::from_utf8(tmsh("r", rule)).unwrap(),
+            "tmsh create ltm rule r '{\nwhen HTTP_REQUEST {\n    log local0. \"it'\\''s [HTTP::uri]\\?\"\n}\n}'\n"
+        );
+    }
+
+    #[test]
+    fn embeds_rules_in_as3_declarations() {
+        assert_eq!(
+            as3("r", b"when X {\n\tpool \"p\"\n}\n"),
+            "{\n  \"r\": {\n    \"class\": \"iRule\",\n    \"iRule\": \"when X {\\n\\tpool \\\"p\\\"\\n}\\n\"\n  }\n}\n"
+        );
+    }
+}
@@ -0,0 +1,113 @@
+// Machine-applicable fixes carried by diagnostics. A fix either replaces a
+// range of the source or names a pass that rewrites the AST. Rules only
+// attach fixes that keep the meaning of the script.
+use std::ops::Range;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fix {
+    Replace {
+        range: Range<usize>, // bytes of the source, as in the lexer's spans
+        replacement: Vec<u8>,
+    },
+    Pass(&'static str), // a transformation pass, see `passes::run`
+}
+
+impl Fix {
+    pub fn is_edit(&self) -> bool {
+        matches!(self, Fix::Replace { .. })
+    }
+}
+
+// `source` with the replacements of `fixes` made. Of overlapping ones only the
+// first starting is made, the others are left for another run.
+pub fn apply_edits(source: &[u8], fixes: &[Fix]) -> Vec<u8> {
+    let mut edits: Vec<(&Range<usize>, &[u8])> = fixes
+        .iter()
+        .filter_map(|fix| match fix {
+            Fix::Replace { range, replacement } => Some((range, &replacement[..])),
+            Fix::Pass(_) => None,
+        })
+        .filter(|(range, _)| range.start <= range.end && range.end <= source.len())
+        .collect();
+    edits.sort_by_key(|(range, _)| (range.start, range.end));
+    let mut fixed = Vec::with_capacity(source.len());
+    let mut done = 0;
+    for (range, replacement) in edits {
+        if range.start < done {
+            continue;
+        }
+        fixed.extend_from_slice(&source[done..range.start]);
+        fixed.extend_from_slice(replacement);
+        done = range.end;
+    }
+    fixed.extend_from_slice(&source[done..]);
+    fixed
+}
+
+// `configured` followed by the passes `fixes` name, each once
+pub fn passes(configured: &[String], fixes: &[Fix]) -> Vec<String> {
+    let mut passes = configured.to_vec();
+    for fix in fixes {
+        if let Fix::Pass(name) = fix {
+            if !passes.iter().any(|pass| pass == name) {
+                passes.push(name.to_string());
+            }
+        }
+    }
+    passes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_edits, passes, Fix};
+
+    fn replace(range: std::ops::Range<usize>, replacement: &str) -> Fix {
+        Fix::Replace {
+            range,
+            replacement: replacement.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn applies_edits_in_source_order() {
+        let fixes = [
+            replace(6..7, "2"),
+            Fix::Pass("brace-conditions"),
+            replace(4..5, "b"),
+            replace(0..0, "# x\n"),
+        ];
+        assert_eq!(apply_edits(b"set a 1\n", &fixes), b"# x\nset b 2\n");
+        assert_eq!(apply_edits(b"set a 1\n", &[]), b"set a 1\n");
+    }
+
+    #[test]
+    fn skips_overlapping_and_out_of_range_edits() {
+        let fixes = [
+            replace(4..7, "x"),
+            replace(5..7, "y"),
+            replace(4..6, "z"),
+            replace(7..20, "w"),
+        ];
+        // `4..6` starts first and ends first, the others overlap it
+        assert_eq!(apply_edits(b"set a 1\n", &fixes), b"set z1\n");
+    }
+
+    #[test]
+    fn adds_fix_passes_after_the_configured_ones() {
+        let configured = [
+            "collapse-elseif".to_string(),
+            "brace-conditions".to_string(),
+        ];
+        let fixes = [
+            Fix::Pass("brace-conditions"),
+            replace(0..1, "x"),
+            Fix::Pass("brace-literals"),
+            Fix::Pass("brace-literals"),
+        ];
+        assert_eq!(
+            passes(&configured, &fixes),
+            ["collapse-elseif", "brace-conditions", "brace-literals"]
+        );
+        assert!(replace(0..1, "x").is_edit() && !Fix::Pass("brace-literals").is_edit());
+    }
+}
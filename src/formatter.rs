@@ -1,25 +1,208 @@
-use std::iter;
+use crate::{
+    ast::{split_words, word_len, Ast, Statement, Text},
+    comments::leading_comments,
+    config::{Config, ConfigFail},
+    directive,
+    emitter::Emitter,
+    keep_sorted::keep_sorted,
+    lexer::{Lexer, LexerFail},
+    parser::{ParseError, Parser},
+    passes::normalize_expression,
+    width::width,
+};
 
-use crate::ast::{Ast, Statement};
+#[derive(Debug)]
+pub enum FormatFail {
+    Lex(LexerFail),
+    Parse(ParseError),
+    NotOneCommand(usize), // a `SnippetKind::Statement` snippet with this many commands
+    Directive(ConfigFail), // an invalid or misplaced `# tclfmt:` directive
+}
+type Result<T> = std::result::Result<T, FormatFail>;
+
+// What a snippet given to `Formatter::format_snippet` holds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SnippetKind {
+    Statement,
+    Expression,
+    Block,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl LineEnding {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "lf" => Some(LineEnding::Lf),
+            "crlf" => Some(LineEnding::CrLf),
+            "cr" => Some(LineEnding::Cr),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(self) -> &'static [u8] {
+        match self {
+            LineEnding::Lf => b"\n",
+            LineEnding::CrLf => b"\r\n",
+            LineEnding::Cr => b"\r",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatterOptions {
+    pub indent_width: usize,    // spaces per nesting level
+    pub use_tabs: bool,         // one tab per nesting level instead
+    pub cuddle_else: bool,      // `} else {` rather than `else {` on its own line
+    pub pad_braces: bool,       // `if { $x }` rather than `if {$x}`
+    pub max_empty_lines: usize, // consecutive empty lines kept
+    pub line_ending: LineEnding,
+    pub final_newline: bool, // end the output with a line ending
+    // column `;#` comments after statements start at, in runs of adjacent lines
+    pub align_trailing_comments: Option<usize>,
+    pub align_switch_arms: bool, // pad switch arm patterns so the bodies line up
+    // exactly one empty line between adjacent top-level procs and `when` blocks
+    pub blank_line_between_definitions: bool,
+    pub compact_empty_blocks: bool, // `proc noop {} {}` rather than a `{` line and a `}` line
+    // columns past which braced list arguments are laid out one element per line
+    pub max_width: Option<usize>,
+    // `if { $x } { pool p1 }` for bodies of one statement that fit in `max_width`
+    pub one_line_bodies: bool,
+    // `set a 1; set b 2` kept on one line rather than split into two
+    pub keep_semicolons: bool,
+}
+
+impl Default for FormatterOptions {
+    fn default() -> Self {
+        Self {
+            indent_width: 4,
+            use_tabs: false,
+            cuddle_else: false,
+            pad_braces: true,
+            max_empty_lines: 2,
+            line_ending: LineEnding::Lf,
+            final_newline: true,
+            align_trailing_comments: None,
+            align_switch_arms: false,
+            blank_line_between_definitions: false,
+            compact_empty_blocks: false,
+            max_width: None,
+            one_line_bodies: false,
+            keep_semicolons: false,
+        }
+    }
+}
+
+impl FormatterOptions {
+    pub const PRESETS: &[&str] = &["f5-irule", "tcl-std"];
+
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            // the layout of F5 documentation and DevCentral iRules
+            "f5-irule" => Some(Self {
+                indent_width: 4,
+                use_tabs: false,
+                cuddle_else: true,
+                pad_braces: true,
+                max_empty_lines: 1,
+                line_ending: LineEnding::Lf,
+                final_newline: true,
+                align_trailing_comments: None,
+                align_switch_arms: false,
+                blank_line_between_definitions: false,
+                compact_empty_blocks: false,
+                max_width: None,
+                one_line_bodies: false,
+                keep_semicolons: false,
+            }),
+            // the Tcl Style Guide
+            "tcl-std" => Some(Self {
+                indent_width: 4,
+                use_tabs: false,
+                cuddle_else: true,
+                pad_braces: false,
+                max_empty_lines: 1,
+                line_ending: LineEnding::Lf,
+                final_newline: true,
+                align_trailing_comments: None,
+                align_switch_arms: false,
+                blank_line_between_definitions: false,
+                compact_empty_blocks: false,
+                max_width: None,
+                one_line_bodies: false,
+                keep_semicolons: false,
+            }),
+            _ => None,
+        }
+    }
+}
 
 pub struct Formatter {
-    depth: usize,
+    options: FormatterOptions,
+    out: Emitter,
     consecutive_empty_lines: usize,
-    buf: Vec<u8>,
+    trailing: Vec<(usize, usize)>, // (line, offset of `;#`) of each trailing comment
+}
+
+impl Default for Formatter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Formatter {
     pub fn new() -> Self {
+        Self::with_options(FormatterOptions::default())
+    }
+
+    pub fn with_options(options: FormatterOptions) -> Self {
+        let out = Emitter::new()
+            .indent_width(options.indent_width)
+            .use_tabs(options.use_tabs)
+            .line_ending(options.line_ending)
+            .max_width(options.max_width)
+            .final_newline(options.final_newline);
         Self {
-            depth: 0,
+            options,
+            out,
             consecutive_empty_lines: 0,
-            buf: Vec::new(),
+            trailing: Vec::new(),
         }
     }
 
-    pub fn format(mut self, ast: Ast) -> Vec<u8> {
+    // the length of the source being formatted, to size the output from;
+    // indentation usually makes it a little longer
+    pub fn size_hint(mut self, source_len: usize) -> Self {
+        self.out = std::mem::take(&mut self.out).capacity(source_len + source_len / 8);
+        self
+    }
+
+    // Lexes, parses and formats `source`. Unlike the steps on their own, this
+    // is guaranteed to return rather than panic on any input, which the fuzz
+    // target in `fuzz/` checks.
+    pub fn try_format(self, source: &[u8]) -> Result<Vec<u8>> {
+        let (tokens, spans) = Lexer::new()
+            .lex_with_spans(source)
+            .map_err(FormatFail::Lex)?;
+        let ast = Parser::new(source, &tokens, &spans)
+            .parse()
+            .map_err(FormatFail::Parse)?;
+        directive::configure(&mut Config::default(), source).map_err(FormatFail::Directive)?;
+        Ok(self.size_hint(source.len()).format(ast))
+    }
+
+    pub fn format(mut self, mut ast: Ast) -> Vec<u8> {
+        keep_sorted(&mut ast);
         self.run(ast);
-        self.buf
+        if let Some(column) = self.options.align_trailing_comments {
+            self.align_trailing_comments(column);
+        }
+        self.out.finish()
     }
 
     fn run(&mut self, ast: Ast) {
@@ -29,165 +212,1029 @@ impl Formatter {
         };
 
         match ast {
-            Ast::Block(trees) => {
+            Ast::Block(mut trees) => {
+                if self.out.depth() == 0 && self.options.blank_line_between_definitions {
+                    separate_definitions(&mut trees);
+                }
+                keep_semicolons(&mut trees, self.options.keep_semicolons);
+                // directives in the block end with it
+                let options = self.options.clone();
                 for tree in trees {
                     self.run(tree);
                 }
+                if self.options != options {
+                    self.options = options;
+                    self.configure_emitter();
+                }
             }
             Ast::Comment(data) => {
-                self.indent();
-                self.write(b"# ");
-                self.write(&data);
-                self.newline();
+                // invalid ones are reported by `directive::configure`, before formatting
+                if let Some(entries) = directive::entries(&data, 0) {
+                    let _ = directive::apply(&mut self.options, &entries);
+                    self.configure_emitter();
+                }
+                self.out.indent();
+                self.out.word(b"#");
+                self.out.space();
+                self.out.word(&data);
+                self.out.newline();
+            }
+            Ast::Verbatim(line) => {
+                self.out.indent();
+                self.out.word(&line);
+                self.out.newline();
+            }
+            Ast::Semicolon => {
+                self.out.unwrite_line_ending();
+                self.out.word(b";");
+                self.out.space();
+                self.out.join();
+            }
+            Ast::TrailingComment(data) => {
+                // back onto the line just written
+                self.out.unwrite_line_ending();
+                self.out.space();
+                self.trailing.push((self.out.lines(), self.out.len()));
+                self.out.word(b";#");
+                self.out.space();
+                self.out.word(&data);
+                self.out.newline();
             }
             Ast::Procedure {
                 name,
                 parameters,
                 body,
             } => {
-                self.indent();
-                self.write(b"proc ");
-                self.write(&name);
-                self.write(b" {");
-                for p in parameters {
-                    self.write(b" ");
-                    self.write(&p);
-                }
-                self.writeline(b" } {");
-                self.run_nested(*body);
-                self.close_block();
+                self.out.indent();
+                self.out.word(b"proc");
+                self.out.space();
+                self.out.word(&name);
+                self.out.space();
+                self.write_parameters(parameters);
+                self.write_short_body(*body);
+                self.out.newline();
             }
             Ast::If {
                 condition_body_clauses: condition_block_vec,
                 maybe_block_if_false,
             } => {
+                let cuddle = self.options.cuddle_else;
+                let single = condition_block_vec.len() == 1 && maybe_block_if_false.is_none();
                 for (idx, (condition, block)) in condition_block_vec.into_iter().enumerate() {
-                    self.indent();
-                    if idx == 0 {
-                        self.write(b"if { ");
+                    match idx {
+                        0 => self.out.indent(),
+                        _ if cuddle => self.out.space(),
+                        _ => self.out.indent(),
+                    }
+                    self.out.word(if idx == 0 { b"if" } else { b"elseif" });
+                    self.out.space();
+                    if condition.braced {
+                        self.write_braced(&condition);
+                    } else {
+                        self.out.word(&condition);
+                    }
+                    if single {
+                        self.write_short_body(block);
                     } else {
-                        self.write(b"elseif { ");
+                        self.write_body(block);
+                    }
+                    if !cuddle {
+                        self.out.newline();
                     }
-                    self.write(&condition);
-                    self.writeline(b" } {");
-                    self.run_nested(block);
-                    self.close_block();
                 }
                 if let Some(block_if_false) = maybe_block_if_false {
-                    self.indent();
-                    self.writeline(b"else {");
-                    self.run_nested(*block_if_false);
-                    self.close_block();
+                    if cuddle {
+                        self.out.space();
+                    } else {
+                        self.out.indent();
+                    }
+                    self.out.word(b"else");
+                    self.write_body(*block_if_false);
+                    if !cuddle {
+                        self.out.newline();
+                    }
+                }
+                if cuddle {
+                    self.out.newline();
                 }
             }
             Ast::Switch {
                 condition,
                 value_block_or_fallthrough_vec,
             } => {
-                // TODO: sort conditions of fallthrough blocks
-                self.indent();
-                self.write(b"switch ");
-                self.write(&condition);
-                self.writeline(b" {");
+                self.out.indent();
+                self.out.word(b"switch");
+                self.out.space();
+                self.out.word(&condition);
+                self.out.space();
+                self.out.open_block();
 
-                self.depth += 1;
+                let value_width = match self.options.align_switch_arms {
+                    true => value_block_or_fallthrough_vec
+                        .iter()
+                        .map(|(value, _)| width(value))
+                        .max()
+                        .unwrap_or(0),
+                    false => 0,
+                };
                 for (value, block_or_fallthrough) in value_block_or_fallthrough_vec {
-                    self.indent();
-                    self.write(&value);
+                    self.out.indent();
+                    self.out.word(&value);
+                    self.out.spaces(value_width.saturating_sub(width(&value)));
                     match block_or_fallthrough {
-                        Some(block) => {
-                            self.writeline(b" {");
-                            self.run_nested(block);
-                            self.close_block();
-                        }
+                        Some(block) => self.write_short_body(block),
                         None => {
-                            self.writeline(b" -");
+                            self.out.space();
+                            self.out.word(b"-");
                         }
                     }
+                    self.out.newline();
                 }
-                self.depth -= 1;
-                self.close_block();
+                self.out.close_block();
+                self.out.newline();
+            }
+            Ast::Script {
+                command,
+                arguments,
+                body,
+            } => {
+                self.out.indent();
+                self.out.word(&command);
+                for argument in arguments {
+                    self.out.space();
+                    self.out.word(&argument);
+                }
+                self.write_short_body(*body);
+                self.out.newline();
             }
             Ast::Statement(s) => {
-                self.indent();
+                self.out.indent();
                 self.write_statement(s);
             }
             Ast::EmptyLine => {
-                if self.consecutive_empty_lines <= 2 {
-                    self.newline();
+                if self.consecutive_empty_lines <= self.options.max_empty_lines {
+                    self.out.newline();
                 }
-            },
+            }
             Ast::When { event_name, body } => {
-                self.indent();
-                self.write(b"when ");
-                self.write(&event_name);
-                self.writeline(b" {");
-                self.run_nested(*body);
-                self.close_block();
+                self.out.indent();
+                self.out.word(b"when");
+                self.out.space();
+                self.out.word(&event_name);
+                self.write_body(*body);
+                self.out.newline();
             }
         }
     }
 
-    fn run_nested(&mut self, ast: Ast) {
-        self.depth += 1;
-        self.run(ast);
-        self.depth -= 1;
+    // Pads the code before each trailing comment so that the comments of
+    // adjacent lines start at the same column: `column`, or one past the
+    // longest line of the run if that is further right
+    fn align_trailing_comments(&mut self, column: usize) {
+        let line_ending = self.options.line_ending.as_bytes();
+        let last = line_ending[line_ending.len() - 1];
+        let buf = self.out.as_bytes();
+        let widths = self
+            .trailing
+            .iter()
+            .map(|&(_, offset)| {
+                let start = buf[..offset]
+                    .iter()
+                    .rposition(|&c| c == last)
+                    .map_or(0, |idx| idx + 1);
+                width(&buf[start..offset])
+            })
+            .collect::<Vec<_>>();
+
+        let mut padding = Vec::with_capacity(self.trailing.len());
+        let mut run_start = 0;
+        for idx in 0..=self.trailing.len() {
+            let run_ends = idx == self.trailing.len()
+                || (idx > 0 && self.trailing[idx].0 != self.trailing[idx - 1].0 + 1);
+            if !run_ends || idx == 0 {
+                continue;
+            }
+            let run = &widths[run_start..idx];
+            let target = run.iter().copied().max().unwrap_or(0).max(column - 1);
+            padding.extend(run.iter().map(|w| target - w));
+            run_start = idx;
+        }
+
+        // back to front, so earlier offsets stay valid
+        for (&(_, offset), pad) in self.trailing.iter().zip(padding).rev() {
+            self.out.insert_spaces(offset, pad);
+        }
     }
 
-    fn write_statement(&mut self, s: Statement) {
-        let (keyword, v1, v2) = match s {
-            Statement::Set { identifier, value } => (b"set".to_vec(), Some(identifier), Some(value)),
-            Statement::Log { bucket, value } => (b"log".to_vec(), Some(bucket), Some(value)),
-            Statement::Snat { ip_address, port } => (b"snat".to_vec(), Some(ip_address), Some(port)),
-            Statement::Node { ip_address, port } => (b"node".to_vec(), Some(ip_address), Some(port)),
-            Statement::Pool { identifier } => (b"pool".to_vec(), Some(identifier), None),
-            Statement::SnatPool { identifier } => (b"snatpool".to_vec(), Some(identifier), None),
-            Statement::Return { value } => (b"return".to_vec(), value, None),
-            Statement::Other { data } => (data, None, None),
+    // the options the emitter keeps, changed by a directive or restored at
+    // the end of its block
+    fn configure_emitter(&mut self) {
+        self.out = std::mem::take(&mut self.out)
+            .indent_width(self.options.indent_width)
+            .use_tabs(self.options.use_tabs)
+            .max_width(self.options.max_width);
+    }
+
+    // ` {`, the nested body and the closing `}`, or ` {}` for a body with
+    // nothing in it if configured
+    fn write_body(&mut self, body: Ast) {
+        let empty = match &body {
+            Ast::Block(trees) => trees.iter().all(|tree| matches!(tree, Ast::EmptyLine)),
+            tree => matches!(tree, Ast::EmptyLine),
+        };
+        self.out.space();
+        if empty && self.options.compact_empty_blocks {
+            self.out.word(b"{}");
+            return;
+        }
+        self.out.open_block();
+        self.run(body);
+        self.out.close_block();
+    }
+
+    // `{ a b c }`, or one parameter per backslash-continued line, aligned
+    // under the first, when the signature would pass `max_width`
+    fn write_parameters(&mut self, parameters: Vec<Text>) {
+        let (open, close): (&[u8], &[u8]) = match self.options.pad_braces {
+            true => (b"{ ", b" }"),
+            false => (b"{", b"}"),
         };
-        self.write(&keyword);
-        match (v1, v2) {
-            (Some(v1), Some(v2)) => {
-                self.write(b" ");
-                self.write(&v1);
-                self.write(b" ");
-                self.write(&v2);
+        if parameters.is_empty() {
+            match self.options.compact_empty_blocks {
+                true => self.out.word(b"{}"),
+                false => {
+                    self.out.word(b"{");
+                    self.out.word(close);
+                }
+            }
+            return;
+        }
+        let checkpoint = self.out.checkpoint();
+        self.out.word(open);
+        let column = self.out.column();
+        self.out.word(&parameters.join(&b' '));
+        self.out.word(close);
+        // the signature ends with the ` {` opening the body
+        if self.out.fits(2) || parameters.len() < 2 {
+            return;
+        }
+        self.out.rewind(checkpoint);
+        self.out.word(open);
+        for (idx, parameter) in parameters.into_iter().enumerate() {
+            if idx > 0 {
+                self.out.space();
+                self.out.word(b"\\");
+                self.out.newline();
+                self.out.indent();
+                self.out.spaces(column - self.out.column());
+            }
+            self.out.word(&parameter);
+        }
+        self.out.word(close);
+    }
+
+    // ` { statement }` if configured, the body is one statement and the line
+    // still fits; `write_body` otherwise. The short form is written first
+    // and taken back if it turns out too wide.
+    fn write_short_body(&mut self, body: Ast) {
+        if let (true, Some(statement)) = (self.options.one_line_bodies, single_statement(&body)) {
+            let line = statement.words().join(&b' ');
+            let checkpoint = self.out.checkpoint();
+            self.out.word(b" { ");
+            self.out.word(&line);
+            self.out.word(b" }");
+            let max_width = self.out.width_limit().unwrap_or(DEFAULT_MAX_WIDTH);
+            if self.out.column() <= max_width {
+                return;
+            }
+            self.out.rewind(checkpoint);
+        }
+        self.write_body(body);
+    }
+
+    fn write_statement(&mut self, s: Statement) {
+        let words = s.words();
+        // most statements take no list arguments, and go out word by word
+        if !may_take_lists(&words) {
+            for (idx, word) in words.into_iter().enumerate() {
+                if idx > 0 {
+                    self.out.space();
+                }
+                self.out.word(word);
+            }
+            self.out.newline();
+            return;
+        }
+        let line = words.join(&b' ');
+        match list_layout(&line, !self.out.fits(width(&line))) {
+            Some(words) => self.write_lists(words),
+            None => self.out.word(&line),
+        }
+        self.out.newline();
+    }
+
+    // words separated by spaces, each list as a `{` line, an indented line per
+    // element and a `}` in line with the command
+    fn write_lists(&mut self, words: Vec<LaidOutWord>) {
+        for (idx, (word, elements)) in words.into_iter().enumerate() {
+            if idx > 0 {
+                self.out.space();
+            }
+            let Some(elements) = elements else {
+                self.out.word(word);
+                continue;
+            };
+            self.out.open_block();
+            for element in elements {
+                self.out.indent();
+                self.out.word(element);
+                self.out.newline();
+            }
+            self.out.close_block();
+        }
+    }
+
+    // {condition}, padded with spaces inside the braces if configured
+    fn write_braced(&mut self, slice: &[u8]) {
+        self.out.word(b"{");
+        if self.options.pad_braces {
+            self.out.space();
+        }
+        self.out.word(slice);
+        if self.options.pad_braces {
+            self.out.space();
+        }
+        self.out.word(b"}");
+    }
+}
+
+// Formats a fragment of an iRule, for templates stitching rules together:
+// a block of commands, like `try_format`; a single command, without the
+// line ending after it; or an expression, like an `if` condition.
+pub fn format_snippet(
+    source: &[u8],
+    kind: SnippetKind,
+    options: FormatterOptions,
+) -> Result<Vec<u8>> {
+    let formatter = Formatter::with_options(options);
+    match kind {
+        SnippetKind::Block => formatter.try_format(source),
+        SnippetKind::Statement => {
+            let (tokens, spans) = Lexer::new()
+                .lex_with_spans(source)
+                .map_err(FormatFail::Lex)?;
+            let Ast::Block(mut trees) = Parser::new(source, &tokens, &spans)
+                .parse()
+                .map_err(FormatFail::Parse)?
+            else {
+                unreachable!("the parser returns a block");
+            };
+            trees.retain(|tree| !matches!(tree, Ast::EmptyLine));
+            let commands = trees
+                .iter()
+                .filter(|tree| !matches!(tree, Ast::Comment(_) | Ast::TrailingComment(_)))
+                .count();
+            if commands != 1 {
+                return Err(FormatFail::NotOneCommand(commands));
+            }
+            let line_ending = formatter.options.line_ending.as_bytes();
+            let mut buf = formatter.format(Ast::Block(trees));
+            if buf.ends_with(line_ending) {
+                buf.truncate(buf.len() - line_ending.len());
             }
-            (Some(v1), None) => {
-                self.write(b" ");
-                self.write(&v1);
+            Ok(buf)
+        }
+        SnippetKind::Expression => {
+            // lexed only to reject what no Tcl source could hold
+            Lexer::new().lex(source).map_err(FormatFail::Lex)?;
+            Ok(normalize_expression(source.trim_ascii()))
+        }
+    }
+}
+
+// Drops the `;` between commands, splitting them onto lines of their own,
+// unless kept. One that ended up next to anything but two commands, after a
+// pass moved trees around, is always dropped.
+fn keep_semicolons(trees: &mut Vec<Ast>, keep: bool) {
+    let is_command = |tree: Option<&Ast>| {
+        !matches!(
+            tree,
+            None | Some(
+                Ast::Comment(_)
+                    | Ast::TrailingComment(_)
+                    | Ast::Verbatim(_)
+                    | Ast::EmptyLine
+                    | Ast::Semicolon
+                    | Ast::Block(_)
+            )
+        )
+    };
+    // rebuilt rather than removed from, which is quadratic in long blocks
+    let mut rest = std::mem::take(trees).into_iter().peekable();
+    trees.reserve(rest.len());
+    while let Some(tree) = rest.next() {
+        let joins = keep && is_command(trees.last()) && is_command(rest.peek());
+        if !matches!(tree, Ast::Semicolon) || joins {
+            trees.push(tree);
+        }
+    }
+}
+
+// line width one-line bodies must fit in when `max_width` isn't set
+const DEFAULT_MAX_WIDTH: usize = 80;
+
+// the only statement of a body, if it has no other trees and fits on a line
+fn single_statement<'a, 'src>(body: &'a Ast<'src>) -> Option<&'a Statement<'src>> {
+    match body {
+        Ast::Block(trees) => match trees.as_slice() {
+            [Ast::Statement(statement)] => Some(statement),
+            _ => None,
+        },
+        Ast::Statement(statement) => Some(statement),
+        _ => None,
+    }
+    .filter(|statement| statement.words().iter().all(|word| !word.contains(&b'\n')))
+}
+
+// Commands and the position of their first list argument; the arguments
+// from there on are lists, whose value doesn't change when the whitespace
+// between their elements does. `list`, `lappend`, `dict create` and
+// `concat` aren't here: they take a braced word as one plain value.
+const LIST_ARGUMENTS: &[(&[&[u8]], usize)] = &[(&[b"array", b"set"], 3)];
+
+// Whether `list_layout` could find list arguments in a statement of `words`.
+// Only its first word is looked at: a statement whose first word is one word
+// when split again, and no command of `LIST_ARGUMENTS`, has none.
+fn may_take_lists(words: &[&[u8]]) -> bool {
+    let Some(first) = words.first() else {
+        return false;
+    };
+    first.is_empty()
+        || first.iter().any(u8::is_ascii_whitespace)
+        || LIST_ARGUMENTS
+            .iter()
+            .any(|(command, _)| command[0] == *first)
+}
+
+// a word of a statement, and its lines if it is a list laid out over several
+type LaidOutWord<'a> = (&'a [u8], Option<Vec<&'a [u8]>>);
+
+// The words of a statement, with the lines of each braced list argument
+// that spans several, or None if it has none. A list already laid out over
+// lines keeps its lines, and when `too_wide`, a list on one line with more
+// than one element gets a line per element.
+fn list_layout(line: &[u8], too_wide: bool) -> Option<Vec<LaidOutWord<'_>>> {
+    let words = split_words(line);
+    let (_, first) = LIST_ARGUMENTS
+        .iter()
+        .find(|(command, _)| words.len() >= command.len() && words[..command.len()] == **command)?;
+    let mut found = false;
+    let layout = words
+        .iter()
+        .enumerate()
+        .map(|(idx, &word)| {
+            let inner = match word {
+                [b'{', inner @ .., b'}'] if idx >= *first => inner,
+                _ => return (word, None),
+            };
+            if word_len(word, b'{', b'}') != word.len() {
+                return (word, None);
             }
-            (None, None) => {},
-            _ => unreachable!(),
+            let lines = match inner.contains(&b'\n') {
+                true => {
+                    let lines: Vec<&[u8]> = inner
+                        .split(|&c| c == b'\n')
+                        .map(|line| line.trim_ascii())
+                        .filter(|line| !line.is_empty())
+                        .collect();
+                    // an element spanning lines can't be re-indented
+                    Some(lines).filter(|lines| lines.iter().all(|line| balanced(line)))
+                }
+                false => Some(split_words(inner)).filter(|elements| too_wide && elements.len() > 1),
+            };
+            found |= lines.is_some();
+            (word, lines)
+        })
+        .collect();
+    found.then_some(layout)
+}
+
+// whether every brace in `line` is closed on it
+fn balanced(line: &[u8]) -> bool {
+    let mut depth = 0usize;
+    let mut escaped = false;
+    for &c in line {
+        match c {
+            _ if escaped => escaped = false,
+            b'\\' => escaped = true,
+            b'{' => depth += 1,
+            b'}' if depth == 0 => return false,
+            b'}' => depth -= 1,
+            _ => {}
         }
-        self.newline();
     }
+    depth == 0 && !escaped
+}
 
-    fn write(&mut self, slice: &[u8]) {
-        self.buf.extend_from_slice(slice);
+// Leaves exactly one empty line between each pair of procs or `when` blocks
+// that only have empty lines between them
+fn separate_definitions(trees: &mut Vec<Ast>) {
+    let is_definition = |tree: &Ast| matches!(tree, Ast::Procedure { .. } | Ast::When { .. });
+    let mut previous_definition = false;
+    let mut idx = 0;
+    while idx < trees.len() {
+        let empty = trees[idx..]
+            .iter()
+            .take_while(|tree| matches!(tree, Ast::EmptyLine))
+            .count();
+        // the comments documenting a definition go below the empty line
+        let comments = leading_comments(&trees[idx + empty..]);
+        let next = trees.get(idx + empty + comments);
+        if previous_definition && next.is_some_and(is_definition) {
+            trees.splice(idx..idx + empty, [Ast::EmptyLine]);
+            idx += 1;
+        } else {
+            idx += empty;
+        }
+        idx += comments;
+        previous_definition = trees.get(idx).is_some_and(is_definition);
+        idx += 1;
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        format_snippet, may_take_lists, FormatFail, Formatter, FormatterOptions, Result,
+        SnippetKind,
+    };
+    use crate::{
+        ast::Ast,
+        config::ConfigFail,
+        lexer::Lexer,
+        parser::{Parser, ParserFail},
+    };
 
-    fn writeline(&mut self, slice: &[u8]) {
-        self.write(slice);
-        self.newline();
+    fn parse(source: &str) -> Ast<'_> {
+        let (tokens, spans) = Lexer::new().lex_with_spans(source.as_bytes()).unwrap();
+        Parser::new(source.as_bytes(), &tokens, &spans)
+            .parse()
+            .unwrap()
     }
 
-    fn newline(&mut self) {
-        self.buf.push(b'\n');
+    fn format_with(options: FormatterOptions, source: &str) -> String {
+        String::from_utf8(Formatter::with_options(options).format(parse(source))).unwrap()
     }
 
-    fn close_block(&mut self) {
-        self.indent();
-        self.write(b"}\n");
+    #[test]
+    fn presets_differ_in_braces_and_else() {
+        let source = "if {$a} {\nset b 1\n} else {\nset b 2\n}\n\n\n\nset c 3\n";
+        let f5 = FormatterOptions::preset("f5-irule").unwrap();
+        assert_eq!(
+            format_with(f5, source),
+            "if { $a } {\n    set b 1\n} else {\n    set b 2\n}\n\nset c 3\n"
+        );
+        let std = FormatterOptions::preset("tcl-std").unwrap();
+        assert_eq!(
+            format_with(std, source),
+            "if {$a} {\n    set b 1\n} else {\n    set b 2\n}\n\nset c 3\n"
+        );
+        assert_eq!(
+            format_with(FormatterOptions::default(), source),
+            "if { $a } {\n    set b 1\n}\nelse {\n    set b 2\n}\n\n\nset c 3\n"
+        );
+    }
+
+    #[test]
+    fn knows_every_listed_preset() {
+        for name in FormatterOptions::PRESETS {
+            assert!(FormatterOptions::preset(name).is_some(), "{name}");
+        }
+        assert!(FormatterOptions::preset("k&r").is_none());
+    }
+
+    #[test]
+    fn keeps_trailing_comments_on_their_line() {
+        let source = "set a 1 ;# one\nset long_name 2;# two\n\nset b 3 ;# three\n";
+        assert_eq!(
+            format_with(FormatterOptions::default(), source),
+            "set a 1 ;# one\nset long_name 2 ;# two\n\nset b 3 ;# three\n"
+        );
+    }
+
+    #[test]
+    fn aligns_trailing_comments_in_runs() {
+        let source = "set a 1 ;# one\nset long_name 2;# two\n\nset b 3 ;# three\n";
+        let options = FormatterOptions {
+            align_trailing_comments: Some(12),
+            ..FormatterOptions::default()
+        };
+        assert_eq!(
+            format_with(options, source),
+            "set a 1         ;# one\nset long_name 2 ;# two\n\nset b 3    ;# three\n"
+        );
+    }
+
+    #[test]
+    fn aligns_switch_arm_bodies() {
+        let source = "switch $a {\nx -\nlonger {\nset b 1\n}\ndefault {\nset b 2\n}\n}\n";
+        let options = FormatterOptions {
+            align_switch_arms: true,
+            ..FormatterOptions::default()
+        };
+        let formatted = format_with(options, source);
+        assert!(
+            formatted.contains("\n    x       -\n    longer  {\n"),
+            "{formatted}"
+        );
+        assert!(formatted.contains("\n    default {\n"), "{formatted}");
+        assert!(
+            format_with(FormatterOptions::default(), source).contains("\n    x -\n    longer {\n")
+        );
+    }
+
+    #[test]
+    fn separates_top_level_definitions() {
+        let source = "proc a {} {\nset x 1\n}\nproc b {} {\nset x 2\n}\n\n\n\nwhen X {\npool p\n}\nset y 1\nproc c {} {\nset x 3\n}\n";
+        let options = FormatterOptions {
+            blank_line_between_definitions: true,
+            ..FormatterOptions::default()
+        };
+        assert_eq!(
+            format_with(options, source),
+            "proc a { } {\n    set x 1\n}\n\nproc b { } {\n    set x 2\n}\n\nwhen X {\n    pool p\n}\nset y 1\nproc c { } {\n    set x 3\n}\n"
+        );
+    }
+
+    #[test]
+    fn separates_definitions_above_their_comments() {
+        let source = "proc a {} {\nset x 1\n}\n# about b\nproc b {} {\nset x 2\n}\n";
+        let options = FormatterOptions {
+            blank_line_between_definitions: true,
+            ..FormatterOptions::default()
+        };
+        assert!(format_with(options, source).contains("}\n\n# about b\nproc b"));
+    }
+
+    #[test]
+    fn compacts_empty_blocks() {
+        let source = "proc noop {} {\n}\nif {$x} {\n} else {\nset a 1\n}\nwhen X {\n\n}\n";
+        let options = FormatterOptions {
+            compact_empty_blocks: true,
+            ..FormatterOptions::default()
+        };
+        let formatted = format_with(options, source);
+        assert!(
+            formatted.starts_with("proc noop {} {}\nif { $x } {}\n"),
+            "{formatted}"
+        );
+        assert!(
+            formatted.contains("else {\n    set a 1\n}\n"),
+            "{formatted}"
+        );
+        assert!(!format_with(FormatterOptions::default(), source).contains("{}"));
+    }
+
+    #[test]
+    fn formats_braced_scripts() {
+        let source = "eval {\nset a  1\n}\nuplevel 1 {\nif {$a} {\npool p\n}\n}\n";
+        assert_eq!(
+            format_with(FormatterOptions::default(), source),
+            "eval {\n    set a 1\n}\nuplevel 1 {\n    if { $a } {\n        pool p\n    }\n}\n"
+        );
+    }
+
+    #[test]
+    fn keeps_dynamic_scripts_as_written() {
+        for source in [
+            "eval $script\n",
+            "uplevel #0 $script\n",
+            "eval {set a 1} extra\n",
+            "subst {$a  [b]}\n",
+        ] {
+            assert_eq!(format_with(FormatterOptions::default(), source), source);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_input_without_panicking() {
+        for source in [
+            "node 10.0.0.1 80 extra\n",
+            "snat 10.0.0.1 80 extra\n",
+            "switch $a {",
+            "if {$a} x {\n}\n",
+            "when {\n}\n",
+        ] {
+            assert!(
+                Formatter::new().try_format(source.as_bytes()).is_err(),
+                "{source:?}"
+            );
+        }
+        assert!(Formatter::new().try_format(b"set a x/y\\z\n").is_ok());
+    }
+
+    // a small deterministic generator, as the crate has no dependencies
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    #[test]
+    fn formats_arbitrary_bytes_without_panicking() {
+        const ALPHABET: &[u8] = b"{}[]\"$;#\\\n \tab:-if when switch proc set \xe6\x97\xa5";
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..2000 {
+            let len = rng.below(64);
+            let source: Vec<u8> = (0..len)
+                .map(|_| ALPHABET[rng.below(ALPHABET.len())])
+                .collect();
+            let _ = Formatter::new().try_format(&source);
+        }
     }
 
-    fn indent(&mut self) {
-        let data = iter::repeat(b"    ")
-            .take(self.depth)
-            .fold(Vec::new(), |mut acc, e| {
-                acc.extend_from_slice(e);
-                acc
-            });
-        self.buf.extend_from_slice(&data);
+    #[test]
+    fn formats_braced_after_scripts() {
+        let source =
+            "after 100 -periodic {\nset a  1\n}\nafter cancel $id\ntable  set  -notouch  k  v\n";
+        assert_eq!(
+            format_with(FormatterOptions::default(), source),
+            "after 100 -periodic {\n    set a 1\n}\nafter cancel $id\ntable set -notouch k v\n"
+        );
+    }
+
+    #[test]
+    fn spaces_log_words_and_keeps_messages() {
+        let source = "log   -noname  local0.info   \"a  b\"\n";
+        assert_eq!(
+            format_with(FormatterOptions::default(), source),
+            "log -noname local0.info \"a  b\"\n"
+        );
+    }
+
+    #[test]
+    fn breaks_long_lists_past_max_width() {
+        let options = FormatterOptions {
+            max_width: Some(30),
+            ..FormatterOptions::default()
+        };
+        let source = "array set n {alpha 1 beta 2 gamma 3}\nset s {alpha 1 beta 2 gamma 3 delta}\narray set m {\nk1   v1\n  k2 v2\n}\n";
+        assert_eq!(
+            format_with(options, source),
+            "array set n {\n    alpha\n    1\n    beta\n    2\n    gamma\n    3\n}\nset s {alpha 1 beta 2 gamma 3 delta}\narray set m {\n    k1   v1\n    k2 v2\n}\n"
+        );
+    }
+
+    #[test]
+    fn laid_out_lists_keep_their_value() {
+        let options = FormatterOptions {
+            max_width: Some(20),
+            ..FormatterOptions::default()
+        };
+        let elements = |line: &str| -> Vec<String> {
+            let inner = &line[line.find('{').unwrap() + 1..line.rfind('}').unwrap()];
+            inner.split_whitespace().map(str::to_string).collect()
+        };
+        let source = "array set m {k1 v1 k2 v2 k3 v3}\n";
+        let formatted = format_with(options.clone(), source);
+        assert_ne!(formatted, source);
+        assert_eq!(elements(&formatted), elements(source));
+        // a braced word is one value to these, whitespace and all
+        for source in [
+            "dict create {alpha 1 beta 2 gamma 3}\n",
+            "dict create {alpha 1\n  beta 2}\n",
+            "lappend l {alpha 1 beta 2 gamma 3}\n",
+            "list {alpha 1 beta 2 gamma 3}\n",
+            "concat {alpha 1 beta 2 gamma 3}\n",
+        ] {
+            assert_eq!(format_with(options.clone(), source), source);
+        }
+    }
+
+    #[test]
+    fn writes_statements_without_lists_word_by_word() {
+        let words = |line: &'static str| -> Vec<&'static [u8]> {
+            line.split(' ').map(str::as_bytes).collect()
+        };
+        assert!(!may_take_lists(&words("set a 1")));
+        assert!(!may_take_lists(&[]));
+        assert!(!may_take_lists(&words("dict create a 1")));
+        assert!(!may_take_lists(&words("list a b")));
+        assert!(may_take_lists(&words("array set m {k v}")));
+        assert!(may_take_lists(&[b"array set", b"m", b"{k v}"]));
+        assert!(may_take_lists(&[b"", b"x"]));
+        let options = FormatterOptions {
+            max_width: Some(10),
+            ..FormatterOptions::default()
+        };
+        assert_eq!(
+            format_with(
+                options,
+                "set s {alpha 1 beta 2}
+pool p
+"
+            ),
+            "set s {alpha 1 beta 2}
+pool p
+"
+        );
+    }
+
+    #[test]
+    fn drops_semicolons_in_long_blocks() {
+        let source = "set a 1; set b 2;
+"
+        .repeat(20_000);
+        let kept = format_with(
+            FormatterOptions {
+                keep_semicolons: true,
+                ..FormatterOptions::default()
+            },
+            &source,
+        );
+        assert_eq!(kept, "set a 1; set b 2\n".repeat(20_000));
+        let dropped = format_with(FormatterOptions::default(), &source);
+        assert_eq!(dropped, "set a 1\nset b 2\n".repeat(20_000));
+    }
+
+    #[test]
+    fn size_hint_keeps_the_output() {
+        let source = "when HTTP_REQUEST {\nif {$a} {\npool p\n}\n}\n";
+        let format = |hint: Option<usize>| {
+            let (tokens, spans) = Lexer::new().lex_with_spans(source.as_bytes()).unwrap();
+            let ast = Parser::new(source.as_bytes(), &tokens, &spans)
+                .parse()
+                .unwrap();
+            let formatter = Formatter::new();
+            match hint {
+                Some(len) => formatter.size_hint(len).format(ast),
+                None => formatter.format(ast),
+            }
+        };
+        assert_eq!(format(Some(source.len())), format(None));
+        assert_eq!(format(Some(0)), format(None));
+    }
+
+    #[test]
+    fn keeps_single_statement_bodies_on_one_line() {
+        let options = FormatterOptions {
+            one_line_bodies: true,
+            max_width: Some(40),
+            ..FormatterOptions::default()
+        };
+        let source = "if {$x} {\npool p1\n}\nproc p {} { return 1 }\nif {$x} {\npool p1\n} else {\npool p2\n}\nif {$x} {\nlog local0. \"a message too long to fit\"\n}\n";
+        assert_eq!(
+            format_with(options, source),
+            "if { $x } { pool p1 }\nproc p { } { return 1 }\nif { $x } {\n    pool p1\n}\nelse {\n    pool p2\n}\nif { $x } {\n    log local0. \"a message too long to fit\"\n}\n"
+        );
+        // accepted, but spread out, without the option
+        assert_eq!(
+            format_with(FormatterOptions::default(), "proc p {} { return 1 }\n"),
+            "proc p { } {\n    return 1\n}\n"
+        );
+    }
+
+    #[test]
+    fn wraps_long_proc_signatures() {
+        let options = FormatterOptions {
+            max_width: Some(30),
+            ..FormatterOptions::default()
+        };
+        let source =
+            "proc handle_request {client_address server_port {timeout 30}} {\nreturn 1\n}\n";
+        let formatted = format_with(options.clone(), source);
+        assert_eq!(
+            formatted,
+            "proc handle_request { client_address \\\n                      server_port \\\n                      {timeout 30} } {\n    return 1\n}\n"
+        );
+        // and reads back to the same layout
+        assert_eq!(format_with(options, &formatted), formatted);
+    }
+
+    #[test]
+    fn keeps_shebangs_and_modelines_verbatim() {
+        let source =
+            "#!/usr/bin/env tclsh\n#   -*- mode: tcl -*-\nset a  1\n#  vim: set sw=2 et:\n";
+        assert_eq!(
+            format_with(FormatterOptions::default(), source),
+            "#!/usr/bin/env tclsh\n#   -*- mode: tcl -*-\nset a 1\n#  vim: set sw=2 et:\n"
+        );
+    }
+
+    fn snippet(source: &str, kind: SnippetKind) -> Result<String> {
+        format_snippet(source.as_bytes(), kind, FormatterOptions::default())
+            .map(|out| String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn formats_snippets() {
+        assert_eq!(
+            snippet("  if {$a} {\npool p\n}\n", SnippetKind::Statement).unwrap(),
+            "if { $a } {\n    pool p\n}"
+        );
+        assert_eq!(
+            snippet("$a==1&&$b", SnippetKind::Expression).unwrap(),
+            "$a == 1 && $b"
+        );
+        assert_eq!(
+            snippet("set a  1\n\nset b 2", SnippetKind::Block).unwrap(),
+            "set a 1\n\nset b 2\n"
+        );
+    }
+
+    #[test]
+    fn statement_snippets_hold_one_command() {
+        assert!(matches!(
+            snippet("set a 1\nset b 2\n", SnippetKind::Statement),
+            Err(FormatFail::NotOneCommand(2))
+        ));
+        assert!(matches!(
+            snippet("# only a comment\n", SnippetKind::Statement),
+            Err(FormatFail::NotOneCommand(0))
+        ));
+        assert_eq!(
+            snippet("# why\nset a 1 ;# what\n", SnippetKind::Statement).unwrap(),
+            "# why\nset a 1 ;# what"
+        );
+    }
+
+    fn nested(open: &str, body: &str, close: &str, depth: usize) -> Vec<u8> {
+        (open.repeat(depth) + body + &close.repeat(depth)).into_bytes()
+    }
+
+    fn too_deep(source: &[u8]) -> bool {
+        matches!(
+            Formatter::new().try_format(source),
+            Err(FormatFail::Parse(e)) if matches!(e.kind, ParserFail::TooDeep(_))
+        )
+    }
+
+    // Inputs deeper than the fuzz target in `fuzz/` reaches with its default
+    // size limit; they must come back as an error, not overflow the stack.
+    #[test]
+    fn rejects_deeply_nested_blocks() {
+        assert!(too_deep(&nested("if {1} {\n", "set a 1\n", "}\n", 100_000)));
+        assert!(too_deep(&nested("proc p {} {\n", "", "}\n", 10_000)));
+        assert!(too_deep(&nested("when X {\n", "", "}\n", 10_000)));
+        let switch = nested("switch $a {\nb {\n", "set c 1\n", "}\n}\n", 10_000);
+        assert!(too_deep(&switch));
+    }
+
+    // Words nested as deep come back as an error or output, as long as the
+    // stack holds.
+    #[test]
+    fn formats_deeply_nested_words() {
+        for (open, close) in [("{", "}"), ("[", "]"), ("\"[", "]\"")] {
+            let source = [
+                b"set a ".as_slice(),
+                &nested(open, "x", close, 10_000),
+                b"\n",
+            ]
+            .concat();
+            let _ = Formatter::new().try_format(&source);
+        }
+    }
+
+    #[test]
+    fn formats_long_elseif_chains() {
+        let mut source = String::from("if {$a == 0} {\n    set b 0\n}");
+        for i in 1..100_000 {
+            source.push_str(&format!(" elseif {{$a == {i}}} {{\n    set b {i}\n}}"));
+        }
+        source.push('\n');
+        let formatted = Formatter::new().try_format(source.as_bytes()).unwrap();
+        let elseifs = formatted
+            .split(|&c| c == b'\n')
+            .filter(|line| line.starts_with(b"elseif "));
+        assert_eq!(elseifs.count(), 99_999);
+    }
+
+    #[test]
+    fn reports_invalid_directives() {
+        let source = b"when X {\n    # tclfmt: one_line_bodies=maybe\n    pool p1\n}\n";
+        assert!(matches!(
+            Formatter::new().try_format(source),
+            Err(FormatFail::Directive(ConfigFail::InvalidValue {
+                line: 2,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn restores_directive_options_at_the_end_of_their_block() {
+        let source = b"when X {\n    # tclfmt: max_width=20\n    array set a {k1 v1 k2 v2}\n}\narray set a {k1 v1 k2 v2}\n";
+        let options = FormatterOptions {
+            use_tabs: true,
+            ..FormatterOptions::default()
+        };
+        let formatted = Formatter::with_options(options).try_format(source).unwrap();
+        let expected = "when X {\n\t# tclfmt: max_width=20\n\tarray set a {\n\t\tk1\n\t\tv1\n\t\tk2\n\t\tv2\n\t}\n}\narray set a {k1 v1 k2 v2}\n";
+        assert_eq!(String::from_utf8_lossy(&formatted), expected);
     }
 }
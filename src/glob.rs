@@ -0,0 +1,123 @@
+// Glob matching for `/`-separated paths: `*` and `?` stay within one path
+// segment, `**` spans segments, plus `[abc]`, `[!a-z]` and `{a,b}`.
+
+pub fn matches(pattern: &str, path: &str) -> bool {
+    expand_braces(pattern)
+        .iter()
+        .any(|p| match_bytes(p.as_bytes(), path.as_bytes()))
+}
+
+// `pattern` as in .editorconfig sections: anchored to the directory `relative`
+// is relative to, but without a `/` matching the file name in any directory
+pub fn matches_relative(pattern: &str, relative: &str) -> bool {
+    match pattern.strip_prefix('/') {
+        Some(anchored) => matches(anchored, relative),
+        None if pattern.contains('/') => matches(pattern, relative),
+        None => matches(&format!("**/{pattern}"), relative),
+    }
+}
+
+// a{b,c}d => [abd, acd], recursively for every brace group
+fn expand_braces(pattern: &str) -> Vec<String> {
+    let bytes = pattern.as_bytes();
+    let Some(open) = bytes.iter().position(|&c| c == b'{') else {
+        return vec![pattern.to_string()];
+    };
+    let mut depth = 0;
+    let mut alternatives = Vec::new();
+    let mut start = open + 1;
+    for (idx, &c) in bytes.iter().enumerate().skip(open) {
+        match c {
+            b'{' => depth += 1,
+            b',' if depth == 1 => {
+                alternatives.push(&pattern[start..idx]);
+                start = idx + 1;
+            }
+            b'}' if depth == 1 => {
+                alternatives.push(&pattern[start..idx]);
+                let (prefix, suffix) = (&pattern[..open], &pattern[idx + 1..]);
+                return alternatives
+                    .into_iter()
+                    .flat_map(|alt| expand_braces(&format!("{prefix}{alt}{suffix}")))
+                    .collect();
+            }
+            b'}' => depth -= 1,
+            _ => {}
+        }
+    }
+    vec![pattern.to_string()] // unbalanced, match the brace literally
+}
+
+fn match_bytes(pattern: &[u8], path: &[u8]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        [b'*', b'*', b'/', rest @ ..] => {
+            // zero or more whole directories
+            match_bytes(rest, path)
+                || (0..path.len()).any(|i| path[i] == b'/' && match_bytes(rest, &path[i + 1..]))
+        }
+        [b'*', b'*', rest @ ..] => (0..=path.len()).any(|i| match_bytes(rest, &path[i..])),
+        [b'*', rest @ ..] => {
+            let segment = path.iter().take_while(|&&c| c != b'/').count();
+            (0..=segment).any(|i| match_bytes(rest, &path[i..]))
+        }
+        [b'?', rest @ ..] => match path {
+            [c, path @ ..] if *c != b'/' => match_bytes(rest, path),
+            _ => false,
+        },
+        [b'[', class @ ..] => match class.iter().position(|&c| c == b']') {
+            Some(end) if end > 0 => match path {
+                [c, path @ ..] if *c != b'/' && class_contains(&class[..end], *c) => {
+                    match_bytes(&class[end + 1..], path)
+                }
+                _ => false,
+            },
+            _ => path.first() == Some(&b'[') && match_bytes(class, &path[1..]),
+        },
+        [b'\\', c, rest @ ..] | [c, rest @ ..] => match path {
+            [p, path @ ..] if p == c => match_bytes(rest, path),
+            _ => false,
+        },
+    }
+}
+
+fn class_contains(class: &[u8], c: u8) -> bool {
+    let (negated, class) = match class {
+        [b'!' | b'^', class @ ..] => (true, class),
+        class => (false, class),
+    };
+    let mut idx = 0;
+    let mut found = false;
+    while idx < class.len() {
+        if idx + 2 < class.len() && class[idx + 1] == b'-' {
+            found |= (class[idx]..=class[idx + 2]).contains(&c);
+            idx += 3;
+        } else {
+            found |= class[idx] == c;
+            idx += 1;
+        }
+    }
+    found != negated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches_relative;
+
+    #[test]
+    fn patterns_without_a_slash_match_file_names_anywhere() {
+        assert!(matches_relative("*.tcl", "a.tcl"));
+        assert!(matches_relative("*.tcl", "legacy/deep/a.tcl"));
+        assert!(!matches_relative("*.tcl", "a.irul"));
+    }
+
+    #[test]
+    fn patterns_with_a_slash_are_anchored() {
+        assert!(matches_relative("legacy/**", "legacy/a.tcl"));
+        assert!(matches_relative("legacy/**", "legacy/deep/a.tcl"));
+        assert!(!matches_relative("legacy/**", "src/legacy/a.tcl"));
+        assert!(matches_relative("/a.tcl", "a.tcl"));
+        assert!(!matches_relative("/a.tcl", "src/a.tcl"));
+        assert!(matches_relative("src/{a,b}.tcl", "src/b.tcl"));
+    }
+}
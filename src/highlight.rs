@@ -0,0 +1,169 @@
+// Syntax-highlighted rendering of source text, driven by the token stream.
+// Bytes between tokens (whitespace) are copied through untouched, so the
+// output reads exactly like the input, only colored.
+use std::ops::Range;
+
+use crate::lexer::Token;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    Ansi,
+    Html, // <span class="tcl-..."> inside a <pre>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Class {
+    Keyword,
+    String,
+    Comment,
+    Variable,
+}
+
+impl Class {
+    fn ansi(self) -> &'static [u8] {
+        match self {
+            Class::Keyword => b"\x1b[31m",
+            Class::String => b"\x1b[32m",
+            Class::Comment => b"\x1b[90m",
+            Class::Variable => b"\x1b[36m",
+        }
+    }
+
+    fn html(self) -> &'static [u8] {
+        match self {
+            Class::Keyword => b"<span class=\"tcl-keyword\">",
+            Class::String => b"<span class=\"tcl-string\">",
+            Class::Comment => b"<span class=\"tcl-comment\">",
+            Class::Variable => b"<span class=\"tcl-variable\">",
+        }
+    }
+}
+
+// `tokens` and `spans` as returned by `Lexer::lex_with_spans` for `src`
+pub fn highlight(src: &[u8], tokens: &[Token], spans: &[Range<usize>], style: Style) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(src.len() * 2);
+    if style == Style::Html {
+        buf.extend_from_slice(b"<pre class=\"tcl\">");
+    }
+    let mut pos = 0;
+    for (class, range) in classify(tokens, spans) {
+        write_text(&mut buf, &src[pos..range.start], style);
+        match style {
+            Style::Ansi => buf.extend_from_slice(class.ansi()),
+            Style::Html => buf.extend_from_slice(class.html()),
+        }
+        write_text(&mut buf, &src[range.clone()], style);
+        match style {
+            Style::Ansi => buf.extend_from_slice(b"\x1b[0m"),
+            Style::Html => buf.extend_from_slice(b"</span>"),
+        }
+        pos = range.end;
+    }
+    write_text(&mut buf, &src[pos..], style);
+    if style == Style::Html {
+        buf.extend_from_slice(b"</pre>\n");
+    }
+    buf
+}
+
+// non-overlapping, ordered ranges of `src` to color
+fn classify(tokens: &[Token], spans: &[Range<usize>]) -> Vec<(Class, Range<usize>)> {
+    let mut classes = Vec::new();
+    let mut idx = 0;
+    while idx < tokens.len() {
+        let start = spans[idx].start;
+        let (class, end_idx) = match (&tokens[idx], tokens.get(idx + 1)) {
+            (t, _) if t.is_keyword() => (Some(Class::Keyword), idx),
+            (Token::Hash, Some(Token::Other(_))) => (Some(Class::Comment), idx + 1),
+            (Token::Hash, _) => (Some(Class::Comment), idx),
+            (Token::Other(data), _) if data.starts_with(b"\"") => (Some(Class::String), idx),
+            (Token::Quote, _) => {
+                // closing quote on the same line, or the rest of the line if unterminated
+                let end_idx = tokens[idx + 1..]
+                    .iter()
+                    .position(|t| matches!(t, Token::Quote | Token::Newline))
+                    .map(|n| match tokens[idx + 1 + n] {
+                        Token::Quote => idx + 1 + n,
+                        _ => idx + n,
+                    })
+                    .unwrap_or(tokens.len() - 1);
+                (Some(Class::String), end_idx)
+            }
+            (Token::Dollar, Some(Token::Identifier(_))) => {
+                // $name, $ns::name
+                let mut end_idx = idx + 1;
+                while let (Some(Token::DoubleColon), Some(Token::Identifier(_))) =
+                    (tokens.get(end_idx + 1), tokens.get(end_idx + 2))
+                {
+                    end_idx += 2;
+                }
+                (Some(Class::Variable), end_idx)
+            }
+            _ => (None, idx),
+        };
+        if let Some(class) = class {
+            classes.push((class, start..spans[end_idx].end));
+        }
+        idx = end_idx + 1;
+    }
+    classes
+}
+
+fn write_text(buf: &mut Vec<u8>, text: &[u8], style: Style) {
+    if style == Style::Ansi {
+        buf.extend_from_slice(text);
+        return;
+    }
+    for &c in text {
+        match c {
+            b'<' => buf.extend_from_slice(b"&lt;"),
+            b'>' => buf.extend_from_slice(b"&gt;"),
+            b'&' => buf.extend_from_slice(b"&amp;"),
+            b'"' => buf.extend_from_slice(b"&quot;"),
+            c => buf.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{highlight, Style};
+    use crate::lexer::Lexer;
+
+    fn render(source: &str, style: Style) -> String {
+        let (tokens, spans) = Lexer::new().lex_with_spans(source.as_bytes()).unwrap();
+        String::from_utf8(highlight(source.as_bytes(), &tokens, &spans, style)).unwrap()
+    }
+
+    // `text` with the ANSI color sequences taken out
+    fn plain(text: &str) -> String {
+        let mut out = String::new();
+        let mut rest = text;
+        while let Some(start) = rest.find('\x1b') {
+            out.push_str(&rest[..start]);
+            rest = &rest[start + rest[start..].find('m').unwrap() + 1..];
+        }
+        out + rest
+    }
+
+    const SOURCE: &str = "# note\nset a \"x $b\"\nif {$ns::v < 1} {\n    pool p\n}\n";
+
+    #[test]
+    fn colors_tokens_without_changing_the_text() {
+        let ansi = render(SOURCE, Style::Ansi);
+        assert_eq!(plain(&ansi), SOURCE);
+        assert!(
+            ansi.starts_with("\x1b[90m# note\x1b[0m\n\x1b[31mset\x1b[0m a \x1b[32m\"x $b\"\x1b[0m")
+        );
+        assert!(ansi.contains("\x1b[36m$ns::v\x1b[0m"));
+    }
+
+    #[test]
+    fn escapes_html() {
+        let html = render(SOURCE, Style::Html);
+        assert!(html.starts_with("<pre class=\"tcl\"><span class=\"tcl-comment\"># note</span>"));
+        assert!(html.contains("<span class=\"tcl-string\">&quot;x $b&quot;</span>"));
+        assert!(html.contains("<span class=\"tcl-variable\">$ns::v</span> &lt; 1"));
+        assert!(html.ends_with("}\n</pre>\n"));
+    }
+}
@@ -0,0 +1,220 @@
+// Git integration: installing the pre-commit hook and formatting what is
+// staged in the index, which may differ from the worktree when a file is
+// only partially staged.
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::is_tcl;
+
+const HOOK_MARKER: &str = "# installed by tcl-formatter --hook install";
+const HOOK_SCRIPT: &str = "\
+#!/bin/sh
+# installed by tcl-formatter --hook install
+exec tcl-formatter --staged
+";
+
+// An index entry of a staged file
+pub struct Staged {
+    pub path: PathBuf, // absolute, in the worktree
+    top: PathBuf,      // top level of the worktree
+    name: String,      // relative to `top`, as git spells it
+    mode: String,
+    blob: String,
+}
+
+// Writes .git/hooks/pre-commit, refusing to replace a hook we did not write
+pub fn install() -> io::Result<PathBuf> {
+    let hooks = PathBuf::from(git(None, &["rev-parse", "--git-path", "hooks"], None)?.trim());
+    install_into(&hooks)
+}
+
+fn install_into(hooks: &Path) -> io::Result<PathBuf> {
+    std::fs::create_dir_all(hooks)?;
+    let path = hooks.join("pre-commit");
+    if let Ok(existing) = std::fs::read_to_string(&path) {
+        if !existing.contains(HOOK_MARKER) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "{} exists and was not installed by tcl-formatter",
+                    path.display()
+                ),
+            ));
+        }
+    }
+    std::fs::write(&path, HOOK_SCRIPT)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(path)
+}
+
+// Added, copied, modified or renamed Tcl files in the index
+pub fn staged_files() -> io::Result<Vec<Staged>> {
+    let top = PathBuf::from(git(None, &["rev-parse", "--show-toplevel"], None)?.trim());
+    staged_in(top)
+}
+
+fn staged_in(top: PathBuf) -> io::Result<Vec<Staged>> {
+    let names = git(
+        Some(&top),
+        &[
+            "diff",
+            "--cached",
+            "--name-only",
+            "-z",
+            "--diff-filter=ACMR",
+        ],
+        None,
+    )?;
+    let mut staged = Vec::new();
+    for name in names.split('\0').filter(|name| is_tcl(Path::new(name))) {
+        // <mode> SP <blob> SP <stage> TAB <name>
+        let pathspec = format!(":(literal){name}");
+        let entry = git(
+            Some(&top),
+            &["ls-files", "--stage", "-z", "--", &pathspec],
+            None,
+        )?;
+        let mut fields = entry.split(['\t', '\0']).next().unwrap_or("").split(' ');
+        let (Some(mode), Some(blob)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        staged.push(Staged {
+            path: top.join(name),
+            top: top.clone(),
+            name: name.to_string(),
+            mode: mode.to_string(),
+            blob: blob.to_string(),
+        });
+    }
+    Ok(staged)
+}
+
+impl Staged {
+    pub fn read(&self) -> io::Result<Vec<u8>> {
+        git_bytes(Some(&self.top), &["cat-file", "blob", &self.blob], None)
+    }
+
+    // Replaces the index entry with `content`, and the worktree file too when
+    // it holds no unstaged changes that would be lost
+    pub fn write(&self, original: &[u8], content: &[u8]) -> io::Result<()> {
+        let top = Some(self.top.as_path());
+        let blob = git(
+            top,
+            &["hash-object", "-w", "--stdin", "--path", &self.name],
+            Some(content),
+        )?;
+        let cacheinfo = format!("{},{},{}", self.mode, blob.trim(), self.name);
+        git(top, &["update-index", "--cacheinfo", &cacheinfo], None)?;
+        if std::fs::read(&self.path).is_ok_and(|worktree| worktree == original) {
+            std::fs::write(&self.path, content)?;
+        }
+        Ok(())
+    }
+}
+
+fn git(dir: Option<&Path>, args: &[&str], input: Option<&[u8]>) -> io::Result<String> {
+    let out = git_bytes(dir, args, input)?;
+    String::from_utf8(out).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn git_bytes(dir: Option<&Path>, args: &[&str], input: Option<&[u8]>) -> io::Result<Vec<u8>> {
+    let mut command = Command::new("git");
+    if let Some(dir) = dir {
+        command.current_dir(dir);
+    }
+    let mut child = command
+        .args(args)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input)?;
+    }
+    let out = child.wait_with_output()?;
+    if !out.status.success() {
+        return Err(io::Error::other(format!(
+            "git {}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&out.stderr).trim()
+        )));
+    }
+    Ok(out.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{git, install_into, staged_in, HOOK_SCRIPT};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tclfmt-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn installs_only_over_its_own_hook() {
+        let hooks = temp_dir("hooks");
+        let path = install_into(&hooks).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), HOOK_SCRIPT);
+        // reinstalling over our own hook is fine
+        install_into(&hooks).unwrap();
+        std::fs::write(&path, "#!/bin/sh\nmake lint\n").unwrap();
+        let e = install_into(&hooks).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "#!/bin/sh\nmake lint\n"
+        );
+        std::fs::remove_dir_all(&hooks).unwrap();
+    }
+
+    #[test]
+    fn rewrites_staged_content() {
+        let top = temp_dir("staged");
+        let top = top.canonicalize().unwrap();
+        git(Some(&top), &["init", "-q"], None).unwrap();
+        std::fs::write(top.join("a.tcl"), "set a  1\n").unwrap();
+        std::fs::write(top.join("b.tcl"), "set b  1\n").unwrap();
+        std::fs::write(top.join("notes.txt"), "x\n").unwrap();
+        git(Some(&top), &["add", "a.tcl", "b.tcl", "notes.txt"], None).unwrap();
+        // b.tcl has unstaged changes, which must survive
+        std::fs::write(top.join("b.tcl"), "set b  2\n").unwrap();
+
+        let staged = staged_in(top.clone()).unwrap();
+        let names: Vec<&str> = staged.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["a.tcl", "b.tcl"]);
+        for file in &staged {
+            let original = file.read().unwrap();
+            let formatted = String::from_utf8(original.clone())
+                .unwrap()
+                .replace("  ", " ");
+            file.write(&original, formatted.as_bytes()).unwrap();
+        }
+
+        let index = |name: &str| git(Some(&top), &["show", &format!(":{name}")], None).unwrap();
+        assert_eq!(index("a.tcl"), "set a 1\n");
+        assert_eq!(index("b.tcl"), "set b 1\n");
+        assert_eq!(
+            std::fs::read_to_string(top.join("a.tcl")).unwrap(),
+            "set a 1\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(top.join("b.tcl")).unwrap(),
+            "set b  2\n"
+        );
+        std::fs::remove_dir_all(&top).unwrap();
+    }
+}
@@ -0,0 +1,100 @@
+// `.tclfmtignore` files: gitignore-style glob patterns of files to leave
+// alone. Every ignore file in the path's ancestor directories applies, with
+// patterns relative to its own directory; the last matching pattern wins and
+// a leading `!` re-includes.
+use std::path::Path;
+
+use crate::glob;
+
+pub const IGNORE_FILE_NAME: &str = ".tclfmtignore";
+
+// `path` should be absolute, so that every ancestor directory is visited
+pub fn is_ignored(path: &Path) -> std::io::Result<bool> {
+    let mut files = Vec::new();
+    for dir in path.ancestors().skip(1) {
+        let candidate = dir.join(IGNORE_FILE_NAME);
+        if candidate.is_file() {
+            files.push((dir, std::fs::read_to_string(&candidate)?));
+        }
+    }
+
+    let mut ignored = false;
+    for (dir, text) in files.iter().rev() {
+        let Ok(relative) = path.strip_prefix(dir) else {
+            continue;
+        };
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (negated, pattern) = match line.strip_prefix('!') {
+                Some(pattern) => (true, pattern),
+                None => (false, line),
+            };
+            if pattern_matches(pattern, &relative) {
+                ignored = !negated;
+            }
+        }
+    }
+    Ok(ignored)
+}
+
+fn pattern_matches(pattern: &str, relative: &str) -> bool {
+    // dir/ matches everything below the directory
+    let (pattern, directory) = match pattern.strip_suffix('/') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+    // patterns without a `/` match at any depth
+    let pattern = match pattern.strip_prefix('/') {
+        Some(anchored) => anchored.to_string(),
+        None if pattern.contains('/') => pattern.to_string(),
+        None => format!("**/{pattern}"),
+    };
+    match directory {
+        true => glob::matches(&format!("{pattern}/**"), relative),
+        false => {
+            glob::matches(&pattern, relative) || glob::matches(&format!("{pattern}/**"), relative)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_ignored, pattern_matches, IGNORE_FILE_NAME};
+
+    #[test]
+    fn matches_gitignore_style_patterns() {
+        assert!(pattern_matches("*.tcl", "a.tcl"));
+        assert!(pattern_matches("*.tcl", "lib/deep/a.tcl"));
+        assert!(pattern_matches("/a.tcl", "a.tcl"));
+        assert!(!pattern_matches("/a.tcl", "lib/a.tcl"));
+        assert!(pattern_matches("vendor/", "vendor/x/a.tcl"));
+        assert!(!pattern_matches("vendor/", "vendor.tcl"));
+        assert!(pattern_matches("lib/gen", "lib/gen/a.tcl"));
+        assert!(!pattern_matches("lib/gen", "other/lib/gen/a.tcl"));
+    }
+
+    #[test]
+    fn applies_every_ancestor_ignore_file() {
+        let root = std::env::temp_dir().join(format!("tclfmt-ignore-{}", std::process::id()));
+        let nested = root.join("nested");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::write(
+            root.join(IGNORE_FILE_NAME),
+            "# generated\n*.gen.tcl\nvendor/\n",
+        )
+        .unwrap();
+        std::fs::write(nested.join(IGNORE_FILE_NAME), "!keep.gen.tcl\n").unwrap();
+
+        // the files need not exist, as with a `--stdin-filepath` hint
+        assert!(is_ignored(&root.join("a.gen.tcl")).unwrap());
+        assert!(is_ignored(&root.join("vendor/lib/a.tcl")).unwrap());
+        assert!(is_ignored(&nested.join("b.gen.tcl")).unwrap());
+        assert!(!is_ignored(&nested.join("keep.gen.tcl")).unwrap());
+        assert!(!is_ignored(&root.join("a.tcl")).unwrap());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,160 @@
+// The `# tclfmt: keep-sorted` directive. The run of statements right after
+// it, up to the first empty line or block, is kept in alphabetical order;
+// before a `switch`, its arms are. Comments move with the statement they
+// document, and fallthrough arms with the arm whose body they share, and the
+// `default` arm goes last.
+use crate::{
+    ast::{Ast, Text},
+    comments::attach,
+    visit::{walk_block_mut, VisitMut},
+};
+
+pub const DIRECTIVE: &[u8] = b"tclfmt: keep-sorted";
+
+pub fn keep_sorted(ast: &mut Ast) {
+    KeepSorted.visit_ast_mut(ast);
+}
+
+struct KeepSorted;
+
+impl VisitMut for KeepSorted {
+    fn visit_block_mut(&mut self, trees: &mut Vec<Ast>) {
+        walk_block_mut(self, trees);
+
+        let mut idx = 0;
+        while idx < trees.len() {
+            idx += 1;
+            if !matches!(&trees[idx - 1], Ast::Comment(text) if **text == *DIRECTIVE) {
+                continue;
+            }
+            if let Some(Ast::Switch {
+                value_block_or_fallthrough_vec: arms,
+                ..
+            }) = trees.get_mut(idx)
+            {
+                sort_arms(arms);
+                continue;
+            }
+            let mut end = idx
+                + trees[idx..]
+                    .iter()
+                    .position(|tree| {
+                        !matches!(
+                            tree,
+                            Ast::Statement(_) | Ast::Comment(_) | Ast::TrailingComment(_)
+                        )
+                    })
+                    .unwrap_or(trees.len() - idx);
+            // comments ending the run document the block after it
+            if trees
+                .get(end)
+                .is_some_and(|tree| !matches!(tree, Ast::EmptyLine))
+            {
+                while end > idx && matches!(trees[end - 1], Ast::Comment(_)) {
+                    end -= 1;
+                }
+            }
+            sort_statements(trees, idx..end);
+            idx = end;
+        }
+    }
+}
+
+fn sort_statements(trees: &mut Vec<Ast>, range: std::ops::Range<usize>) {
+    let mut groups = attach(trees.drain(range.clone()).collect());
+    // comments documenting nothing stay at the end of the run
+    let free = groups
+        .iter()
+        .position(|g| g.is_comment())
+        .unwrap_or(groups.len());
+    let free = groups.split_off(free);
+    groups.sort_by_cached_key(|group| match &group.node {
+        Ast::Statement(s) => s.words().join(&b' '),
+        _ => Vec::new(),
+    });
+    let sorted = groups.into_iter().chain(free).flat_map(|g| g.into_trees());
+    trees.splice(range.start..range.start, sorted);
+}
+
+// Sorts arms by pattern, keeping `default` last. Patterns falling through
+// to the same body are sorted among themselves, the body staying last.
+pub(crate) fn sort_arms(arms: &mut Vec<(Text, Option<Ast>)>) {
+    let mut groups: Vec<Vec<(Text, Option<Ast>)>> = Vec::new();
+    let mut open = false; // the last group still waits for its body
+    for arm in arms.drain(..) {
+        match groups.last_mut() {
+            Some(group) if open => group.push(arm),
+            _ => groups.push(vec![arm]),
+        }
+        open = groups
+            .last()
+            .is_some_and(|g| g.last().is_some_and(|a| a.1.is_none()));
+    }
+    let default = groups
+        .iter()
+        .position(|group| group.iter().any(|(value, _)| **value == *b"default"))
+        .map(|idx| groups.remove(idx));
+    for group in &mut groups {
+        let body = group.last_mut().and_then(|arm| arm.1.take());
+        group.sort_by_cached_key(|(value, _)| pattern(value).to_vec());
+        if let Some(last) = group.last_mut() {
+            last.1 = body;
+        }
+    }
+    groups.sort_by_cached_key(|group| pattern(&group[0].0).to_vec());
+    arms.extend(groups.into_iter().chain(default).flatten());
+}
+
+// an arm pattern without the quotes or braces around it
+fn pattern(value: &[u8]) -> &[u8] {
+    match value {
+        [b'"', inner @ .., b'"'] | [b'{', inner @ .., b'}'] => inner,
+        _ => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::keep_sorted;
+    use crate::{formatter::Formatter, lexer::Lexer, parser::Parser};
+
+    fn sorted(source: &str) -> String {
+        let (tokens, spans) = Lexer::new().lex_with_spans(source.as_bytes()).unwrap();
+        let mut ast = Parser::new(source.as_bytes(), &tokens, &spans)
+            .parse()
+            .unwrap();
+        keep_sorted(&mut ast);
+        String::from_utf8(Formatter::new().format(ast)).unwrap()
+    }
+
+    #[test]
+    fn sorts_statements_up_to_an_empty_line() {
+        let source = "# tclfmt: keep-sorted\nset c 3\n# about b\nset b 2\nset a 1 ;# first\n\nset z 0\nset y 0\n";
+        assert_eq!(
+            sorted(source),
+            "# tclfmt: keep-sorted\nset a 1 ;# first\n# about b\nset b 2\nset c 3\n\nset z 0\nset y 0\n"
+        );
+    }
+
+    #[test]
+    fn leaves_comments_before_a_block_in_place() {
+        let source =
+            "# tclfmt: keep-sorted\nset b 2\nset a 1\n# about p\nproc p {} {\n    set c 1\n}\n";
+        assert_eq!(
+            sorted(source),
+            "# tclfmt: keep-sorted\nset a 1\nset b 2\n# about p\nproc p { } {\n    set c 1\n}\n"
+        );
+    }
+
+    #[test]
+    fn sorts_switch_arms_keeping_fallthrough_and_default() {
+        let source = "# tclfmt: keep-sorted\nswitch $a {\ndefault {\nset b 0\n}\n\"q\" -\nm {\nset b 1\n}\nc {\nset b 2\n}\n}\n";
+        let formatted = sorted(source);
+        let arms: Vec<&str> = formatted
+            .lines()
+            .filter(|line| line.starts_with("    ") && !line.starts_with("        "))
+            .map(str::trim)
+            .collect();
+        assert_eq!(arms, ["c {", "}", "m -", "\"q\" {", "}", "default {", "}"]);
+    }
+}
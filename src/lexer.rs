@@ -1,154 +1,216 @@
-use std::io::BufRead;
-
-pub enum Token {
-    KeywordSet,          // set
-    KeywordProc,         // proc
-    KeywordIf,           // if
-    KeywordElseIf,       // elseif
-    KeywordElse,         // else
-    KeywordSwitch,       // switch
-    KeywordLog,          // log
-    KeywordSnat,         // snat
-    KeywordNode,         // node
-    KeywordPool,         // pool
-    KeywordSnatPool,     // snatpool
-    KeywordReturn,       // return
-    KeywordWhen,         // when
-    DoubleColon,         // ::
-    Colon,               // :
-    LCurlyBracket,       // {
-    RCurlyBracket,       // }
-    LSquareBracket,      // [
-    RSquareBracket,      // ]
-    LParen,              // (
-    RParen,              // )
-    LAngleBracket,       // <
-    RAngleBracket,       // >
-    Dollar,              // $
-    Quote,               // "
-    Hash,                // #
-    Minus,               // -
-    Plus,                // +
-    Equals,              // =
-    Modulo,              // %
-    Star,                // *
-    Bang,                // !
-    Ampersand,           // &
-    Newline,             // \n
-    Identifier(Vec<u8>), // [a-zA-Z0-9_\.]+
-    Other(Vec<u8>),      // <lazy>
+use std::ops::Range;
+
+pub enum Token<'src> {
+    KeywordSet,             // set
+    KeywordProc,            // proc
+    KeywordIf,              // if
+    KeywordElseIf,          // elseif
+    KeywordElse,            // else
+    KeywordSwitch,          // switch
+    KeywordLog,             // log
+    KeywordSnat,            // snat
+    KeywordNode,            // node
+    KeywordPool,            // pool
+    KeywordSnatPool,        // snatpool
+    KeywordReturn,          // return
+    KeywordWhen,            // when
+    DoubleColon,            // ::
+    Colon,                  // :
+    LCurlyBracket,          // {
+    RCurlyBracket,          // }
+    LSquareBracket,         // [
+    RSquareBracket,         // ]
+    LParen,                 // (
+    RParen,                 // )
+    LAngleBracket,          // <
+    RAngleBracket,          // >
+    Dollar,                 // $
+    Quote,                  // "
+    Hash,                   // #
+    Minus,                  // -
+    Plus,                   // +
+    Equals,                 // =
+    Modulo,                 // %
+    Star,                   // *
+    Slash,                  // /
+    Bang,                   // !
+    Ampersand,              // &
+    Newline,                // \n
+    Identifier(&'src [u8]), // [a-zA-Z0-9_\.\x80-\xff]+(::[a-zA-Z0-9_\.\x80-\xff]+)*
+    Other(&'src [u8]),      // <lazy>
 }
 
-impl Token {
-    fn is_keyword(&self) -> bool {
-        match self {
+impl Token<'_> {
+    pub(crate) fn is_keyword(&self) -> bool {
+        matches!(
+            self,
             Token::KeywordSet
-            | Token::KeywordProc
-            | Token::KeywordIf
-            | Token::KeywordElseIf
-            | Token::KeywordElse
-            | Token::KeywordSwitch
-            | Token::KeywordLog
-            | Token::KeywordSnat
-            | Token::KeywordNode
-            | Token::KeywordPool
-            | Token::KeywordSnatPool
-            | Token::KeywordReturn
-            | Token::KeywordWhen => true,
-            _ => false,
-        }
+                | Token::KeywordProc
+                | Token::KeywordIf
+                | Token::KeywordElseIf
+                | Token::KeywordElse
+                | Token::KeywordSwitch
+                | Token::KeywordLog
+                | Token::KeywordSnat
+                | Token::KeywordNode
+                | Token::KeywordPool
+                | Token::KeywordSnatPool
+                | Token::KeywordReturn
+                | Token::KeywordWhen
+        )
     }
 
-    fn is_operator(&self) -> bool {
-        match self {
+    pub(crate) fn is_operator(&self) -> bool {
+        matches!(
+            self,
             Token::DoubleColon
-            | Token::Minus
-            | Token::Plus
-            | Token::Equals
-            | Token::Modulo
-            | Token::Star
-            | Token::Ampersand => true,
-            _ => false,
-        }
+                | Token::Minus
+                | Token::Plus
+                | Token::Equals
+                | Token::Modulo
+                | Token::Star
+                | Token::Slash
+                | Token::Ampersand
+        )
     }
 
-    fn is_symbol(&self) -> bool {
-        match self {
-            Token::Colon | Token::Dollar | Token::Quote | Token::Bang => true,
-            _ => false,
-        }
+    pub(crate) fn is_symbol(&self) -> bool {
+        matches!(
+            self,
+            Token::Colon | Token::Dollar | Token::Quote | Token::Bang
+        )
     }
 
-    fn is_bracket(&self) -> bool {
-        match self {
+    pub(crate) fn is_bracket(&self) -> bool {
+        matches!(
+            self,
             Token::LCurlyBracket
-            | Token::RCurlyBracket
-            | Token::LSquareBracket
-            | Token::RSquareBracket
-            | Token::LParen
-            | Token::RParen
-            | Token::LAngleBracket
-            | Token::RAngleBracket => true,
-            _ => false,
-        }
+                | Token::RCurlyBracket
+                | Token::LSquareBracket
+                | Token::RSquareBracket
+                | Token::LParen
+                | Token::RParen
+                | Token::LAngleBracket
+                | Token::RAngleBracket
+        )
     }
 }
 
 #[derive(Debug)]
 pub enum LexerFail {
-    ExpectedString, // when parsing log
+    ExpectedString,         // when parsing log
+    UnexpectedInput(usize), // byte offset no token could be read at
 }
 
 type Result<T> = std::result::Result<T, LexerFail>;
 
-pub struct Lexer {
-    tokens: Vec<Token>,
+pub struct Lexer<'src> {
+    tokens: Vec<Token<'src>>,
+    spans: Vec<Range<usize>>, // byte range of each token in the input
 }
 
-impl Lexer {
+impl Default for Lexer<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'src> Lexer<'src> {
     pub fn new() -> Self {
-        Self { tokens: Vec::new() }
+        Self {
+            tokens: Vec::new(),
+            spans: Vec::new(),
+        }
     }
 
-    pub fn lex(mut self, buf: Vec<u8>) -> Result<Vec<Token>> {
-        for line in buf.lines() {
-            self.lex_line(&Lexer::normalize(line.unwrap().as_bytes()))?; // lstrip & rstrip
-            self.tokens.push(Token::Newline);
+    pub fn lex(self, buf: &'src [u8]) -> Result<Vec<Token<'src>>> {
+        Ok(self.lex_with_spans(buf)?.0)
+    }
+
+    // like `lex`, additionally returning the byte range each token covers in `buf`
+    pub fn lex_with_spans(
+        mut self,
+        buf: &'src [u8],
+    ) -> Result<(Vec<Token<'src>>, Vec<Range<usize>>)> {
+        let mut offset = 0;
+        for line in buf.split_inclusive(|&c| c == b'\n') {
+            let content = line.strip_suffix(b"\n").unwrap_or(line);
+            let content = content.strip_suffix(b"\r").unwrap_or(content);
+            self.lex_line(content, offset)?;
+            self.push(Token::Newline, offset + content.len()..offset + line.len());
+            offset += line.len();
         }
 
-        Ok(self.tokens)
+        Ok((self.tokens, self.spans))
+    }
+
+    fn push(&mut self, token: Token<'src>, span: Range<usize>) {
+        self.tokens.push(token);
+        self.spans.push(span);
     }
 
-    fn lex_line(&mut self, mut line: &[u8]) -> Result<()> {
+    fn lex_line(&mut self, line: &'src [u8], offset: usize) -> Result<()> {
+        // lstrip & rstrip
+        let leading = leading_whitespace(line);
+        let trailing = line[leading..]
+            .iter()
+            .rev()
+            .take_while(|&&x| is_whitespace_or_semicolon(x))
+            .count();
+        let mut line = &line[leading..line.len() - trailing];
+        let mut pos = offset + leading;
+        let (stripped, line_start, first_token) = (line, pos, self.tokens.len());
+
         while let Some((token, consumed)) = self.try_lex(line) {
-            self.tokens.push(token);
+            self.push(token, pos + leading_whitespace(line)..pos + consumed);
             line = &line[consumed..];
+            pos += consumed;
 
             let n = self.tokens.len().saturating_sub(1);
             match (self.tokens.get(n.saturating_sub(1)), self.tokens.get(n)) {
                 (_, Some(Token::Hash)) => {
-                    self.tokens.push(Token::Other(Lexer::normalize(&line))); // lstrip only
+                    // `cmd ;# comment` ends the command, like a newline would
+                    let hash = self.spans[n].start;
+                    if n > first_token && is_trailing_comment(&stripped[..hash - line_start]) {
+                        self.tokens.insert(n, Token::Newline);
+                        self.spans.insert(n, hash..hash);
+                    }
+                    let start = pos + leading_whitespace(line);
+                    let end = pos + line.len();
+                    self.push(Token::Other(Lexer::normalize(line)), start..end);
                     return Ok(());
                 }
-                (Some(Token::KeywordLog), Some(Token::Identifier(_))) => {
+                // a quoted log message is one token, whatever it holds
+                (_, Some(Token::Identifier(_)))
+                    if self.follows_log(n) && line.trim_ascii_start().starts_with(b"\"") =>
+                {
                     let (value, consumed) =
                         Lexer::extract_string(line).ok_or(LexerFail::ExpectedString)?;
-                    self.tokens.push(Token::Other(value.to_vec()));
+                    let span = pos + consumed - value.len()..pos + consumed;
+                    self.push(Token::Other(value), span);
                     line = &line[consumed..];
+                    pos += consumed;
                 }
                 _ => {}
             }
         }
         if !line.is_empty() {
-            println!("lexer: failed to read next");
-            dbg!(self.tokens.last());
-            dbg!(String::from_utf8_lossy(line));
-            unreachable!();
+            return Err(LexerFail::UnexpectedInput(pos));
         }
         Ok(())
     }
 
-    fn try_lex(&self, line: &[u8]) -> Option<(Token, usize)> {
+    // whether the token at `idx` is among the words between `log` and its
+    // message: options, the remote destination and the facility and level
+    fn follows_log(&self, idx: usize) -> bool {
+        self.tokens[..=idx]
+            .iter()
+            .rev()
+            .find(|t| !matches!(t, Token::Identifier(_) | Token::Minus | Token::Colon))
+            .is_some_and(|t| matches!(t, Token::KeywordLog))
+    }
+
+    fn try_lex(&self, line: &'src [u8]) -> Option<(Token<'src>, usize)> {
         let consumed = line
             .iter()
             .take_while(|&&x| is_whitespace_or_semicolon(x))
@@ -185,15 +247,17 @@ impl Lexer {
             x if x.starts_with(b"=") => Some((Token::Equals, 1)),
             x if x.starts_with(b"%") => Some((Token::Modulo, 1)),
             x if x.starts_with(b"*") => Some((Token::Star, 1)),
+            x if x.starts_with(b"/") => Some((Token::Slash, 1)),
             x if x.starts_with(b"!") => Some((Token::Bang, 1)),
             x if x.starts_with(b"&") => Some((Token::Ampersand, 1)),
             x if x.starts_with(b":") => Some((Token::Colon, 1)),
             b"" => None,
             x => {
-                let identifier = Lexer::extract_identifier(x);
+                let identifier = Lexer::extract_namespaced_identifier(x);
                 let len = identifier.len();
                 if len == 0 {
-                    None
+                    // any other character, like `/` or `\`, passes through as is
+                    Some((Token::Other(&x[..1]), 1))
                 } else {
                     Some((Token::Identifier(identifier), len))
                 }
@@ -202,26 +266,37 @@ impl Lexer {
         .map(|(t, c)| (t, c + consumed))
     }
 
-    // TODO: normalize = lstrip + rstrip
-    fn normalize(line: &[u8]) -> Vec<u8> {
-        let mut buf: Vec<u8> = line
-            .into_iter()
+    // lstrip + rstrip
+    fn normalize(line: &[u8]) -> &[u8] {
+        let leading = leading_whitespace(line);
+        let trailing = line[leading..]
+            .iter()
             .rev()
-            .skip_while(|&&x| is_whitespace_or_semicolon(x))
-            .copied()
-            .collect();
-        buf.reverse();
-        buf.into_iter()
-            .skip_while(|&x| is_whitespace_or_semicolon(x))
-            .collect()
+            .take_while(|&&x| is_whitespace_or_semicolon(x))
+            .count();
+        &line[leading..line.len() - trailing]
     }
 
-    fn extract_identifier(line: &[u8]) -> Vec<u8> {
-        // assume line is lstripped
-        line.into_iter()
-            .take_while(|&&x| x.is_ascii_alphanumeric() || x == b'_' || x == b'.')
-            .cloned()
-            .collect()
+    fn extract_identifier(line: &[u8]) -> &[u8] {
+        // assume line is lstripped; non-ASCII bytes are taken whole, so a
+        // multi-byte UTF-8 character never straddles two tokens
+        let len = line
+            .iter()
+            .take_while(|&&x| x.is_ascii_alphanumeric() || x == b'_' || x == b'.' || !x.is_ascii())
+            .count();
+        &line[..len]
+    }
+
+    // `HTTP::host` and `a::b::c` are one word, never split at `::`
+    fn extract_namespaced_identifier(line: &[u8]) -> &[u8] {
+        let mut len = Lexer::extract_identifier(line).len();
+        while len > 0 && line[len..].starts_with(b"::") {
+            match Lexer::extract_identifier(&line[len + 2..]).len() {
+                0 => break,
+                n => len += 2 + n,
+            }
+        }
+        &line[..len]
     }
 
     fn extract_string(mut data: &[u8]) -> Option<(&[u8], usize)> {
@@ -231,7 +306,9 @@ impl Lexer {
             .count();
         data = &data[consumed..];
 
-        let Some(b'"') = data.get(0) else { return None };
+        let Some(b'"') = data.first() else {
+            return None;
+        };
         let inside_len = data.iter().skip(1).take_while(|&&c| c != b'"').count();
         match data.get(inside_len + 1) {
             Some(b'"') => Some((&data[..=inside_len + 1], consumed + inside_len + 2)),
@@ -240,15 +317,30 @@ impl Lexer {
     }
 }
 
+// whether a `#` after `before` on the same line starts a comment: `;` ends
+// the command before it, and it isn't inside a quoted word
+fn is_trailing_comment(before: &[u8]) -> bool {
+    let quotes = before
+        .iter()
+        .enumerate()
+        .filter(|&(idx, &c)| c == b'"' && (idx == 0 || before[idx - 1] != b'\\'))
+        .count();
+    let before = before.trim_ascii_end();
+    before.ends_with(b";") && quotes % 2 == 0
+}
+
+fn leading_whitespace(line: &[u8]) -> usize {
+    line.iter()
+        .take_while(|&&x| is_whitespace_or_semicolon(x))
+        .count()
+}
+
 fn is_whitespace_or_semicolon(symbol: u8) -> bool {
-    match symbol {
-        b' ' | b'\t' | b';' => true,
-        _ => false,
-    }
+    matches!(symbol, b' ' | b'\t' | b';')
 }
 
-impl From<&Token> for Vec<u8> {
-    fn from(val: &Token) -> Self {
+impl From<&Token<'_>> for Vec<u8> {
+    fn from(val: &Token<'_>) -> Self {
         match val {
             Token::KeywordSet => b"set".to_vec(),
             Token::KeywordProc => b"proc".to_vec(),
@@ -283,6 +375,7 @@ impl From<&Token> for Vec<u8> {
             Token::Equals => b"=".to_vec(),
             Token::Modulo => b"%".to_vec(),
             Token::Star => b"*".to_vec(),
+            Token::Slash => b"/".to_vec(),
             Token::Bang => b"!".to_vec(),
             Token::Ampersand => b"&".to_vec(),
             Token::Colon => b":".to_vec(),
@@ -290,9 +383,9 @@ impl From<&Token> for Vec<u8> {
     }
 }
 
-impl std::fmt::Debug for Token {
+impl std::fmt::Debug for Token<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let s = String::from_utf8(Vec::from(self)).expect("Failed to utf8 decode");
+        let s = String::from_utf8_lossy(&Vec::from(self)).into_owned();
         match self {
             x if x.is_keyword() => write!(f, "kwrd:  \u{001b}[31m{}\u{001b}[0m", s),
             x if x.is_symbol() => write!(f, "sym:   \u{001b}[32m{}\u{001b}[0m", s),
@@ -302,10 +395,76 @@ impl std::fmt::Debug for Token {
             Self::Other(_) => write!(f, "other: \u{001b}[36m{}\u{001b}[0m", s),
             Self::Identifier(_) => write!(f, "ident: {}", s),
             Self::Hash => write!(f, "hash:  \u{001b}[32m{}\u{001b}[0m", s),
-            _ => {
-                println!("{}", s);
-                unreachable!()
+            _ => write!(f, "{}", s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_trailing_comment, Lexer, Token};
+
+    #[test]
+    fn spans_cover_borrowed_token_text() {
+        let source =
+            b"when HTTP_REQUEST {\n    set host [HTTP::host]\r\n    log local0. \"to $host\"\n}\n";
+        let (tokens, spans) = Lexer::new().lex_with_spans(source).unwrap();
+        assert_eq!(tokens.len(), spans.len());
+        for (token, span) in tokens.iter().zip(&spans) {
+            match token {
+                // `\r\n` is one line break
+                Token::Newline => assert!(matches!(&source[span.clone()], b"\n" | b"\r\n")),
+                Token::Identifier(text) | Token::Other(text) => {
+                    assert_eq!(text.as_ptr(), source[span.start..].as_ptr());
+                    assert_eq!(*text, &source[span.clone()]);
+                }
+                token => assert_eq!(Vec::from(token), &source[span.clone()]),
             }
         }
+        assert!(tokens
+            .iter()
+            .any(|token| matches!(token, Token::Identifier(b"HTTP::host"))));
+    }
+
+    #[test]
+    fn keeps_multi_byte_characters_in_one_token() {
+        let source = "set größe 日本\n".as_bytes();
+        let tokens = Lexer::new().lex(source).unwrap();
+        let words: Vec<Vec<u8>> = tokens.iter().map(Vec::from).collect();
+        assert_eq!(
+            words,
+            [
+                b"set".to_vec(),
+                "größe".as_bytes().to_vec(),
+                "日本".as_bytes().to_vec(),
+                b"\n".to_vec()
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_trailing_comments_outside_quotes() {
+        assert!(is_trailing_comment(b"set a 1 ;"));
+        assert!(is_trailing_comment(b"set a \"x\";"));
+        assert!(!is_trailing_comment(b"set a 1 "));
+        assert!(!is_trailing_comment(b"set a \"x;"));
+        assert!(!is_trailing_comment(b"set a \"x\\\";"));
+    }
+
+    #[test]
+    fn lexes_namespaced_commands_as_one_word() {
+        let tokens = Lexer::new().lex(b"HTTP::host a::b::c :: d::\n").unwrap();
+        let words: Vec<Vec<u8>> = tokens.iter().map(Vec::from).collect();
+        assert_eq!(
+            words,
+            [
+                b"HTTP::host".to_vec(),
+                b"a::b::c".to_vec(),
+                b"::".to_vec(),
+                b"d".to_vec(),
+                b"::".to_vec(),
+                b"\n".to_vec()
+            ]
+        );
     }
 }
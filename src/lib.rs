@@ -0,0 +1,35 @@
+pub mod ast;
+pub mod cache;
+pub mod comments;
+pub mod complexity;
+pub mod config;
+pub mod diagnostic;
+pub mod diff;
+pub mod directive;
+pub mod editorconfig;
+pub mod embed;
+pub mod emitter;
+pub mod encoding;
+pub mod export;
+pub mod fix;
+pub mod formatter;
+pub mod glob;
+pub mod highlight;
+pub mod ignore;
+mod json;
+pub mod keep_sorted;
+pub mod lexer;
+pub mod lint;
+pub mod minifier;
+pub mod modeline;
+pub mod namespace;
+pub mod parser;
+pub mod passes;
+pub mod report;
+pub mod sarif;
+pub mod stats;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod verify;
+pub mod visit;
+pub mod width;
@@ -0,0 +1,204 @@
+// Lint rules over the parsed AST. Rules are disabled unless configured under
+// `[lint]` in `.tclfmt.toml`.
+use crate::{
+    ast::{Ast, Condition, Statement},
+    complexity::complexity,
+    diagnostic::{Diagnostic, Severity},
+    fix::Fix,
+    passes::{braceable, rewrite_substitutions, unbraced_expr},
+    visit::{walk_if, Visit},
+};
+
+// How a rule that a pass can fix is applied
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Level {
+    #[default]
+    Off,
+    Warn,
+    Fix, // apply its fixes without `--fix`
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct LintOptions {
+    pub max_complexity: Option<usize>, // max-complexity
+    pub unbraced_expr: Level,          // unbraced-expr
+}
+
+impl LintOptions {
+    // whether the fixes of `rule` are applied without `--fix`
+    pub fn fixes(&self, rule: &str) -> bool {
+        match rule {
+            "unbraced-expr" => self.unbraced_expr == Level::Fix,
+            _ => false,
+        }
+    }
+}
+
+pub fn lint(ast: &Ast, options: &LintOptions) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    if let Some(max) = options.max_complexity {
+        for (name, value) in complexity(ast) {
+            if value > max {
+                diagnostics.push(Diagnostic {
+                    rule: "max-complexity",
+                    severity: Severity::Warning,
+                    message: format!("{name} has a cyclomatic complexity of {value} (max {max})"),
+                    span: None,
+                    snippet: None,
+                    fix: None,
+                });
+            }
+        }
+    }
+    if options.unbraced_expr != Level::Off {
+        let mut finder = UnbracedExpressions {
+            diagnostics: &mut diagnostics,
+        };
+        finder.visit_ast(ast);
+    }
+    diagnostics
+}
+
+// Unbraced `if` conditions and `expr` arguments are substituted twice, once
+// by the parser and again by `expr`, and can't be compiled to bytecode
+struct UnbracedExpressions<'a> {
+    diagnostics: &'a mut Vec<Diagnostic>,
+}
+
+impl UnbracedExpressions<'_> {
+    fn report(&mut self, what: &str, source: &[u8], fixable: bool) {
+        let hint = match fixable {
+            true => "`--fix` braces it",
+            false => "bracing it would change its meaning, check it by hand",
+        };
+        self.diagnostics.push(Diagnostic {
+            rule: "unbraced-expr",
+            severity: Severity::Warning,
+            message: format!(
+                "{what} `{}` is substituted twice and not compiled; {hint}",
+                String::from_utf8_lossy(source)
+            ),
+            span: None,
+            snippet: None,
+            fix: fixable.then_some(Fix::Pass("brace-conditions")),
+        });
+    }
+
+    fn find_exprs(&mut self, source: &[u8]) {
+        rewrite_substitutions(source, &mut |command| {
+            if let Some(arguments) = unbraced_expr(command) {
+                let fixable = braceable(&arguments.join(&b' ')).is_some();
+                self.report("unbraced `expr`", command, fixable);
+            }
+            None
+        });
+    }
+}
+
+impl Visit for UnbracedExpressions<'_> {
+    fn visit_if(
+        &mut self,
+        condition_body_clauses: &[(Condition, Ast)],
+        block_if_false: Option<&Ast>,
+    ) {
+        for (condition, _) in condition_body_clauses {
+            if !condition.braced {
+                let fixable = braceable(&condition.text).is_some();
+                self.report("unbraced condition", &condition.text, fixable);
+            }
+            self.find_exprs(&condition.text);
+        }
+        walk_if(self, condition_body_clauses, block_if_false);
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        for word in statement.words() {
+            self.find_exprs(word);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lint, Level, LintOptions};
+    use crate::{ast::Ast, fix::Fix, lexer::Lexer, parser::Parser};
+
+    fn parse(source: &[u8]) -> Ast<'_> {
+        let (tokens, spans) = Lexer::new().lex_with_spans(source).unwrap();
+        Parser::new(source, &tokens, &spans).parse().unwrap()
+    }
+
+    #[test]
+    fn rules_are_off_by_default() {
+        let source = b"proc p {} {\n    if $a {\n        set b [expr $c + 1]\n    }\n}\n";
+        assert!(lint(&parse(source), &LintOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn reports_complexity_over_the_limit() {
+        let source = b"proc p {} {\n    if {$a} {\n        return 1\n    }\n}\nproc q {} {\n}\n";
+        let options = LintOptions {
+            max_complexity: Some(1),
+            ..LintOptions::default()
+        };
+        let diagnostics = lint(&parse(source), &options);
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            ["proc p has a cyclomatic complexity of 2 (max 1)"]
+        );
+    }
+
+    #[test]
+    fn reports_unbraced_expressions() {
+        let source = b"if $a {\n    set b [expr $c + 1]\n}\nif \"$d\" {\n    set e [expr {$f}]\n}\nset g [expr \"$h{\"]\n";
+        let options = LintOptions {
+            unbraced_expr: Level::Warn,
+            ..LintOptions::default()
+        };
+        let diagnostics = lint(&parse(source), &options);
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "unbraced condition `$a` is substituted twice and not compiled; `--fix` braces it",
+                "unbraced `expr` `expr $c + 1` is substituted twice and not compiled; `--fix` braces it",
+                "unbraced condition `\"$d\"` is substituted twice and not compiled; `--fix` braces it",
+                "unbraced `expr` `expr \"$h{\"` is substituted twice and not compiled; bracing it would change its meaning, check it by hand"
+            ]
+        );
+        assert!(diagnostics.iter().all(|d| d.rule == "unbraced-expr"));
+    }
+
+    #[test]
+    fn attaches_the_brace_conditions_pass_where_it_is_safe() {
+        let source = b"if $a {\n    set b [expr $c + 1]\n}\nset g [expr \"$h{\"]\n";
+        let options = LintOptions {
+            unbraced_expr: Level::Warn,
+            ..LintOptions::default()
+        };
+        let fixes: Vec<_> = lint(&parse(source), &options)
+            .into_iter()
+            .map(|d| d.fix)
+            .collect();
+        assert_eq!(
+            fixes,
+            [
+                Some(Fix::Pass("brace-conditions")),
+                Some(Fix::Pass("brace-conditions")),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn fixes_only_rules_set_to_fix() {
+        let mut options = LintOptions::default();
+        assert!(!options.fixes("unbraced-expr"));
+        options.unbraced_expr = Level::Warn;
+        assert!(!options.fixes("unbraced-expr"));
+        options.unbraced_expr = Level::Fix;
+        assert!(options.fixes("unbraced-expr"));
+        assert!(!options.fixes("max-complexity"));
+    }
+}
@@ -1,40 +1,797 @@
-use std::io;
-
-mod ast;
-mod formatter;
-mod lexer;
-mod parser;
-
-use formatter::*;
-use lexer::*;
-use parser::*;
-
-fn main() -> io::Result<()> {
-    for path in [
-        "../a1-gtp-proxy/src/GTP-C-INIT_V5.tcl",
-        "../a1-gtp-proxy/src/GTP-C-clientAcceptV5.tcl",
-        "../a1-gtp-proxy/src/GTP-C-clientAcceptv3.tcl",
-        "../a1-gtp-proxy/src/GTP-C-clientAcceptv31.tcl",
-        "../a1-gtp-proxy/src/GTP-C-clientEgressV5.tcl",
-        "../a1-gtp-proxy/src/GTP-C-clientEgressv3.tcl",
-        "../a1-gtp-proxy/src/GTP-C-clientIngressV5.tcl",
-        "../a1-gtp-proxy/src/GTP-C-clientIngressv3.tcl",
-        "../a1-gtp-proxy/src/GTP-C-clientIngressv31.tcl",
-        "../a1-gtp-proxy/src/GTP-C-variables_V5.tcl",
-        "../a1-gtp-proxy/src/GTP-U_v4.tcl",
-        "../a1-gtp-proxy/src/GTP-Uv3.tcl",
-        "../a1-gtp-proxy/src/GTP-Uv31.tcl",
-        "../a1-gtp-proxy/src/lib_GTPutil_V4.tcl",
-        "../a1-gtp-proxy/src/lib_GTPutil_V5.tcl",
-        "../a1-gtp-proxy/src/lib_GTPutilv3.tcl",
-        "../a1-gtp-proxy/src/lib_LogUtil.tcl",
-    ] {
-        println!("formatting {path}");
-        let buf = std::fs::read(path)?;
-        let tokens = Lexer::new().lex(buf).expect("Failed to lex");
-        let ast = Parser::new().parse(&tokens).expect("Failed to parse");
-        let buf = Formatter::new().format(ast); // cursed interface
-        std::fs::write(path, buf)?;
-    }
-    Ok(())
+use std::collections::BTreeSet;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use tcl_formatter::ast::Ast;
+use tcl_formatter::cache::{self, Cache, Fingerprint};
+use tcl_formatter::config::{Config, ConfigFail};
+use tcl_formatter::diagnostic::{first_difference, Diagnostic, Position, Severity, Span};
+use tcl_formatter::diff::UnifiedDiff;
+use tcl_formatter::directive;
+use tcl_formatter::editorconfig::EditorConfig;
+use tcl_formatter::embed::{self, Host, Region};
+use tcl_formatter::encoding;
+use tcl_formatter::export;
+use tcl_formatter::fix::{self, Fix};
+use tcl_formatter::formatter::*;
+use tcl_formatter::highlight::highlight;
+use tcl_formatter::ignore::is_ignored;
+use tcl_formatter::lexer::*;
+use tcl_formatter::lint::lint;
+use tcl_formatter::minifier::*;
+use tcl_formatter::modeline;
+use tcl_formatter::parser::*;
+use tcl_formatter::report::HtmlReport;
+use tcl_formatter::sarif;
+use tcl_formatter::stats::{self, Stats};
+use tcl_formatter::verify::{verify, VerifyFail};
+use tcl_formatter::width;
+
+mod cli;
+mod hook;
+mod watch;
+
+use cli::{Args, Color, Emit, Failure, Hook, MessageFormat, ReportFormat, StatsFormat};
+use watch::Watcher;
+
+const TCL_EXTENSIONS: &[&str] = &["tcl", "irule", "irul"];
+
+// Where an input is read from and formatted output goes back to
+enum Source {
+    File,
+    Stdin, // the path only locates config and ignore files
+    Index(hook::Staged),
+}
+
+fn main() {
+    let code = match try_main() {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {e}");
+            Failure::Io.exit_code()
+        }
+    };
+    std::process::exit(code);
+}
+
+// the exit status, see `Failure`
+fn try_main() -> io::Result<i32> {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {e}\n\n{}", cli::USAGE);
+            return Ok(Failure::Io.exit_code());
+        }
+    };
+
+    if args.hook == Some(Hook::Install) {
+        let path = hook::install()?;
+        eprintln!("installed {}", path.display());
+        return Ok(0);
+    }
+
+    let mut inputs: Vec<(PathBuf, Source)> = args
+        .paths
+        .iter()
+        .map(|path| (path.clone(), Source::File))
+        .chain(
+            args.stdin_filepath
+                .clone()
+                .map(|path| (path, Source::Stdin)),
+        )
+        .collect();
+    if args.staged {
+        for staged in hook::staged_files()? {
+            inputs.push((staged.path.clone(), Source::Index(staged)));
+        }
+    }
+
+    let color = |terminal: bool| match args.color {
+        Color::Always => true,
+        Color::Never => false,
+        Color::Auto => terminal && std::env::var_os("NO_COLOR").is_none(),
+    };
+    let mut run = Run {
+        reporter: Reporter {
+            format: args.message_format,
+            color: color(io::stderr().is_terminal()),
+            collected: Vec::new(),
+            html: args.report.as_ref().map(|_| HtmlReport::new()),
+        },
+        diff: args.diff.then(|| {
+            UnifiedDiff::new()
+                .word_level(args.word_diff)
+                .color(color(io::stdout().is_terminal()))
+        }),
+        stats_rows: Vec::new(),
+        failures: BTreeSet::new(),
+        cache: args.cache.as_deref().map(Cache::load),
+        bad_configs: Vec::new(),
+        args,
+    };
+    for (path, source) in inputs {
+        if let Err(e) = run.process(&path, source) {
+            run.io_error(&path, e);
+        }
+    }
+    if let Some(dir) = run.args.watch.clone() {
+        run.watch(&dir)?;
+    }
+
+    match run.args.stats {
+        Some(StatsFormat::Table) => print!("{}", stats::render_table(&run.stats_rows)),
+        Some(StatsFormat::Json) => print!("{}", stats::render_json(&run.stats_rows)),
+        None => {}
+    }
+    let code = run.exit_code();
+    if let (Some((ReportFormat::Html, path)), Some(html)) = (&run.args.report, &run.reporter.html) {
+        std::fs::write(path, html.render())?;
+    }
+    run.reporter.finish();
+    if let Some(cache) = &run.cache {
+        cache.save()?;
+    }
+    Ok(code)
+}
+
+// What became of one input
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Outcome {
+    Ignored,
+    Failed, // did not lex or parse, its config is invalid or it can't be read
+    Unchanged,
+    Reformatted,
+}
+
+struct Run {
+    args: Args,
+    reporter: Reporter,
+    stats_rows: Vec<(String, Stats)>,
+    failures: BTreeSet<Failure>, // what went wrong so far
+    diff: Option<UnifiedDiff>,
+    cache: Option<Cache>,
+    bad_configs: Vec<PathBuf>, // config files already reported as invalid
+}
+
+impl Run {
+    fn process(&mut self, path: &Path, source: Source) -> io::Result<Outcome> {
+        let args = &self.args;
+        let absolute = std::path::absolute(path)?;
+        if is_ignored(&absolute)? {
+            if matches!(source, Source::Stdin) && !args.check {
+                io::copy(&mut io::stdin(), &mut io::stdout())?;
+            }
+            return Ok(Outcome::Ignored);
+        }
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let mut config = match Config::find(dir) {
+            Some(config_path) => match Config::load(&config_path) {
+                Ok(config) => {
+                    let root = std::path::absolute(&config_path)?;
+                    let root = root.parent().unwrap_or(Path::new("/"));
+                    let relative = absolute.strip_prefix(root).unwrap_or(&absolute);
+                    config.for_path(&relative.to_string_lossy().replace('\\', "/"))
+                }
+                Err(e) => {
+                    self.config_error(config_path, e);
+                    return Ok(Outcome::Failed);
+                }
+            },
+            None => Config::default(),
+        };
+        if config.exclude {
+            if matches!(source, Source::Stdin) && !args.check {
+                io::copy(&mut io::stdin(), &mut io::stdout())?;
+            }
+            return Ok(Outcome::Ignored);
+        }
+        let mut editorconfig = if config.editorconfig {
+            EditorConfig::resolve(&absolute)?
+        } else {
+            EditorConfig::default()
+        };
+        let raw = match &source {
+            Source::File => std::fs::read(path)?,
+            Source::Stdin => {
+                let mut buf = Vec::new();
+                io::stdin().read_to_end(&mut buf)?;
+                buf
+            }
+            Source::Index(staged) => staged.read()?,
+        };
+        let decoded = encoding::decode(&raw, config.encoding);
+        let input = &decoded.text[..];
+        let host = Host::detect(path, input);
+        if host.is_none() {
+            if let Err(e) = directive::configure(&mut config, input) {
+                self.failures.insert(Failure::Parse);
+                let file = path.display().to_string();
+                self.reporter.report(&file, directive_error(input, e));
+                return Ok(Outcome::Failed);
+            }
+        }
+        let encode = |text: &[u8]| decoded.encode(text, config.keep_bom);
+        if config.modelines {
+            let hints = modeline::indent_hints(input);
+            editorconfig.use_tabs = hints.use_tabs.or(editorconfig.use_tabs);
+            editorconfig.indent_width = hints.indent_width.or(editorconfig.indent_width);
+        }
+        let options = config
+            .formatter_options(args.style.as_deref(), &editorconfig)
+            .expect("Failed to resolve formatter options");
+        if let Some(host) = host {
+            // only `--emit formatted` applies to host documents
+            if args.emit != Emit::Formatted || args.stats.is_some() {
+                return Ok(Outcome::Ignored);
+            }
+            let regions = embed::regions(host, input, &config.embed_paths);
+            let Some(formatted) = self.format_embedded(path, input, &regions, &config, options)
+            else {
+                return Ok(Outcome::Failed);
+            };
+            let buf = encode(&embed::splice(input, &regions, formatted));
+            return self.write_embedded(path, source, &raw, buf);
+        }
+        // only runs that format are cached, and stdin must be echoed anyway
+        let fingerprint = Fingerprint::new(&config, &options, args.fix).to_string();
+        let cache = self.cache.as_mut().filter(|_| {
+            args.emit == Emit::Formatted && args.stats.is_none() && !matches!(source, Source::Stdin)
+        });
+        if cache
+            .as_ref()
+            .is_some_and(|cache| cache.contains(cache::key(&raw, &fingerprint)))
+        {
+            return Ok(Outcome::Unchanged);
+        }
+        let file = path.display().to_string();
+        let failures = &mut self.failures;
+        let reporter = &mut self.reporter;
+        // files with diagnostics are not cached, so they are reported again
+        let (mut clean, mut errors) = (true, false);
+        let mut report = |d: Diagnostic| {
+            errors |= d.severity == Severity::Error;
+            clean = false;
+            reporter.report(&file, d);
+        };
+        if args.stats.is_some() {
+            let Ok(ast) = parse_source(input, &config, &mut report) else {
+                failures.insert(Failure::Parse);
+                return Ok(Outcome::Failed);
+            };
+            self.stats_rows.push((file, Stats::collect(&ast)));
+            return Ok(Outcome::Unchanged);
+        }
+        if args.emit != Emit::Formatted {
+            let mut edited = Vec::new();
+            let prepared = parse_source(input, &config, &mut report)
+                .and_then(|ast| prepare(&config, args.fix, input, ast, &mut edited, &mut report));
+            let ast = match prepared {
+                Ok((_, ast)) => ast,
+                Err(failure) => {
+                    failures.insert(failure);
+                    return Ok(Outcome::Failed);
+                }
+            };
+            if errors {
+                failures.insert(Failure::Parse);
+            }
+            match args.emit {
+                Emit::Formatted => unreachable!(),
+                Emit::Minified => {
+                    let buf = Minifier::new()
+                        .keep_comments(args.keep_comments)
+                        .minify(ast);
+                    let buf = encode(&buf);
+                    io::stdout().write_all(&buf)?;
+                }
+                Emit::Tmsh | Emit::As3 => {
+                    let formatted = Formatter::with_options(options).format(ast);
+                    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                    let name = export::rule_name(&stem);
+                    let buf = match args.emit {
+                        Emit::Tmsh => export::tmsh(&name, &formatted),
+                        _ => export::as3(&name, &formatted).into_bytes(),
+                    };
+                    io::stdout().write_all(&buf)?;
+                }
+                Emit::Highlighted => {
+                    let buf = Formatter::with_options(options).format(ast);
+                    let (tokens, spans) = match Lexer::new().lex_with_spans(&buf) {
+                        Ok(lexed) => lexed,
+                        Err(e) => {
+                            failures.insert(Failure::Parse);
+                            reporter.report(
+                                &file,
+                                error("lex-error", format!("after formatting: {e:?}")),
+                            );
+                            return Ok(Outcome::Failed);
+                        }
+                    };
+                    let buf = highlight(&buf, &tokens, &spans, args.highlight_style);
+                    io::stdout().write_all(&buf)?;
+                }
+            }
+            return Ok(Outcome::Unchanged);
+        }
+        let formatted = match format_source(&config, options, args, input, &mut report) {
+            Ok(formatted) => formatted,
+            Err(failure) => {
+                failures.insert(failure);
+                return Ok(Outcome::Failed);
+            }
+        };
+        if errors {
+            failures.insert(Failure::Parse);
+        }
+        let remember = |formatted: &[u8]| {
+            if let (true, Some(cache)) = (clean, cache) {
+                cache.insert(cache::key(formatted, &fingerprint));
+            }
+        };
+        let buf = encode(&formatted);
+        reporter.file(&file, &raw, &buf);
+        if !args.check {
+            remember(&buf);
+            match source {
+                Source::Stdin => io::stdout().write_all(&buf)?,
+                _ if buf == raw => {}
+                Source::File => {
+                    eprintln!("formatting {file}");
+                    std::fs::write(path, buf)?;
+                    return Ok(Outcome::Reformatted);
+                }
+                Source::Index(staged) => {
+                    eprintln!("formatting {file}");
+                    staged.write(&raw, &buf)?;
+                    return Ok(Outcome::Reformatted);
+                }
+            }
+        } else if args.list_different && buf != raw {
+            self.failures.insert(Failure::Reformat);
+            println!("{file}");
+            return Ok(Outcome::Reformatted);
+        } else if let (Some(diff), true) = (&self.diff, buf != raw) {
+            self.failures.insert(Failure::Reformat);
+            io::stdout().write_all(&diff.render(&file, &raw, &buf))?;
+            return Ok(Outcome::Reformatted);
+        } else if let Some(line) = first_difference(&raw, &buf) {
+            failures.insert(Failure::Reformat);
+            reporter.report(
+                &file,
+                Diagnostic {
+                    rule: "would-reformat",
+                    severity: Severity::Warning,
+                    message: "file is not formatted".to_string(),
+                    span: Some(Span::line(line)),
+                    snippet: None,
+                    fix: None,
+                },
+            );
+            return Ok(Outcome::Reformatted);
+        } else {
+            remember(&buf);
+        }
+        Ok(Outcome::Unchanged)
+    }
+
+    fn io_error(&mut self, path: &Path, e: io::Error) {
+        self.failures.insert(Failure::Io);
+        self.reporter.report(
+            &path.display().to_string(),
+            error("io-error", e.to_string()),
+        );
+    }
+
+    // the exit status of the worst failure `--fail-on` selects
+    fn exit_code(&self) -> i32 {
+        self.failures
+            .iter()
+            .filter(|failure| self.args.fail_on.contains(failure))
+            .max()
+            .map_or(0, |failure| failure.exit_code())
+    }
+
+    // Reports a config file that failed to load, once for all the files it
+    // applies to
+    fn config_error(&mut self, path: PathBuf, e: ConfigFail) {
+        self.failures.insert(Failure::Io);
+        if self.bad_configs.contains(&path) {
+            return;
+        }
+        let line = e.line();
+        let snippet = line.and_then(|line| {
+            let text = std::fs::read_to_string(&path).ok()?;
+            text.lines().nth(line - 1).map(str::to_string)
+        });
+        self.reporter.report(
+            &path.display().to_string(),
+            Diagnostic {
+                rule: "invalid-config",
+                severity: Severity::Error,
+                message: e.to_string(),
+                span: line.map(Span::line),
+                snippet,
+                fix: None,
+            },
+        );
+        self.bad_configs.push(path);
+    }
+
+    // The formatted replacement of each region, or None if one failed to
+    // lex, parse or verify
+    fn format_embedded(
+        &mut self,
+        path: &Path,
+        input: &[u8],
+        regions: &[Region],
+        config: &Config,
+        options: FormatterOptions,
+    ) -> Option<Vec<Vec<u8>>> {
+        let file = path.display().to_string();
+        let mut replacements = Vec::with_capacity(regions.len());
+        let (mut ok, mut misconfigured) = (true, false);
+        for region in regions {
+            let mut errors = false;
+            let mut report = |d: Diagnostic| {
+                errors |= d.severity == Severity::Error;
+                self.reporter.report(&file, relocate(d, region));
+            };
+            let Some(script) = region.script(input) else {
+                ok = false;
+                report(error("lex-error", "invalid escape in string".to_string()));
+                continue;
+            };
+            // whole-file options of embedded scripts come from the config
+            // alone, but their directives are checked like those of Tcl files
+            if let Err(e) = directive::configure(&mut config.clone(), &script) {
+                ok = false;
+                report(directive_error(&script, e));
+                continue;
+            }
+            match format_source(config, options.clone(), &self.args, &script, &mut report) {
+                Ok(formatted) => {
+                    ok &= !errors;
+                    replacements.push(region.embed(&formatted));
+                }
+                Err(Failure::Io) => misconfigured = true,
+                Err(_) => ok = false,
+            }
+        }
+        if !ok {
+            self.failures.insert(Failure::Parse);
+        }
+        if misconfigured {
+            self.failures.insert(Failure::Io);
+        }
+        (ok && !misconfigured).then_some(replacements)
+    }
+
+    fn write_embedded(
+        &mut self,
+        path: &Path,
+        source: Source,
+        raw: &[u8],
+        buf: Vec<u8>,
+    ) -> io::Result<Outcome> {
+        let file = path.display().to_string();
+        self.reporter.file(&file, raw, &buf);
+        if self.args.list_different {
+            if buf == raw {
+                return Ok(Outcome::Unchanged);
+            }
+            self.failures.insert(Failure::Reformat);
+            println!("{file}");
+            return Ok(Outcome::Reformatted);
+        }
+        if let Some(diff) = &self.diff {
+            if buf == raw {
+                return Ok(Outcome::Unchanged);
+            }
+            self.failures.insert(Failure::Reformat);
+            io::stdout().write_all(&diff.render(&file, raw, &buf))?;
+            return Ok(Outcome::Reformatted);
+        }
+        if self.args.check {
+            if let Some(line) = first_difference(raw, &buf) {
+                self.failures.insert(Failure::Reformat);
+                self.reporter.report(
+                    &file,
+                    Diagnostic {
+                        rule: "would-reformat",
+                        severity: Severity::Warning,
+                        message: "embedded Tcl is not formatted".to_string(),
+                        span: Some(Span::line(line)),
+                        snippet: None,
+                        fix: None,
+                    },
+                );
+                return Ok(Outcome::Reformatted);
+            }
+            return Ok(Outcome::Unchanged);
+        }
+        match source {
+            Source::Stdin => io::stdout().write_all(&buf)?,
+            _ if buf == raw => {}
+            Source::File => {
+                eprintln!("formatting {file}");
+                std::fs::write(path, buf)?;
+                return Ok(Outcome::Reformatted);
+            }
+            Source::Index(staged) => {
+                eprintln!("formatting {file}");
+                staged.write(raw, &buf)?;
+                return Ok(Outcome::Reformatted);
+            }
+        }
+        Ok(Outcome::Unchanged)
+    }
+
+    // Reformats Tcl files under `dir` as they are saved, until interrupted
+    fn watch(&mut self, dir: &Path) -> io::Result<()> {
+        let mut watcher = Watcher::new(dir)?;
+        eprintln!("watching {}", dir.display());
+        loop {
+            let changed = watcher.wait()?;
+            let started = Instant::now();
+            let mut counts = [0; 4];
+            for path in &changed {
+                let outcome = self.process(path, Source::File).unwrap_or_else(|e| {
+                    self.io_error(path, e);
+                    Outcome::Failed
+                });
+                counts[outcome as usize] += 1;
+                watcher.refresh(path)?;
+            }
+            let [ignored, failed, unchanged, reformatted] = counts;
+            eprintln!(
+                "{} changed: {reformatted} reformatted, {unchanged} unchanged, {failed} failed, {ignored} ignored ({} ms)",
+                changed.len(),
+                started.elapsed().as_millis()
+            );
+        }
+    }
+}
+
+fn is_tcl(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| TCL_EXTENSIONS.contains(&ext))
+}
+
+// The diagnostics to report, and the fixes to apply instead of reporting
+// their findings: all with `--fix`, else those of rules set to "fix"
+fn split_fixes(
+    diagnostics: Vec<Diagnostic>,
+    config: &Config,
+    fix_all: bool,
+) -> (Vec<Diagnostic>, Vec<Fix>) {
+    let mut reported = Vec::new();
+    let mut fixes = Vec::new();
+    for d in diagnostics {
+        match d.fix {
+            Some(fix) if fix_all || config.lint.fixes(d.rule) => fixes.push(fix),
+            _ => reported.push(d),
+        }
+    }
+    (reported, fixes)
+}
+
+// Lexes and parses `source`, reporting why it can't be
+fn parse_source<'a>(
+    source: &'a [u8],
+    config: &Config,
+    report: &mut dyn FnMut(Diagnostic),
+) -> Result<Ast<'a>, Failure> {
+    let (tokens, spans) = Lexer::new().lex_with_spans(source).map_err(|e| {
+        report(error("lex-error", format!("{e:?}")));
+        Failure::Parse
+    })?;
+    Parser::new(source, &tokens, &spans)
+        .max_depth(config.max_depth)
+        .parse()
+        .map_err(|e| {
+            report(parse_error(source, &e));
+            Failure::Parse
+        })
+}
+
+// Reports the lint diagnostics of `ast`, applies the fixes, reparsing the
+// source from `edited` if they change its text, and runs the passes
+fn prepare<'a>(
+    config: &Config,
+    fix_all: bool,
+    source: &'a [u8],
+    ast: Ast<'a>,
+    edited: &'a mut Vec<u8>,
+    report: &mut dyn FnMut(Diagnostic),
+) -> Result<(&'a [u8], Ast<'a>), Failure> {
+    let (diagnostics, fixes) = split_fixes(lint(&ast, &config.lint), config, fix_all);
+    diagnostics.into_iter().for_each(&mut *report);
+    let (source, mut ast) = match fixes.iter().any(Fix::is_edit) {
+        false => (source, ast),
+        true => {
+            *edited = fix::apply_edits(source, &fixes);
+            let edited: &'a [u8] = edited;
+            let ast = parse_source(edited, config, &mut |mut d: Diagnostic| {
+                if d.rule == "lex-error" {
+                    d.message.insert_str(0, "after fixes: ");
+                }
+                report(d);
+            })?;
+            (edited, ast)
+        }
+    };
+    let passes = fix::passes(&config.passes, &fixes);
+    if let Err(e) = tcl_formatter::passes::run(&mut ast, &passes) {
+        report(error("invalid-config", e.to_string()));
+        return Err(Failure::Io);
+    }
+    Ok((source, ast))
+}
+
+// The formatted text of a whole script, shared by Tcl files and the
+// regions embedded in host documents
+fn format_source(
+    config: &Config,
+    options: FormatterOptions,
+    args: &Args,
+    source: &[u8],
+    report: &mut dyn FnMut(Diagnostic),
+) -> Result<Vec<u8>, Failure> {
+    let ast = parse_source(source, config, report)?;
+    let mut edited = Vec::new();
+    let (source, ast) = prepare(config, args.fix, source, ast, &mut edited, report)?;
+    let formatted = Formatter::with_options(options)
+        .size_hint(source.len())
+        .format(ast);
+    if args.verify {
+        if let Err(e) = verify(source, &formatted) {
+            report(verify_error(source, e));
+            return Err(Failure::Parse);
+        }
+    }
+    Ok(formatted)
+}
+
+fn error(rule: &'static str, message: String) -> Diagnostic {
+    Diagnostic {
+        rule,
+        severity: Severity::Error,
+        message,
+        span: None,
+        snippet: None,
+        fix: None,
+    }
+}
+
+fn verify_error(source: &[u8], e: VerifyFail) -> Diagnostic {
+    let (offset, message) = match e {
+        VerifyFail::Lex(e) => return error("verify-failed", format!("output: {e:?}")),
+        VerifyFail::Mismatch {
+            offset,
+            input,
+            output,
+        } => (
+            offset,
+            match (input.as_str(), output.as_str()) {
+                ("", output) => format!("formatting would add `{output}` here"),
+                (input, "") => format!("formatting would drop `{input}`"),
+                (input, output) => format!("formatting would turn `{input}` into `{output}`"),
+            },
+        ),
+    };
+    let line_start = source[..offset]
+        .iter()
+        .rposition(|&c| c == b'\n')
+        .map_or(0, |pos| pos + 1);
+    let start = Position {
+        line: 1 + source[..offset].iter().filter(|&&c| c == b'\n').count(),
+        column: width::column(&source[line_start..], offset - line_start),
+    };
+    Diagnostic {
+        rule: "verify-failed",
+        severity: Severity::Error,
+        message: format!("{message}, file left unchanged"),
+        span: Some(Span { start, end: start }),
+        snippet: None,
+        fix: None,
+    }
+}
+
+// moves a diagnostic about an embedded script to where the script is in its
+// host document; a JSON string can only be pointed at as a whole
+fn relocate(mut d: Diagnostic, region: &Region) -> Diagnostic {
+    if !region.maps_lines() {
+        d.message = format!("in the embedded script: {}", d.message);
+        d.span = Some(Span::line(region.line));
+        d.snippet = None;
+    } else if let Some(span) = &mut d.span {
+        span.start.line += region.line - 1;
+        span.end.line += region.line - 1;
+    } else {
+        d.span = Some(Span::line(region.line));
+    }
+    d
+}
+
+// points at the offending token, with its line of `source` as the snippet
+fn directive_error(source: &[u8], e: ConfigFail) -> Diagnostic {
+    let line = e.line();
+    let snippet = line.and_then(|line| {
+        let text = source.split(|&c| c == b'\n').nth(line - 1)?;
+        let text = text.strip_suffix(b"\r").unwrap_or(text);
+        Some(String::from_utf8_lossy(text).into_owned())
+    });
+    Diagnostic {
+        rule: "invalid-directive",
+        severity: Severity::Error,
+        message: e.to_string(),
+        span: line.map(Span::line),
+        snippet,
+        fix: None,
+    }
+}
+
+fn parse_error(source: &[u8], e: &ParseError) -> Diagnostic {
+    let line_start = source[..e.span.start]
+        .iter()
+        .rposition(|&c| c == b'\n')
+        .map_or(0, |pos| pos + 1);
+    let line_end = source[line_start..]
+        .iter()
+        .position(|&c| c == b'\n')
+        .map_or(source.len(), |pos| line_start + pos);
+    let line = source[line_start..line_end]
+        .strip_suffix(b"\r")
+        .unwrap_or(&source[line_start..line_end]);
+    let start = Position {
+        line: e.line,
+        column: e.column,
+    };
+    let end = Position {
+        line: e.line,
+        column: width::column(line, e.span.end.min(line_end) - line_start),
+    };
+    Diagnostic {
+        rule: "parse-error",
+        severity: Severity::Error,
+        message: e.to_string(),
+        span: Some(Span { start, end }),
+        snippet: Some(String::from_utf8_lossy(line).into_owned()),
+        fix: None,
+    }
+}
+
+struct Reporter {
+    format: MessageFormat,
+    color: bool,                          // of human-readable diagnostics
+    collected: Vec<(String, Diagnostic)>, // for formats written as one document
+    html: Option<HtmlReport>,             // for `--report html`
+}
+
+impl Reporter {
+    fn report(&mut self, file: &str, d: Diagnostic) {
+        if let Some(html) = &mut self.html {
+            html.diagnostic(file, d.clone());
+        }
+        match self.format {
+            MessageFormat::Human => eprintln!("{}", d.render_human(file, self.color)),
+            MessageFormat::Json => println!("{}", d.render_json(file)),
+            MessageFormat::Sarif => self.collected.push((file.to_string(), d)),
+        }
+    }
+
+    // a file formatted to `formatted`, for the HTML report
+    fn file(&mut self, file: &str, original: &[u8], formatted: &[u8]) {
+        if let Some(html) = &mut self.html {
+            html.file(file, original, formatted);
+        }
+    }
+
+    fn finish(self) {
+        if self.format == MessageFormat::Sarif {
+            print!("{}", sarif::render(&self.collected));
+        }
+    }
 }
@@ -0,0 +1,239 @@
+// Emits semantically equivalent source with minimal whitespace, for devices
+// that limit the size of an iRule. Commands within a block are joined with
+// `;`; a kept comment still needs its own line, as `;` would end up inside it.
+use crate::ast::Ast;
+
+pub struct Minifier {
+    keep_comments: bool,
+    buf: Vec<u8>,
+}
+
+impl Default for Minifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Minifier {
+    pub fn new() -> Self {
+        Self {
+            keep_comments: false,
+            buf: Vec::new(),
+        }
+    }
+
+    pub fn keep_comments(mut self, keep_comments: bool) -> Self {
+        self.keep_comments = keep_comments;
+        self
+    }
+
+    pub fn minify(mut self, ast: Ast) -> Vec<u8> {
+        match ast {
+            Ast::Block(trees) => self.run_block(trees),
+            ast => self.run(ast),
+        }
+        if !self.buf.ends_with(b"\n") {
+            self.buf.push(b'\n');
+        }
+        self.buf
+    }
+
+    fn run_block(&mut self, trees: Vec<Ast>) {
+        let mut after_comment = true; // nothing to separate from yet
+        for tree in trees {
+            match tree {
+                // commands are joined with `;` anyway
+                Ast::EmptyLine | Ast::Semicolon => continue,
+                Ast::Comment(_) | Ast::TrailingComment(_) if !self.keep_comments => continue,
+                Ast::Comment(_) | Ast::Verbatim(_) if !after_comment => self.buf.push(b'\n'),
+                _ if !after_comment => self.buf.push(b';'),
+                _ => {}
+            }
+            after_comment = matches!(
+                tree,
+                Ast::Comment(_) | Ast::TrailingComment(_) | Ast::Verbatim(_)
+            );
+            self.run(tree);
+        }
+    }
+
+    fn run(&mut self, ast: Ast) {
+        match ast {
+            Ast::Block(trees) => self.run_block(trees),
+            // `;` before it separates it from the statement like any command
+            Ast::Comment(data) | Ast::TrailingComment(data) => {
+                self.write(b"#");
+                self.write(&data);
+                self.buf.push(b'\n');
+            }
+            // read by the shell or an editor, so kept even without comments
+            Ast::Verbatim(line) => {
+                self.write(&line);
+                self.buf.push(b'\n');
+            }
+            Ast::Procedure {
+                name,
+                parameters,
+                body,
+            } => {
+                self.write(b"proc ");
+                self.write(&name);
+                self.write(b" {");
+                self.write(&parameters.join(&b' '));
+                self.write(b"} ");
+                self.run_braced(*body);
+            }
+            Ast::If {
+                condition_body_clauses,
+                maybe_block_if_false,
+            } => {
+                for (idx, (condition, block)) in condition_body_clauses.into_iter().enumerate() {
+                    self.write(if idx == 0 { b"if " } else { b" elseif " });
+                    if condition.braced {
+                        self.write(b"{");
+                        self.write(&condition);
+                        self.write(b"}");
+                    } else {
+                        self.write(&condition);
+                    }
+                    self.write(b" ");
+                    self.run_braced(block);
+                }
+                if let Some(block_if_false) = maybe_block_if_false {
+                    self.write(b" else ");
+                    self.run_braced(*block_if_false);
+                }
+            }
+            Ast::Switch {
+                condition,
+                value_block_or_fallthrough_vec,
+            } => {
+                self.write(b"switch ");
+                self.write(&condition);
+                self.write(b" {");
+                for (idx, (value, block_or_fallthrough)) in
+                    value_block_or_fallthrough_vec.into_iter().enumerate()
+                {
+                    if idx > 0 {
+                        self.write(b" ");
+                    }
+                    self.write(&value);
+                    match block_or_fallthrough {
+                        Some(block) => {
+                            self.write(b" ");
+                            self.run_braced(block);
+                        }
+                        None => self.write(b" -"),
+                    }
+                }
+                self.write(b"}");
+            }
+            Ast::When { event_name, body } => {
+                self.write(b"when ");
+                self.write(&event_name);
+                self.write(b" ");
+                self.run_braced(*body);
+            }
+            Ast::Script {
+                command,
+                arguments,
+                body,
+            } => {
+                self.write(&command);
+                for argument in arguments {
+                    self.write(b" ");
+                    self.write(&argument);
+                }
+                self.write(b" ");
+                self.run_braced(*body);
+            }
+            Ast::Statement(s) => self.write(&s.words().join(&b' ')),
+            Ast::EmptyLine | Ast::Semicolon => {}
+        }
+    }
+
+    fn run_braced(&mut self, ast: Ast) {
+        self.write(b"{");
+        self.run(ast);
+        self.write(b"}");
+    }
+
+    fn write(&mut self, slice: &[u8]) {
+        self.buf.extend_from_slice(slice);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Minifier;
+    use crate::{ast::Ast, formatter::Formatter, lexer::Lexer, parser::Parser};
+
+    fn parse(source: &[u8]) -> Ast<'_> {
+        let (tokens, spans) = Lexer::new().lex_with_spans(source).unwrap();
+        Parser::new(source, &tokens, &spans).parse().unwrap()
+    }
+
+    const SOURCE: &str = "# header
+proc p {a b} {
+    # inner
+    if {$a} {
+        set b 1 ;# note
+    } elseif {$b} {
+        return
+    } else {
+        log local0. \"x  y\"
+    }
+}
+
+
+when HTTP_REQUEST {
+    switch $a {
+        x -
+        y {
+            pool p
+        }
+        default {}
+    }
+}
+";
+
+    #[test]
+    fn joins_commands_with_semicolons() {
+        let minified = Minifier::new().minify(parse(SOURCE.as_bytes()));
+        assert_eq!(
+            String::from_utf8(minified).unwrap(),
+            "proc p {a b} {if {$a} {set b 1} elseif {$b} {return} else {log local0. \"x  y\"}};\
+             when HTTP_REQUEST {switch $a {x - y {pool p} default {}}}\n"
+        );
+    }
+
+    #[test]
+    fn keeps_comments_on_their_own_lines() {
+        let minified = Minifier::new()
+            .keep_comments(true)
+            .minify(parse(SOURCE.as_bytes()));
+        let text = String::from_utf8(minified).unwrap();
+        assert!(text.starts_with("#header\nproc p {a b} {#inner\nif {$a} {set b 1;#note\n}"));
+    }
+
+    #[test]
+    fn minified_source_means_the_same() {
+        let source = b"proc p {a} {\n    # why\n    if {$a} {\n        set b 1\n    } else {\n        return $a\n    }\n}\n\nwhen X {\n    p 1\n}\n";
+        let minified = Minifier::new().keep_comments(true).minify(parse(source));
+        let original = Formatter::new().format(parse(source));
+        let restored = Formatter::new().format(parse(&minified));
+        let lines =
+            |text: Vec<u8>| -> Vec<u8> { text.into_iter().filter(|&c| c != b'\n').collect() };
+        assert_eq!(lines(restored), lines(original));
+    }
+
+    #[test]
+    fn minifies_braced_scripts() {
+        let source = b"uplevel 1 {\n    set a 1\n    pool p\n}\neval $dynamic\n";
+        let minified = Minifier::new().minify(parse(source));
+        assert_eq!(
+            String::from_utf8(minified).unwrap(),
+            "uplevel 1 {set a 1;pool p};eval $dynamic\n"
+        );
+    }
+}
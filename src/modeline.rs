@@ -0,0 +1,128 @@
+// Shebangs and editor modelines. They are read by other programs, so they are
+// written back byte for byte, and vim modelines can stand in for the indent
+// options of the file they are in.
+
+// `#!/usr/bin/tclsh`, as the whole first line
+pub fn is_shebang(line: &[u8]) -> bool {
+    line.starts_with(b"#!")
+}
+
+// an emacs `-*- mode: tcl -*-` or a vim `vim: set sw=2:` line
+pub fn is_modeline(line: &[u8]) -> bool {
+    is_emacs_modeline(line) || vim_options(line).is_some()
+}
+
+fn is_emacs_modeline(line: &[u8]) -> bool {
+    let Some(start) = find(line, b"-*-") else {
+        return false;
+    };
+    find(&line[start + 3..], b"-*-").is_some()
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct IndentHints {
+    pub use_tabs: Option<bool>,
+    pub indent_width: Option<usize>,
+}
+
+// The indent options set by vim modelines in the first or last 5 lines of
+// `source`, like vim reads them; later modelines override earlier ones.
+pub fn indent_hints(source: &[u8]) -> IndentHints {
+    let lines: Vec<&[u8]> = source.split(|&c| c == b'\n').collect();
+    let head = lines.len().min(5);
+    let tail = lines.len().saturating_sub(5).max(head);
+    let mut hints = IndentHints::default();
+    let (mut shiftwidth, mut tabstop) = (None, None);
+    for line in lines[..head].iter().chain(&lines[tail..]) {
+        let Some(options) = vim_options(line) else {
+            continue;
+        };
+        for option in options {
+            let (name, value) = match option.split_once('=') {
+                Some((name, value)) => (name, value.parse::<usize>().ok()),
+                None => (option, None),
+            };
+            match name {
+                "et" | "expandtab" => hints.use_tabs = Some(false),
+                "noet" | "noexpandtab" => hints.use_tabs = Some(true),
+                "sw" | "shiftwidth" => shiftwidth = value.or(shiftwidth),
+                "ts" | "tabstop" => tabstop = value.or(tabstop),
+                _ => {}
+            }
+        }
+    }
+    // `sw=0` means the tab stop
+    hints.indent_width = shiftwidth.filter(|&sw| sw > 0).or(tabstop);
+    hints
+}
+
+// The options of a vim modeline: `vim: sw=2 et` or `vim: set sw=2 et:`,
+// after whitespace or at the start of the line, also as `vi:` and `ex:`
+fn vim_options(line: &[u8]) -> Option<Vec<&str>> {
+    let line = std::str::from_utf8(line).ok()?;
+    let rest = ["vim:", "Vim:", "vi:", "ex:"].iter().find_map(|marker| {
+        line.match_indices(marker)
+            .find(|&(idx, _)| idx == 0 || line[..idx].ends_with([' ', '\t']) || line[..idx] == *"#")
+            .map(|(idx, _)| &line[idx + marker.len()..])
+    })?;
+    let rest = rest.trim_start();
+    let options: Vec<&str> = match rest
+        .strip_prefix("set ")
+        .or_else(|| rest.strip_prefix("se "))
+    {
+        // the second form ends at the next `:`
+        Some(set) => set.split(':').next()?.split_whitespace().collect(),
+        None => rest
+            .split([':', ' ', '\t'])
+            .filter(|option| !option.is_empty())
+            .collect(),
+    };
+    (!options.is_empty()).then_some(options)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{indent_hints, is_modeline, is_shebang, IndentHints};
+
+    #[test]
+    fn recognizes_shebangs_and_modelines() {
+        assert!(is_shebang(b"#!/usr/bin/env tclsh"));
+        assert!(!is_shebang(b"# !not"));
+        assert!(is_modeline(b"# -*- mode: tcl; tab-width: 4 -*-"));
+        assert!(is_modeline(b"# vim: set sw=2 et:"));
+        assert!(is_modeline(b"# vim:ts=8"));
+        assert!(!is_modeline(b"# see vim:"));
+        assert!(!is_modeline(b"set url http://vim:80"));
+    }
+
+    #[test]
+    fn reads_vim_indent_options() {
+        let source = b"# vim: set sw=2 et:\nset a 1\n";
+        assert_eq!(
+            indent_hints(source),
+            IndentHints {
+                use_tabs: Some(false),
+                indent_width: Some(2),
+            }
+        );
+        // `sw=0` defers to the tab stop, and the last modeline wins
+        let source = b"# vim: sw=2\nset a 1\n# vim: noet sw=0 ts=8\n";
+        assert_eq!(
+            indent_hints(source),
+            IndentHints {
+                use_tabs: Some(true),
+                indent_width: Some(8),
+            }
+        );
+    }
+
+    #[test]
+    fn reads_only_the_first_and_last_lines() {
+        let source = "set a 1\n".repeat(5) + "# vim: sw=2\n" + &"set a 1\n".repeat(5);
+        assert_eq!(indent_hints(source.as_bytes()), IndentHints::default());
+    }
+}
@@ -0,0 +1,68 @@
+// Namespaced commands, like the iRule `HTTP::host` or `SSL::cert`, are single
+// words: the lexer never splits them at `::`. These helpers find the commands
+// a piece of source calls and classify them by namespace, for lints and stats.
+
+// Namespaces of commands built into BIG-IP iRules
+pub const IRULE_NAMESPACES: &[&str] = &[
+    "ACCESS", "ASM", "AUTH", "CACHE", "COMPRESS", "CRYPTO", "DNS", "GTP", "HTTP", "IP", "LB",
+    "PROFILE", "RESOLV", "SCTP", "SIP", "SSL", "STREAM", "TCP", "UDP", "URI", "X509",
+];
+
+// `HTTP` for `HTTP::host`, `a::b` for `::a::b::c`; None for plain commands
+pub fn namespace(command: &[u8]) -> Option<&[u8]> {
+    let command = command.strip_prefix(b"::").unwrap_or(command);
+    let end = command.windows(2).rposition(|w| w == b"::")?;
+    Some(&command[..end])
+}
+
+// whether `command` is built into iRules rather than defined by a script
+pub fn is_irule_command(command: &[u8]) -> bool {
+    namespace(command).is_some_and(|ns| IRULE_NAMESPACES.iter().any(|n| n.as_bytes() == ns))
+}
+
+// the namespaced commands `source` calls: its first word, and the first word
+// of each bracketed command substitution
+pub fn namespaced_commands(source: &[u8]) -> Vec<&[u8]> {
+    let starts = std::iter::once(0).chain(
+        source
+            .iter()
+            .enumerate()
+            .filter(|&(_, &c)| c == b'[')
+            .map(|(idx, _)| idx + 1),
+    );
+    starts
+        .filter_map(|start| {
+            let rest = source[start..].trim_ascii_start();
+            let len = rest
+                .iter()
+                .take_while(|&&c| c.is_ascii_alphanumeric() || matches!(c, b'_' | b':' | b'.'))
+                .count();
+            Some(&rest[..len]).filter(|word| namespace(word).is_some())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_irule_command, namespace, namespaced_commands};
+
+    #[test]
+    fn splits_off_the_namespace() {
+        assert_eq!(namespace(b"HTTP::host"), Some(&b"HTTP"[..]));
+        assert_eq!(namespace(b"::a::b::c"), Some(&b"a::b"[..]));
+        assert_eq!(namespace(b"set"), None);
+        assert!(is_irule_command(b"SSL::cert"));
+        assert!(!is_irule_command(b"mylib::cert"));
+        assert!(!is_irule_command(b"HTTP"));
+    }
+
+    #[test]
+    fn finds_called_commands() {
+        let source =
+            b"HTTP::respond 200 content [IP::client_addr] [ string tolower [HTTP::uri]] $a::b";
+        assert_eq!(
+            namespaced_commands(source),
+            [&b"HTTP::respond"[..], b"IP::client_addr", b"HTTP::uri"]
+        );
+    }
+}
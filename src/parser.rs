@@ -1,9 +1,28 @@
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::ops::Range;
+
 use crate::{
-    ast::{Ast, Statement},
+    ast::{Ast, Condition, Statement, Text},
     lexer::Token,
+    modeline,
+    namespace::namespace,
+    width,
 };
 
-pub struct Parser {}
+// Parses the tokens of `source` into an AST whose text borrows from `source`
+pub struct Parser<'a, 'src> {
+    source: &'src [u8],
+    tokens: &'a [Token<'src>],
+    spans: &'a [Range<usize>], // byte range of each token in `source`
+    failed_at: Cell<usize>,    // index of the token being parsed, where errors point
+    depth: Cell<usize>,        // blocks entered, the file's own not counted
+    max_depth: usize,
+}
+
+// Nested blocks are parsed, and later formatted, recursively, so deeper
+// nesting is refused before it can overflow the stack
+pub const DEFAULT_MAX_DEPTH: usize = 256;
 
 #[derive(Debug)]
 pub enum ParserFail {
@@ -11,81 +30,200 @@ pub enum ParserFail {
     SwitchBlock,
     Expression,
     BracketMismatch,
-    NoNewline,  // expected newline
-    UnknownAST, // no tokens matched an AST block
-    Other,      // TODO: remove this
+    NoBlock,        // expected a `{` body
+    NoNewline,      // expected newline
+    UnknownAST,     // no tokens matched an AST block
+    TooDeep(usize), // blocks nested deeper than this
+    Other,          // TODO: remove this
 }
 type Result<T> = std::result::Result<T, ParserFail>;
 
-impl Parser {
-    pub fn new() -> Self {
-        Self {}
+impl ParserFail {
+    // what would have been accepted where parsing stopped
+    pub fn expected(&self) -> &'static [&'static str] {
+        match self {
+            Self::ElseIfBlock => &["`elseif`", "`else`", "a `{` body"],
+            Self::SwitchBlock => &["a switch pattern followed by `{` or `-`"],
+            Self::Expression => &["a word", "`$variable`", "`[command]`"],
+            Self::BracketMismatch => &["a closing bracket"],
+            Self::NoBlock => &["a `{` body"],
+            Self::NoNewline => &["the end of the line"],
+            Self::UnknownAST => &["a command"],
+            Self::TooDeep(_) => &[],
+            Self::Other => &[],
+        }
     }
+}
 
-    pub fn parse(self, tokens: &[Token]) -> Result<Ast> {
-        Ok(Parser::try_parse(tokens)?.0)
+// A parse failure, located at the token the parser was looking at
+#[derive(Debug)]
+pub struct ParseError {
+    pub kind: ParserFail,
+    pub span: Range<usize>, // bytes of the offending token in the source
+    pub line: usize,        // 1-based
+    pub column: usize,      // 1-based, in display width
+    pub found: String,      // the offending token, empty at the end of the input
+    pub expected: &'static [&'static str],
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let ParserFail::TooDeep(max_depth) = self.kind {
+            return write!(f, "blocks nested more than {max_depth} levels deep");
+        }
+        match self.found.as_str() {
+            "" => write!(f, "unexpected end of input")?,
+            "\n" => write!(f, "unexpected end of line")?,
+            found => write!(f, "unexpected `{found}`")?,
+        }
+        match self.expected {
+            [] => Ok(()),
+            [one] => write!(f, ", expected {one}"),
+            [init @ .., last] => write!(f, ", expected {} or {last}", init.join(", ")),
+        }
     }
+}
 
-    fn try_parse_if(mut tokens: &[Token]) -> Result<(Ast, usize)> {
-        println!("parsing if");
-        let mut condition_body_clauses = Vec::new();
-        let mut consumed = 1; // starts from 1 for the if keyword
-        tokens = &tokens[consumed..];
+impl<'a, 'src> Parser<'a, 'src> {
+    // `tokens` and `spans` as returned by `Lexer::lex_with_spans(source)`
+    pub fn new(source: &'src [u8], tokens: &'a [Token<'src>], spans: &'a [Range<usize>]) -> Self {
+        Self {
+            source,
+            tokens,
+            spans,
+            failed_at: Cell::new(0),
+            depth: Cell::new(0),
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
 
-        let condition_tokens = Parser::try_extract_block(tokens)?;
-        tokens = &tokens[condition_tokens.len() + 2..];
-        consumed += condition_tokens.len() + 2;
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
 
-        let body_tokens = Parser::try_extract_block(tokens)?;
-        tokens = &tokens[body_tokens.len() + 2..];
-        consumed += body_tokens.len() + 2;
+    pub fn parse(self) -> std::result::Result<Ast<'src>, ParseError> {
+        self.try_parse(self.tokens)
+            .map(|(ast, _)| ast)
+            .map_err(|kind| self.error(kind))
+    }
 
-        let if_condition = Parser::parse_vec(condition_tokens);
-        let (body_if_true, _) = Parser::try_parse(body_tokens)?;
-        condition_body_clauses.push((if_condition, body_if_true));
+    fn error(&self, kind: ParserFail) -> ParseError {
+        let idx = self.failed_at.get();
+        let span = match self.spans.get(idx) {
+            Some(span) => span.clone(),
+            None => self.source.len()..self.source.len(),
+        };
+        let line_start = self.source[..span.start]
+            .iter()
+            .rposition(|&c| c == b'\n')
+            .map_or(0, |pos| pos + 1);
+        let found = match self.tokens.get(idx) {
+            Some(Token::Newline) => "\n".to_string(),
+            Some(token) => String::from_utf8_lossy(&Vec::from(token)).into_owned(),
+            None => String::new(),
+        };
+        ParseError {
+            expected: kind.expected(),
+            kind,
+            line: 1 + self.source[..span.start]
+                .iter()
+                .filter(|&&c| c == b'\n')
+                .count(),
+            column: width::column(&self.source[line_start..], span.start - line_start),
+            span,
+            found,
+        }
+    }
 
-        // eat a newline if possible
-        if let Some(Token::Newline) = tokens.get(0) {
-            tokens = &tokens[1..];
+    // records `token` as the place to report if parsing fails from here
+    fn mark(&self, token: &Token<'src>) {
+        if let Some(idx) = self.tokens.element_offset(token) {
+            self.failed_at.set(idx);
         }
+    }
 
-        let maybe_block_if_false = match (tokens.get(0), tokens.get(1)) {
-            (Some(Token::KeywordElseIf), Some(Token::LCurlyBracket)) => {
-                let (ast, consumed_rem) = Parser::try_parse_if(tokens)?;
-                consumed += consumed_rem + 1; // +1 for the elseif keyword
-                match ast {
-                    Ast::If {
-                        condition_body_clauses: elseif_clauses,
-                        maybe_block_if_false,
-                    } => {
-                        condition_body_clauses.extend(elseif_clauses);
-                        maybe_block_if_false
-                    }
-                    _ => return Err(ParserFail::ElseIfBlock),
+    fn try_parse_if(&self, mut tokens: &'a [Token<'src>]) -> Result<(Ast<'src>, usize)> {
+        let mut condition_body_clauses = Vec::new();
+        let mut consumed = 0;
+
+        // one clause per `if`/`elseif` keyword, in a loop so long chains don't nest
+        let maybe_block_if_false = loop {
+            consumed += 1; // for the if or elseif keyword
+            tokens = &tokens[1..];
+
+            let (condition, n) = self.try_parse_condition(tokens)?;
+            tokens = &tokens[n..];
+            consumed += n;
+
+            let body_tokens = Parser::try_extract_block(tokens)?;
+            tokens = &tokens[body_tokens.len() + 2..];
+            consumed += body_tokens.len() + 2;
+
+            let (body, _) = self.try_parse(body_tokens)?;
+            condition_body_clauses.push((condition, body));
+
+            // eat a newline if possible, it only counts as consumed when an else/elseif follows
+            let newline = match tokens.first() {
+                Some(Token::Newline) => 1,
+                _ => 0,
+            };
+            tokens = &tokens[newline..];
+
+            match (tokens.first(), tokens.get(1)) {
+                (Some(Token::KeywordElseIf), Some(_)) => consumed += newline,
+                (Some(Token::KeywordElse), Some(Token::LCurlyBracket)) => {
+                    let else_body_tokens = Parser::try_extract_block(&tokens[1..])?;
+                    consumed += newline + else_body_tokens.len() + 1 + 2; // +1 for the else keyword, +2 for brackets
+                    let (block_if_false, _) = self.try_parse(else_body_tokens)?;
+                    break Some(Box::new(block_if_false));
                 }
+                _ => break None,
             }
-            (Some(Token::KeywordElse), Some(Token::LCurlyBracket)) => {
-                let else_body_tokens = Parser::try_extract_block(&tokens[1..])?;
-                consumed += else_body_tokens.len() + 1 + 2; // +1 for the else keyword, +2 for brackets
-                let (block_if_false, _) = Parser::try_parse(else_body_tokens)?;
-                Some(Box::new(block_if_false))
-            }
-            _ => None,
         };
 
-        return Ok((
+        Ok((
             Ast::If {
                 condition_body_clauses,
                 maybe_block_if_false,
             },
             consumed,
-        ));
+        ))
     }
 
-    fn try_parse_when(mut tokens: &[Token]) -> Result<(Ast, usize)> {
-        println!("parsing when");
+    // `{ expr }`, or a bare word running up to the `{` that opens the body
+    fn try_parse_condition(&self, tokens: &'a [Token<'src>]) -> Result<(Condition<'src>, usize)> {
+        if let Some(Token::LCurlyBracket) = tokens.first() {
+            let condition_tokens = Parser::try_extract_block(tokens)?;
+            let condition = Condition::braced(self.text(condition_tokens));
+            return Ok((condition, condition_tokens.len() + 2));
+        }
+        let mut square_depth = 0;
+        let mut quoted = false;
+        for (idx, token) in tokens.iter().enumerate() {
+            match token {
+                Token::Quote => quoted = !quoted,
+                Token::LSquareBracket => square_depth += 1,
+                Token::RSquareBracket => square_depth -= 1,
+                // ${name} is part of the word
+                Token::LCurlyBracket if idx > 0 && matches!(tokens[idx - 1], Token::Dollar) => {}
+                Token::LCurlyBracket if idx > 0 && square_depth == 0 && !quoted => {
+                    let condition = Condition {
+                        text: self.text(&tokens[..idx]),
+                        braced: false,
+                    };
+                    return Ok((condition, idx));
+                }
+                Token::Newline => break,
+                _ => {}
+            }
+        }
+        Err(ParserFail::Expression)
+    }
+
+    fn try_parse_when(&self, mut tokens: &'a [Token<'src>]) -> Result<(Ast<'src>, usize)> {
         let Token::Identifier(event_name) = &tokens[1] else {
-            unreachable!();
+            return Err(ParserFail::UnknownAST);
         };
 
         let mut consumed = 2; // starts from 2 for the when keyword & the event name
@@ -94,156 +232,419 @@ impl Parser {
         let body_tokens = Parser::try_extract_block(tokens)?;
         consumed += body_tokens.len() + 2;
 
-        let (body_if_true, _) = Parser::try_parse(body_tokens)?;
+        let (body_if_true, _) = self.try_parse(body_tokens)?;
 
-        return Ok((
+        Ok((
             Ast::When {
-                event_name: event_name.to_vec(),
+                event_name: Cow::Borrowed(event_name),
                 body: Box::new(body_if_true),
             },
             consumed,
-        ));
+        ))
+    }
+
+    // `eval {script}` or `uplevel ?level? {script}`, kept as written when the
+    // braces are followed by more words or hold something that doesn't parse
+    fn try_parse_script(&self, tokens: &'a [Token<'src>]) -> Result<(Ast<'src>, usize)> {
+        let Token::Identifier(command) = &tokens[0] else {
+            return Err(ParserFail::UnknownAST);
+        };
+        let arguments = match &tokens[1] {
+            Token::Identifier(level) => vec![Cow::Borrowed(*level)],
+            _ => Vec::new(),
+        };
+
+        let mut consumed = 1 + arguments.len(); // the command & the level
+        let body_tokens = Parser::try_extract_block(&tokens[consumed..])?;
+        consumed += body_tokens.len() + 2;
+        if !matches!(tokens.get(consumed), Some(Token::Newline) | None) {
+            return self.try_parse_statement(tokens);
+        }
+        let body = match self.try_parse(body_tokens) {
+            Ok((body, _)) => body,
+            Err(e @ ParserFail::TooDeep(_)) => return Err(e),
+            Err(_) => return self.try_parse_statement(tokens),
+        };
+
+        Ok((
+            Ast::Script {
+                command: Cow::Borrowed(command),
+                arguments,
+                body: Box::new(body),
+            },
+            consumed,
+        ))
+    }
+
+    fn try_parse_proc(&self, mut tokens: &'a [Token<'src>]) -> Result<(Ast<'src>, usize)> {
+        // the name runs up to the parameter list, e.g. ns::name
+        let name_len = tokens[1..]
+            .iter()
+            .take_while(|t| !matches!(t, Token::LCurlyBracket | Token::Newline))
+            .count();
+        let name = self.text(&tokens[1..=name_len]);
+
+        let mut consumed = 1 + name_len; // the proc keyword & the name
+        tokens = &tokens[consumed..];
+
+        let parameter_tokens = Parser::try_extract_block(tokens)?;
+        tokens = &tokens[parameter_tokens.len() + 2..];
+        consumed += parameter_tokens.len() + 2;
+
+        let mut parameters = Vec::new();
+        let mut rem_tokens = parameter_tokens;
+        while let Some(token) = rem_tokens.first() {
+            match token {
+                Token::Newline => rem_tokens = &rem_tokens[1..],
+                // a parameter list continued over lines
+                Token::Other(b"\\") if matches!(rem_tokens.get(1), Some(Token::Newline)) => {
+                    rem_tokens = &rem_tokens[2..]
+                }
+                Token::LCurlyBracket => {
+                    // {name default}
+                    let default_tokens = Parser::try_extract_block(rem_tokens)?;
+                    parameters.push(self.text(&rem_tokens[..default_tokens.len() + 2]));
+                    rem_tokens = &rem_tokens[default_tokens.len() + 2..];
+                }
+                _ => {
+                    parameters.push(self.text(&rem_tokens[..1]));
+                    rem_tokens = &rem_tokens[1..];
+                }
+            }
+        }
+
+        let body_tokens = Parser::try_extract_block(tokens)?;
+        consumed += body_tokens.len() + 2;
+
+        let (body, _) = self.try_parse(body_tokens)?;
+
+        Ok((
+            Ast::Procedure {
+                name,
+                parameters,
+                body: Box::new(body),
+            },
+            consumed,
+        ))
     }
 
-    fn try_parse_set(tokens: &[Token]) -> Result<(Ast, usize)> {
-        println!("parsing set");
+    fn try_parse_set(&self, tokens: &'a [Token<'src>]) -> Result<(Ast<'src>, usize)> {
         let Token::Identifier(data) = &tokens[1] else {
-            unreachable!();
+            return Err(ParserFail::UnknownAST);
         };
         let mut consumed = 2; // starts from 2 for the set keyword & the identifier
 
-        // TODO: fix space iterator logic, as no spaces should be in data
-        let mut space_iterator = data.split(|&x| x == b' ');
-        let identifier = space_iterator.next().ok_or(ParserFail::Other)?.to_vec();
-        let mut value = space_iterator
-            .next()
-            .map(|x| x.to_vec())
-            .unwrap_or(Vec::new());
-
+        let identifier = Cow::Borrowed(*data);
         let rem_tokens = Parser::try_extract_until_newline(&tokens[2..])?;
-        consumed += rem_tokens.len() + 1;
-        value.extend(Parser::parse_vec(rem_tokens));
+        consumed += rem_tokens.len();
+        let value = self.text(rem_tokens);
 
-        return Ok((
+        Ok((
             Ast::Statement(Statement::Set { identifier, value }),
             consumed,
-        ));
+        ))
     }
 
-    fn try_parse_log(tokens: &[Token]) -> Result<(Ast, usize)> {
-        println!("parsing log");
-        let Some(Token::Identifier(data)) = tokens.get(1) else {
-            unreachable!();
+    fn try_parse_log(&self, tokens: &'a [Token<'src>]) -> Result<(Ast<'src>, usize)> {
+        let mut consumed = 1; // log
+
+        let rem_tokens = Parser::try_extract_until_newline(&tokens[1..])?;
+        consumed += rem_tokens.len();
+        let mut message = self.words(rem_tokens);
+        let options_len = message
+            .iter()
+            .take_while(|word| word.starts_with(b"-"))
+            .count();
+        let options: Vec<Text> = message.drain(..options_len).collect();
+        // `log [<destination>] [<facility>.<level>] <message>`
+        let is_target = |word: Option<&Text>| {
+            word.is_some_and(|w| w.contains(&b'.') && w.first().is_some_and(u8::is_ascii_lowercase))
+        };
+        let destination = match message.len() >= 3 && is_target(message.get(1)) {
+            true => Some(message.remove(0)),
+            false => None,
         };
-        let consumed = 3; // starts from 3 for the log keyword, the bucket, the value
-        let Some(Token::Other(content)) = tokens.get(2) else {
-            unreachable!();
+        let target = match message.len() >= 2 || (message.len() == 1 && is_target(message.first()))
+        {
+            true => Some(message.remove(0)),
+            false => None,
         };
-        let bucket = data.to_vec();
-        let value = content.to_vec();
 
-        return Ok((Ast::Statement(Statement::Log { bucket, value }), consumed));
+        Ok((
+            Ast::Statement(Statement::Log {
+                options,
+                destination,
+                target,
+                message,
+            }),
+            consumed,
+        ))
     }
 
-    fn try_parse_statement(tokens: &[Token]) -> Result<(Ast, usize)> {
-        println!("parsing other statement");
+    fn try_parse_statement(&self, tokens: &'a [Token<'src>]) -> Result<(Ast<'src>, usize)> {
         let mut consumed = 0;
 
-        let statement_tokens = Parser::try_extract_until_newline(tokens)?;
-        consumed += statement_tokens.len() + 1;
-        let data = Parser::parse_vec(statement_tokens);
+        let statement_tokens = self.try_extract_command(tokens)?;
+        consumed += statement_tokens.len();
+        let data = self.text(statement_tokens);
+
+        Ok((Ast::Statement(Statement::Other { data }), consumed))
+    }
+
+    fn try_parse_package_require(&self, tokens: &'a [Token<'src>]) -> Result<(Ast<'src>, usize)> {
+        let mut consumed = 2; // package require
 
-        return Ok((Ast::Statement(Statement::Other { data }), consumed));
+        let rem_tokens = Parser::try_extract_until_newline(&tokens[2..])?;
+        consumed += rem_tokens.len();
+        let arguments = self.text(rem_tokens);
+
+        Ok((
+            Ast::Statement(Statement::PackageRequire { arguments }),
+            consumed,
+        ))
     }
 
-    fn try_parse_node(tokens: &[Token]) -> Result<(Ast, usize)> {
-        println!("parsing node statement");
+    fn try_parse_source(&self, tokens: &'a [Token<'src>]) -> Result<(Ast<'src>, usize)> {
+        let mut consumed = 1; // source
+
+        let rem_tokens = Parser::try_extract_until_newline(&tokens[1..])?;
+        consumed += rem_tokens.len();
+        let path = self.text(rem_tokens);
+
+        Ok((Ast::Statement(Statement::Source { path }), consumed))
+    }
+
+    fn try_parse_namespace_import(&self, tokens: &'a [Token<'src>]) -> Result<(Ast<'src>, usize)> {
+        let mut consumed = 2; // namespace import
+
+        let rem_tokens = Parser::try_extract_until_newline(&tokens[2..])?;
+        consumed += rem_tokens.len();
+        let patterns = self.text(rem_tokens);
+
+        Ok((
+            Ast::Statement(Statement::NamespaceImport { patterns }),
+            consumed,
+        ))
+    }
+
+    fn try_parse_class(&self, tokens: &'a [Token<'src>]) -> Result<(Ast<'src>, usize)> {
+        let mut consumed = 2; // class & the subcommand
+
+        let rem_tokens = Parser::try_extract_until_newline(&tokens[2..])?;
+        consumed += rem_tokens.len();
+        let mut arguments = self.words(rem_tokens);
+        // options run up to the first other word, or up to and including `--`
+        let mut options_len = arguments
+            .iter()
+            .take_while(|word| word.starts_with(b"-") && word[..] != b"--"[..])
+            .count();
+        if arguments
+            .get(options_len)
+            .is_some_and(|word| word[..] == b"--"[..])
+        {
+            options_len += 1;
+        }
+        let options = arguments.drain(..options_len).collect();
+
+        Ok((
+            Ast::Statement(Statement::Class {
+                subcommand: self.text(&tokens[1..2]),
+                options,
+                arguments,
+            }),
+            consumed,
+        ))
+    }
+
+    fn try_parse_table(&self, tokens: &'a [Token<'src>]) -> Result<(Ast<'src>, usize)> {
+        let mut consumed = 2; // table & the subcommand
+
+        let rem_tokens = Parser::try_extract_until_newline(&tokens[2..])?;
+        consumed += rem_tokens.len();
+        let mut arguments = self.words(rem_tokens);
+        // options run up to the first other word, or up to and including
+        // `--`; `-subtable` is followed by the subtable name
+        let mut options_len = 0;
+        while let Some(option) = arguments.get(options_len) {
+            if !option.starts_with(b"-") {
+                break;
+            }
+            options_len += match &option[..] {
+                b"-subtable" => 2,
+                _ => 1,
+            };
+            if option[..] == b"--"[..] {
+                break;
+            }
+        }
+        let options = arguments
+            .drain(..options_len.min(arguments.len()))
+            .collect();
+
+        Ok((
+            Ast::Statement(Statement::Table {
+                subcommand: self.text(&tokens[1..2]),
+                options,
+                arguments,
+            }),
+            consumed,
+        ))
+    }
+
+    // `after <ms> [-periodic] { script }`; other forms of `after` are statements
+    fn try_parse_after(&self, tokens: &'a [Token<'src>]) -> Result<(Ast<'src>, usize)> {
+        let Some(brace) = tokens
+            .iter()
+            .position(|t| matches!(t, Token::LCurlyBracket | Token::Newline))
+            .filter(|&idx| matches!(tokens[idx], Token::LCurlyBracket) && idx > 1)
+        else {
+            return self.try_parse_statement(tokens);
+        };
+        let arguments = self.words(&tokens[1..brace]);
+
+        let body_tokens = Parser::try_extract_block(&tokens[brace..])?;
+        let consumed = brace + body_tokens.len() + 2;
+        if !matches!(tokens.get(consumed), Some(Token::Newline) | None) {
+            return self.try_parse_statement(tokens);
+        }
+        let body = match self.try_parse(body_tokens) {
+            Ok((body, _)) => body,
+            Err(e @ ParserFail::TooDeep(_)) => return Err(e),
+            Err(_) => return self.try_parse_statement(tokens),
+        };
+
+        Ok((
+            Ast::Script {
+                command: self.text(&tokens[..1]),
+                arguments,
+                body: Box::new(body),
+            },
+            consumed,
+        ))
+    }
+
+    fn try_parse_node(&self, tokens: &'a [Token<'src>]) -> Result<(Ast<'src>, usize)> {
         let mut consumed = 1;
 
         let mut rem_tokens = Parser::try_extract_until_newline(&tokens[1..])?;
-        consumed += rem_tokens.len() + 1;
+        consumed += rem_tokens.len();
 
-        let (ip_address, n) = Parser::try_parse_expression(&rem_tokens)?;
+        let (ip_address, n) = self.try_parse_expression(rem_tokens)?;
         rem_tokens = &rem_tokens[n..];
 
-        let (port, n) = Parser::try_parse_expression(&rem_tokens)?;
+        let (port, n) = self.try_parse_expression(rem_tokens)?;
         rem_tokens = &rem_tokens[n..];
 
-        assert!(rem_tokens.is_empty());
+        if !rem_tokens.is_empty() {
+            return Err(ParserFail::Expression);
+        }
 
-        return Ok((
+        Ok((
             Ast::Statement(Statement::Node { ip_address, port }),
             consumed,
-        ));
+        ))
     }
 
-    fn try_parse_pool(tokens: &[Token]) -> Result<(Ast, usize)> {
-        println!("parsing pool statement");
+    fn try_parse_return(&self, tokens: &'a [Token<'src>]) -> Result<(Ast<'src>, usize)> {
+        let mut consumed = 1; // return
+
+        let rem_tokens = Parser::try_extract_until_newline(&tokens[1..])?;
+        consumed += rem_tokens.len();
+
+        // `return ?-option value ...? ?result?`, so with an odd number of
+        // words the last is the result
+        let mut words = self.words(rem_tokens);
+        let value = match words.len() % 2 {
+            1 => words.pop(),
+            _ => None,
+        };
+        let mut words = words.into_iter();
+        let mut options = Vec::new();
+        while let (Some(name), Some(value)) = (words.next(), words.next()) {
+            options.push((name, value));
+        }
+
+        Ok((
+            Ast::Statement(Statement::Return { options, value }),
+            consumed,
+        ))
+    }
+
+    fn try_parse_pool(&self, tokens: &'a [Token<'src>]) -> Result<(Ast<'src>, usize)> {
         let mut consumed = 1; // pool
 
         let rem_tokens = Parser::try_extract_until_newline(&tokens[1..])?;
-        consumed += rem_tokens.len() + 1;
+        consumed += rem_tokens.len();
 
-        return Ok((
+        Ok((
             Ast::Statement(Statement::Pool {
-                identifier: Parser::parse_vec(rem_tokens),
+                identifier: self.text(rem_tokens),
             }),
             consumed,
-        ));
+        ))
     }
 
-    fn try_parse_snat(tokens: &[Token]) -> Result<(Ast, usize)> {
-        println!("parsing snat statement");
+    fn try_parse_snat(&self, tokens: &'a [Token<'src>]) -> Result<(Ast<'src>, usize)> {
         let mut consumed = 1;
 
         let mut rem_tokens = Parser::try_extract_until_newline(&tokens[1..])?;
-        consumed += rem_tokens.len() + 1;
+        consumed += rem_tokens.len();
 
-        let (ip_address, n) = Parser::try_parse_expression(&rem_tokens)?;
+        let (ip_address, n) = self.try_parse_expression(rem_tokens)?;
         rem_tokens = &rem_tokens[n..];
 
-        let (port, n) = Parser::try_parse_expression(&rem_tokens)?;
+        let (port, n) = self.try_parse_expression(rem_tokens)?;
         rem_tokens = &rem_tokens[n..];
 
-        assert!(rem_tokens.is_empty());
+        if !rem_tokens.is_empty() {
+            return Err(ParserFail::Expression);
+        }
 
-        return Ok((
+        Ok((
             Ast::Statement(Statement::Snat { ip_address, port }),
             consumed,
-        ));
+        ))
     }
 
-    fn try_parse_expression(tokens: &[Token]) -> Result<(Vec<u8>, usize)> {
-        match (tokens.get(0), tokens.get(1)) {
-            (Some(Token::Identifier(data)), ..) => Ok((data.to_vec(), 1)),
+    fn try_parse_expression(&self, tokens: &'a [Token<'src>]) -> Result<(Text<'src>, usize)> {
+        match (tokens.first(), tokens.get(1)) {
+            (Some(Token::Identifier(data)), ..) => Ok((Cow::Borrowed(*data), 1)),
             (Some(Token::LSquareBracket), ..) => {
                 let body = Parser::try_extract_square_block(tokens)?;
-                Ok((Parser::parse_vec(&tokens[..body.len() + 2]), body.len() + 2))
+                Ok((self.text(&tokens[..body.len() + 2]), body.len() + 2))
             }
             (Some(Token::Dollar), Some(Token::LCurlyBracket)) => {
                 let body = Parser::try_extract_block(&tokens[1..])?;
-                Ok((Parser::parse_vec(&tokens[..body.len() + 3]), body.len() + 3))
+                Ok((self.text(&tokens[..body.len() + 3]), body.len() + 3))
             }
             _ => {
-                dbg!(&tokens[0]);
-                dbg!(String::from_utf8_lossy(&Parser::parse_vec(tokens)));
-                return Err(ParserFail::Expression);
+                if let Some(token) = tokens.first() {
+                    self.mark(token);
+                }
+                Err(ParserFail::Expression)
             }
         }
     }
 
-    fn try_parse_switch(mut tokens: &[Token]) -> Result<(Ast, usize)> {
-        println!("parsing switch");
-        let condition = {
-            let (Token::Dollar, Token::Identifier(data)) = (&tokens[1], &tokens[2]) else {
-                unreachable!();
-            };
-            let mut value = Vec::from(&tokens[1]);
-            value.extend(data);
-            value
-        };
-        let mut consumed = 4; // starts from 4 for the switch keyword, dollar, identifier, curly bracket
-        tokens = &tokens[3..];
+    fn try_parse_switch(&self, mut tokens: &'a [Token<'src>]) -> Result<(Ast<'src>, usize)> {
+        // options and the value run up to the `{` of the arms, e.g. `-glob -- $x`
+        let mut condition_len = 0;
+        while !matches!(
+            tokens.get(1 + condition_len),
+            Some(Token::LCurlyBracket) | None
+        ) {
+            if matches!(tokens[1 + condition_len], Token::Newline) {
+                self.mark(&tokens[1 + condition_len]);
+                return Err(ParserFail::NoBlock);
+            }
+            condition_len += self.first_word_len(&tokens[1 + condition_len..]);
+        }
+        if condition_len == 0 {
+            return Err(ParserFail::Expression);
+        }
+        let condition = self.text(&tokens[1..1 + condition_len]);
+        let mut consumed = 1 + condition_len; // the switch keyword & the condition
+        tokens = &tokens[consumed..];
 
         tokens = Parser::try_extract_block(tokens)?;
         consumed += tokens.len() + 2;
@@ -251,157 +652,237 @@ impl Parser {
         let mut value_block_or_fallthrough_vec = Vec::new();
 
         while !tokens.is_empty() {
-            match (
-                tokens.get(0),
-                tokens.get(1),
-                tokens.get(2),
-                tokens.get(3),
-                tokens.get(4),
-            ) {
-                (Some(Token::Newline), ..) => tokens = &tokens[1..],
-                (
-                    Some(Token::Quote),
-                    Some(Token::Identifier(_)),
-                    Some(Token::Quote),
-                    Some(Token::Minus),
-                    Some(Token::Newline),
-                ) => {
-                    // fallthrough
-                    let v = Parser::parse_vec(&tokens[..3]);
-                    tokens = &tokens[5..];
-                    value_block_or_fallthrough_vec.push((v, None));
-                }
-                (
-                    Some(Token::Quote),
-                    Some(Token::Identifier(_)),
-                    Some(Token::Quote),
-                    Some(Token::LCurlyBracket),
-                    ..,
-                ) => {
-                    // no fallthrough
-                    let v = Parser::parse_vec(&tokens[..3]);
-                    tokens = &tokens[3..];
-                    let body_tokens = Parser::try_extract_block(tokens)?;
-                    tokens = &tokens[body_tokens.len() + 2..];
-                    let (body, _) = Parser::try_parse(body_tokens)?;
-                    value_block_or_fallthrough_vec.push((v, Some(body)));
-                }
-                (Some(Token::Identifier(value)), Some(Token::LCurlyBracket), ..)
-                    if value == b"default" =>
-                {
-                    // default
-                    // TODO: assert this is the last condition-block
-                    tokens = &tokens[1..];
-                    let body_tokens = Parser::try_extract_block(tokens)?;
-                    tokens = &tokens[body_tokens.len() + 2..];
-                    let (body, _) = Parser::try_parse(body_tokens)?;
-                    value_block_or_fallthrough_vec.push((value.to_vec(), Some(body)));
-                }
+            match tokens.first() {
+                Some(Token::Newline) => tokens = &tokens[1..],
                 _ => {
-                    dbg!(&tokens[0]);
-                    return Err(ParserFail::SwitchBlock);
+                    // a pattern, then `-` to fall through to the next arm's body, or a body
+                    let len = self.first_word_len(tokens);
+                    let value = self.text(&tokens[..len]);
+                    tokens = &tokens[len..];
+                    match (tokens.first(), tokens.get(1)) {
+                        (Some(Token::Minus), Some(Token::Newline)) => {
+                            tokens = &tokens[2..];
+                            value_block_or_fallthrough_vec.push((value, None));
+                        }
+                        (Some(Token::LCurlyBracket), _) => {
+                            let body_tokens = Parser::try_extract_block(tokens)?;
+                            tokens = &tokens[body_tokens.len() + 2..];
+                            let (body, _) = self.try_parse(body_tokens)?;
+                            value_block_or_fallthrough_vec.push((value, Some(body)));
+                        }
+                        (token, _) => {
+                            if let Some(token) = token {
+                                self.mark(token);
+                            }
+                            return Err(ParserFail::SwitchBlock);
+                        }
+                    }
                 }
             }
         }
 
-        return Ok((
+        Ok((
             Ast::Switch {
                 condition,
                 value_block_or_fallthrough_vec,
             },
             consumed,
-        ));
+        ))
+    }
+
+    fn try_parse(&self, tokens: &'a [Token<'src>]) -> Result<(Ast<'src>, usize)> {
+        let depth = self.depth.get();
+        if depth > self.max_depth {
+            if let Some(token) = tokens.iter().find(|t| !matches!(t, Token::Newline)) {
+                self.mark(token);
+            }
+            return Err(ParserFail::TooDeep(self.max_depth));
+        }
+        self.depth.set(depth + 1);
+        let parsed = self.try_parse_block(tokens);
+        self.depth.set(depth);
+        parsed
     }
 
-    fn try_parse(mut tokens: &[Token]) -> Result<(Ast, usize)> {
-        println!("-> recursive call to try_parse");
+    fn try_parse_block(&self, mut tokens: &'a [Token<'src>]) -> Result<(Ast<'src>, usize)> {
         let mut trees = Vec::new();
         let mut total_consumed = 0;
         loop {
-            let (ast, consumed) = match Parser::try_parse_one(tokens)? {
+            // `a; b`: the command before the `;` is parsed on its own
+            if let Some(end) = self.separator(tokens) {
+                let (Some(ast), consumed) = self.try_parse_one(&tokens[..end])? else {
+                    return Err(ParserFail::UnknownAST);
+                };
+                if consumed < end {
+                    self.mark(&tokens[consumed]);
+                    return Err(ParserFail::NoNewline);
+                }
+                tokens = &tokens[end..];
+                total_consumed += end;
+                trees.extend([ast, Ast::Semicolon]);
+                continue;
+            }
+            let (ast, consumed) = match self.try_parse_one(tokens)? {
                 (None, 0) => break,
-                (None, 1) => {
-                    tokens = &tokens[1..];
-                    total_consumed += 1;
-                    println!("ate a newline");
+                (None, consumed) => {
+                    tokens = &tokens[consumed..];
+                    total_consumed += consumed;
                     continue;
                 }
                 (Some(ast), consumed) => (ast, consumed),
-                _ => unreachable!(),
             };
             tokens = &tokens[consumed..];
             total_consumed += consumed;
-            dbg!(&ast);
             trees.push(ast);
         }
         Ok((Ast::Block(trees), total_consumed))
     }
 
-    fn try_parse_one(tokens: &[Token]) -> Result<(Option<Ast>, usize)> {
-        let (ast, consumed) = match (tokens.get(0), tokens.get(1), tokens.get(2), tokens.get(3)) {
+    fn try_parse_one(&self, tokens: &'a [Token<'src>]) -> Result<(Option<Ast<'src>>, usize)> {
+        if let Some(token) = tokens.first() {
+            self.mark(token);
+        }
+        let (ast, consumed) = match (tokens.first(), tokens.get(1), tokens.get(2), tokens.get(3)) {
             (Some(Token::Hash), Some(Token::Other(comment_text)), Some(Token::Newline), ..) => {
                 // comment
-                let ast = Ast::Comment(comment_text.to_vec());
+                let text = Cow::Borrowed(*comment_text);
+                let ast = match self.trails_statement(tokens) {
+                    true => Ast::TrailingComment(text),
+                    false => match self.verbatim(tokens) {
+                        Some(line) => Ast::Verbatim(line),
+                        None => Ast::Comment(text),
+                    },
+                };
                 Ok((ast, 2))
             }
-            (Some(Token::KeywordIf), Some(Token::LCurlyBracket), ..) => {
-                Parser::try_parse_if(tokens)
-            }
+            (Some(Token::KeywordIf), Some(_), ..) => self.try_parse_if(tokens),
             (
                 Some(Token::KeywordWhen),
                 Some(Token::Identifier(_)),
                 Some(Token::LCurlyBracket),
                 ..,
-            ) => Parser::try_parse_when(tokens),
-            (Some(Token::KeywordSet), Some(Token::Identifier(_)), ..) => {
-                Parser::try_parse_set(tokens)
+            ) => self.try_parse_when(tokens),
+            (Some(Token::KeywordProc), Some(Token::Identifier(_)), ..) => {
+                self.try_parse_proc(tokens)
             }
-            (Some(Token::KeywordNode), ..) => Parser::try_parse_node(tokens),
-            (Some(Token::KeywordSnat), ..) => Parser::try_parse_snat(tokens),
-            (Some(Token::KeywordPool), Some(Token::Identifier(_)), Some(Token::Newline), ..)
+            (Some(Token::KeywordSet), Some(Token::Identifier(_)), ..) => self.try_parse_set(tokens),
+            (Some(Token::KeywordNode), ..) => self.try_parse_node(tokens),
+            (Some(Token::KeywordSnat), ..) => self.try_parse_snat(tokens),
+            (
+                Some(Token::KeywordPool),
+                Some(Token::Identifier(_)),
+                Some(Token::Newline) | None,
+                ..,
+            )
             | (
                 Some(Token::KeywordPool),
                 Some(Token::Dollar),
                 Some(Token::Identifier(_)),
-                Some(Token::Newline),
+                Some(Token::Newline) | None,
                 ..,
-            ) => Parser::try_parse_pool(tokens),
-            (Some(Token::KeywordLog), Some(Token::Identifier(_)), Some(Token::Other(_)), ..) => {
-                Parser::try_parse_log(tokens)
+            ) => self.try_parse_pool(tokens),
+            (Some(Token::KeywordLog), Some(next), ..) if !matches!(next, Token::Newline) => {
+                self.try_parse_log(tokens)
+            }
+            (Some(Token::KeywordSwitch), Some(next), ..) if !matches!(next, Token::Newline) => {
+                self.try_parse_switch(tokens)
+            }
+            // `HTTP::redirect ...`, `STREAM::expression ...` and calls of procs
+            // in other namespaces
+            (Some(Token::Identifier(command)), ..) if namespace(command).is_some() => {
+                self.try_parse_statement(tokens)
             }
             (
-                Some(Token::KeywordSwitch),
-                Some(Token::Dollar),
-                Some(Token::Identifier(_)),
-                Some(Token::LCurlyBracket),
+                Some(Token::Identifier(b"package")),
+                Some(Token::Identifier(b"require")),
+                Some(Token::Identifier(_) | Token::Minus),
+                ..,
+            ) => self.try_parse_package_require(tokens),
+            (
+                Some(Token::Identifier(b"class")),
+                Some(Token::Identifier(b"match" | b"search" | b"lookup")),
+                Some(next),
                 ..,
-            ) => Parser::try_parse_switch(tokens),
+            ) if !matches!(next, Token::Newline) => self.try_parse_class(tokens),
             (
-                Some(Token::Identifier(group)),
-                Some(Token::DoubleColon),
+                Some(Token::Identifier(b"table")),
+                Some(
+                    Token::KeywordSet
+                    | Token::Identifier(
+                        b"add" | b"replace" | b"lookup" | b"incr" | b"append" | b"delete"
+                        | b"timeout" | b"lifetime" | b"keys",
+                    ),
+                ),
+                ..,
+            ) => self.try_parse_table(tokens),
+            // `array set` takes lists, which the formatter may lay out over lines
+            (Some(Token::Identifier(b"array")), Some(next), ..)
+                if !matches!(next, Token::Newline) =>
+            {
+                self.try_parse_statement(tokens)
+            }
+            (Some(Token::Identifier(b"after")), Some(next), ..)
+                if !matches!(next, Token::Newline) =>
+            {
+                self.try_parse_after(tokens)
+            }
+            (Some(Token::Identifier(b"source")), Some(next), ..)
+                if !matches!(next, Token::Newline) =>
+            {
+                self.try_parse_source(tokens)
+            }
+            (
+                Some(Token::Identifier(b"namespace")),
+                Some(Token::Identifier(b"import")),
+                Some(next),
+                ..,
+            ) if !matches!(next, Token::Newline) => self.try_parse_namespace_import(tokens),
+            (Some(Token::Identifier(b"eval")), Some(Token::LCurlyBracket), ..)
+            | (Some(Token::Identifier(b"uplevel")), Some(Token::LCurlyBracket), ..)
+            | (
+                Some(Token::Identifier(b"uplevel")),
                 Some(Token::Identifier(_)),
+                Some(Token::LCurlyBracket),
                 ..,
-            ) if group == b"UDP" || group == b"GTP" => Parser::try_parse_statement(tokens),
-            (Some(Token::KeywordReturn), Some(Token::Newline), ..) => {
-                Ok((Ast::Statement(Statement::Return { value: None }), 2))
+            ) => self.try_parse_script(tokens),
+            // dynamic scripts, and `subst`, whose argument is a string, not a script
+            (Some(Token::Identifier(b"eval" | b"uplevel" | b"subst")), Some(next), ..)
+                if !matches!(next, Token::Newline) =>
+            {
+                self.try_parse_statement(tokens)
             }
+            (Some(Token::KeywordReturn), Some(Token::Newline) | None, ..) => Ok((
+                Ast::Statement(Statement::Return {
+                    options: Vec::new(),
+                    value: None,
+                }),
+                1,
+            )),
+            (Some(Token::KeywordReturn), ..) => self.try_parse_return(tokens),
+            // any other command, `while` and `foreach` included, is kept as written
+            (
+                Some(
+                    Token::Identifier(_)
+                    | Token::Dollar
+                    | Token::LSquareBracket
+                    | Token::Quote
+                    | Token::Other(_),
+                ),
+                ..,
+            ) => self.try_parse_statement(tokens),
 
             (Some(Token::Newline), Some(Token::Newline), ..) => Ok((Ast::EmptyLine, 2)),
-            (Some(Token::Newline), _, ..) => return Ok((None, 1)), // eat newline
+            (Some(Token::Newline), ..) => return Ok((None, 1)), // eat newline
             (None, ..) => return Ok((None, 0)),
-            _ => {
-                dbg!(&tokens[0]);
-                dbg!(&tokens[1]);
-                dbg!(&tokens[2]);
-                dbg!(&tokens[3]);
-                return Err(ParserFail::UnknownAST);
-            } // TODO:
+            _ => return Err(ParserFail::UnknownAST), // TODO:
         }?;
         Ok((Some(ast), consumed))
     }
 
-    fn try_extract_block(tokens: &[Token]) -> Result<&[Token]> {
-        assert!(matches!(tokens.get(0), Some(Token::LCurlyBracket)));
+    fn try_extract_block(tokens: &'a [Token<'src>]) -> Result<&'a [Token<'src>]> {
+        if !matches!(tokens.first(), Some(Token::LCurlyBracket)) {
+            return Err(ParserFail::NoBlock);
+        }
         let mut depth = 0;
         for (idx, token) in tokens.iter().enumerate() {
             match token {
@@ -416,8 +897,10 @@ impl Parser {
         Err(ParserFail::BracketMismatch)
     }
 
-    fn try_extract_square_block(tokens: &[Token]) -> Result<&[Token]> {
-        assert!(matches!(tokens.get(0), Some(Token::LSquareBracket)));
+    fn try_extract_square_block(tokens: &'a [Token<'src>]) -> Result<&'a [Token<'src>]> {
+        if !matches!(tokens.first(), Some(Token::LSquareBracket)) {
+            return Err(ParserFail::BracketMismatch);
+        }
         let mut depth = 0;
         for (idx, token) in tokens.iter().enumerate() {
             match token {
@@ -432,22 +915,492 @@ impl Parser {
         Err(ParserFail::BracketMismatch)
     }
 
-    fn try_extract_until_newline(tokens: &[Token]) -> Result<&[Token]> {
-        let start = 0;
+    // the tokens up to the newline ending the line, or up to the end of a
+    // body written on one line, like `{ pool p1 }`
+    fn try_extract_until_newline(tokens: &'a [Token<'src>]) -> Result<&'a [Token<'src>]> {
         let end = tokens
             .iter()
-            .enumerate()
-            .take_while(|(_, t)| !matches!(t, Token::Newline))
-            .last()
-            .map(|(idx, _)| idx)
-            .unwrap_or(0);
-        match tokens.get(end + 1) {
-            Some(Token::Newline) => Ok(&tokens[start..=end]),
-            _ => Err(ParserFail::NoNewline),
+            .position(|t| matches!(t, Token::Newline))
+            .unwrap_or(tokens.len());
+        match end {
+            0 => Err(ParserFail::NoNewline),
+            _ => Ok(&tokens[..end]),
+        }
+    }
+
+    // The tokens of the command starting `tokens`, up to the newline ending
+    // it or the end of a one-line body: newlines inside braces or brackets
+    // continue the command, like a list laid out over several lines
+    fn try_extract_command(&self, tokens: &'a [Token<'src>]) -> Result<&'a [Token<'src>]> {
+        let (mut depth, mut quoted) = (0usize, false);
+        for (idx, token) in tokens.iter().enumerate() {
+            let escaped = idx > 0
+                && matches!(tokens[idx - 1], Token::Other(b"\\"))
+                && self
+                    .tokens
+                    .element_offset(token)
+                    .is_some_and(|offset| self.spans[offset - 1].end == self.spans[offset].start);
+            match token {
+                Token::Newline if depth == 0 => {
+                    return match idx {
+                        0 => Err(ParserFail::NoNewline),
+                        _ => Ok(&tokens[..idx]),
+                    };
+                }
+                Token::LSquareBracket if !escaped => depth += 1,
+                Token::RSquareBracket if !escaped => depth = depth.saturating_sub(1),
+                Token::LCurlyBracket if !escaped && !quoted => depth += 1,
+                Token::RCurlyBracket if !escaped && !quoted => depth = depth.saturating_sub(1),
+                Token::Quote if !escaped && depth == 0 => quoted = !quoted,
+                _ => {}
+            }
+        }
+        match tokens.is_empty() {
+            true => Err(ParserFail::NoNewline),
+            // a bracket or brace left open runs to the end of the input
+            false if depth > 0 => Err(ParserFail::BracketMismatch),
+            false => Ok(tokens),
+        }
+    }
+
+    // The index of the token starting the next command of the line, when a
+    // `;` outside of quotes, braces and brackets separates it from the first.
+    // The lexer reads `;` as whitespace, so it is found in the gaps between
+    // tokens.
+    fn separator(&self, tokens: &[Token<'src>]) -> Option<usize> {
+        let first = self.tokens.element_offset(tokens.first()?)?;
+        let (mut depth, mut quoted) = (0usize, false);
+        for (idx, token) in tokens.iter().enumerate() {
+            if idx > 0 && depth == 0 && !quoted && !matches!(token, Token::Newline) {
+                let start = self.spans[first + idx - 1].end;
+                let gap = &self.source[start..self.spans[first + idx].start];
+                let escaped = |pos: usize| self.source[start + pos - 1] == b'\\';
+                if gap
+                    .iter()
+                    .enumerate()
+                    .any(|(pos, &c)| c == b';' && !escaped(pos))
+                {
+                    return Some(idx);
+                }
+            }
+            match token {
+                Token::Newline if depth == 0 && !quoted => return None,
+                Token::Quote if depth == 0 => quoted = !quoted,
+                Token::LCurlyBracket if !quoted => depth += 1,
+                Token::RCurlyBracket if !quoted => depth = depth.saturating_sub(1),
+                Token::LSquareBracket => depth += 1,
+                Token::RSquareBracket => depth = depth.saturating_sub(1),
+                _ => {}
+            }
         }
+        None
+    }
+
+    // a shebang starting the source, or a modeline: `#` and its text as written
+    fn verbatim(&self, tokens: &[Token<'src>]) -> Option<Text<'src>> {
+        let idx = self.tokens.element_offset(tokens.first()?)?;
+        let line = &self.source[self.spans[idx].start..self.spans[idx + 1].end];
+        let shebang = modeline::is_shebang(line) && self.spans[idx].start == 0;
+        (shebang || modeline::is_modeline(line)).then_some(Cow::Borrowed(line))
     }
 
-    fn parse_vec(tokens: &[Token]) -> Vec<u8> {
-        tokens.into_iter().flat_map(|t| Vec::from(t)).collect()
+    // whether the comment starting `tokens` follows a statement on the same
+    // line: the lexer ends the statement with a newline that takes no space
+    fn trails_statement(&self, tokens: &[Token<'src>]) -> bool {
+        let Some(idx) = tokens.first().and_then(|t| self.tokens.element_offset(t)) else {
+            return false;
+        };
+        idx > 0 && matches!(self.tokens[idx - 1], Token::Newline) && self.spans[idx - 1].is_empty()
+    }
+
+    // the words of a command line, split at whitespace outside of quotes,
+    // braces and brackets
+    fn words(&self, mut tokens: &[Token<'src>]) -> Vec<Text<'src>> {
+        let mut words = Vec::new();
+        while !tokens.is_empty() {
+            let len = self.first_word_len(tokens);
+            words.push(self.text(&tokens[..len]));
+            tokens = &tokens[len..];
+        }
+        words
+    }
+
+    // the number of tokens in the word starting `tokens`, at least one
+    fn first_word_len(&self, tokens: &[Token<'src>]) -> usize {
+        let (mut depth, mut quoted) = (0usize, false);
+        for (idx, token) in tokens.iter().enumerate() {
+            let Some(offset) = self.tokens.element_offset(token) else {
+                continue;
+            };
+            let gap = idx > 0 && self.spans[offset - 1].end < self.spans[offset].start;
+            if gap && depth == 0 && !quoted {
+                return idx;
+            }
+            let escaped = idx > 0 && !gap && matches!(tokens[idx - 1], Token::Other(b"\\"));
+            match token {
+                Token::LSquareBracket if !escaped => depth += 1,
+                Token::RSquareBracket if !escaped => depth = depth.saturating_sub(1),
+                Token::LCurlyBracket if !escaped && !quoted => depth += 1,
+                Token::RCurlyBracket if !escaped && !quoted => depth = depth.saturating_sub(1),
+                Token::Quote if !escaped && depth == 0 => quoted = !quoted,
+                _ => {}
+            }
+        }
+        tokens.len()
+    }
+
+    // the source text covered by `tokens`, a subslice of `self.tokens`
+    fn text(&self, tokens: &[Token<'src>]) -> Text<'src> {
+        let (Some(first), Some(last)) = (tokens.first(), tokens.last()) else {
+            return Cow::Borrowed(b"");
+        };
+        let (Some(start), Some(end)) = (
+            self.tokens.element_offset(first),
+            self.tokens.element_offset(last),
+        ) else {
+            return Cow::Borrowed(b"");
+        };
+        Cow::Borrowed(&self.source[self.spans[start].start..self.spans[end].end])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Parser, ParserFail, DEFAULT_MAX_DEPTH};
+    use crate::ast::{Ast, Statement};
+    use crate::lexer::Lexer;
+
+    fn parse(source: &str) -> Result<Ast<'_>, ParserFail> {
+        let (tokens, spans) = Lexer::new().lex_with_spans(source.as_bytes()).unwrap();
+        Parser::new(source.as_bytes(), &tokens, &spans)
+            .parse()
+            .map(|ast| ast.into_owned())
+            .map_err(|e| e.kind)
+    }
+
+    fn elseif_chain(clauses: usize) -> String {
+        let mut source = String::from("if {$a == 0} {\n    set b 0\n}");
+        for i in 1..clauses {
+            source.push_str(&format!(" elseif {{$a == {i}}} {{\n    set b {i}\n}}"));
+        }
+        source.push_str(" else {\n    set b -1\n}\n");
+        source
+    }
+
+    fn clauses(ast: &Ast) -> (usize, bool) {
+        let Ast::Block(statements) = ast else {
+            panic!("expected a block, got {ast:?}");
+        };
+        match &statements[..] {
+            [Ast::If {
+                condition_body_clauses,
+                maybe_block_if_false,
+            }] => (condition_body_clauses.len(), maybe_block_if_false.is_some()),
+            other => panic!("expected one if, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parses_elseif_clauses_in_order() {
+        assert_eq!(clauses(&parse(&elseif_chain(3)).unwrap()), (3, true));
+        let source = "if {$a} {\n    set b 1\n}\nelseif {$c} {\n    set b 2\n}\n";
+        assert_eq!(clauses(&parse(source).unwrap()), (2, false));
+    }
+
+    // the options and result of the lone `return` in `source`
+    fn return_words(source: &str) -> (Vec<(String, String)>, Option<String>) {
+        let text = |word: &[u8]| String::from_utf8(word.to_vec()).unwrap();
+        match parse(source).unwrap() {
+            Ast::Block(statements) => match &statements[..] {
+                [Ast::Statement(Statement::Return { options, value })] => (
+                    options
+                        .iter()
+                        .map(|(name, value)| (text(name), text(value)))
+                        .collect(),
+                    value.as_deref().map(text),
+                ),
+                other => panic!("expected one return, got {other:?}"),
+            },
+            other => panic!("expected a block, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn splits_return_options_from_the_result() {
+        assert_eq!(return_words("return\n"), (vec![], None));
+        assert_eq!(return_words("return $a\n"), (vec![], Some("$a".into())));
+        let option = |name: &str, value: &str| (name.to_string(), value.to_string());
+        assert_eq!(
+            return_words("return -code error \"bad $x\"\n"),
+            (vec![option("-code", "error")], Some("\"bad $x\"".into()))
+        );
+        // with an even number of words there is no result
+        assert_eq!(
+            return_words("return -code error -errorinfo [info $e]\n"),
+            (
+                vec![option("-code", "error"), option("-errorinfo", "[info $e]")],
+                None
+            )
+        );
+    }
+
+    #[test]
+    fn parses_long_elseif_chains() {
+        assert_eq!(
+            clauses(&parse(&elseif_chain(100_000)).unwrap()),
+            (100_000, true)
+        );
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        let depth = 100_000;
+        let source = "if {1} {\n".repeat(depth) + "set a 1\n" + &"}\n".repeat(depth);
+        assert!(matches!(
+            parse(&source),
+            Err(ParserFail::TooDeep(DEFAULT_MAX_DEPTH))
+        ));
+        let source = "proc p {} {\n".repeat(depth) + &"}\n".repeat(depth);
+        assert!(matches!(
+            parse(&source),
+            Err(ParserFail::TooDeep(DEFAULT_MAX_DEPTH))
+        ));
+    }
+
+    #[test]
+    fn keeps_other_commands_as_written() {
+        let source = "while {$i < 3} {\n  incr i\n}\nforeach x $l { drop }\n$cmd a\n[f] b\n";
+        let Ast::Block(statements) = parse(source).unwrap() else {
+            panic!("expected a block");
+        };
+        let commands: Vec<_> = statements
+            .iter()
+            .map(|ast| match ast {
+                Ast::Statement(Statement::Other { data }) => String::from_utf8_lossy(data),
+                other => panic!("expected a command, got {other:?}"),
+            })
+            .collect();
+        assert_eq!(
+            commands,
+            [
+                "while {$i < 3} {\n  incr i\n}",
+                "foreach x $l { drop }",
+                "$cmd a",
+                "[f] b"
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_stray_closing_braces() {
+        assert!(parse("set a 1\n}\n").is_err());
+        assert!(parse("while {1} {\n").is_err());
+    }
+
+    #[test]
+    fn parses_package_source_and_import_commands() {
+        let source = "package require -exact http 2.9\nsource lib/util.tcl\nnamespace import ::json::*\npackage provide p 1.0\n";
+        let Ast::Block(statements) = parse(source).unwrap() else {
+            panic!("expected a block");
+        };
+        let text = |text: &[u8]| String::from_utf8_lossy(text).into_owned();
+        let commands: Vec<_> = statements
+            .iter()
+            .map(|ast| match ast {
+                Ast::Statement(Statement::PackageRequire { arguments }) => {
+                    format!("require {}", text(arguments))
+                }
+                Ast::Statement(Statement::Source { path }) => format!("source {}", text(path)),
+                Ast::Statement(Statement::NamespaceImport { patterns }) => {
+                    format!("import {}", text(patterns))
+                }
+                Ast::Statement(Statement::Other { data }) => format!("other {}", text(data)),
+                other => panic!("unexpected {other:?}"),
+            })
+            .collect();
+        assert_eq!(
+            commands,
+            [
+                "require -exact http 2.9",
+                "source lib/util.tcl",
+                "import ::json::*",
+                "other package provide p 1.0"
+            ]
+        );
+    }
+
+    #[test]
+    fn locates_parse_errors() {
+        let source = "when X {\n    switch $a {\n        日本 x {\n        }\n    }\n}\n";
+        let (tokens, spans) = Lexer::new().lex_with_spans(source.as_bytes()).unwrap();
+        let e = Parser::new(source.as_bytes(), &tokens, &spans)
+            .parse()
+            .unwrap_err();
+        assert!(matches!(e.kind, ParserFail::SwitchBlock));
+        // the column is in display width, `日本` taking four
+        assert_eq!((e.line, e.column), (3, 14));
+        assert_eq!(&source[e.span.clone()], "x");
+        assert_eq!(
+            e.to_string(),
+            "unexpected `x`, expected a switch pattern followed by `{` or `-`"
+        );
+    }
+
+    #[test]
+    fn reports_unclosed_blocks() {
+        let source = "when X {\n    pool p\n";
+        let (tokens, spans) = Lexer::new().lex_with_spans(source.as_bytes()).unwrap();
+        let e = Parser::new(source.as_bytes(), &tokens, &spans)
+            .parse()
+            .unwrap_err();
+        // an unclosed block points at the command it opens
+        assert!(matches!(e.kind, ParserFail::BracketMismatch));
+        assert_eq!((e.line, e.column), (1, 1));
+        assert_eq!(
+            e.to_string(),
+            "unexpected `when`, expected a closing bracket"
+        );
+    }
+
+    #[test]
+    fn parses_class_commands() {
+        let Ast::Block(statements) = parse("class match -nocase -- $x eq dg\n").unwrap() else {
+            panic!("expected a block");
+        };
+        let [Ast::Statement(Statement::Class {
+            subcommand,
+            options,
+            arguments,
+        })] = &statements[..]
+        else {
+            panic!("expected a class command, got {statements:?}");
+        };
+        let words = |words: &[crate::ast::Text]| {
+            words
+                .iter()
+                .map(|word| String::from_utf8_lossy(word).into_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(&subcommand[..], b"match");
+        assert_eq!(words(options), ["-nocase", "--"]);
+        assert_eq!(words(arguments), ["$x", "eq", "dg"]);
+    }
+
+    #[test]
+    fn splits_table_options_from_arguments() {
+        let source = "table set -notouch -subtable s1 -- -key v 60\ntable lookup k\n";
+        let Ast::Block(statements) = parse(source).unwrap() else {
+            panic!("expected a block");
+        };
+        let tables: Vec<_> = statements
+            .iter()
+            .map(|ast| match ast {
+                Ast::Statement(Statement::Table {
+                    subcommand,
+                    options,
+                    arguments,
+                }) => (
+                    String::from_utf8_lossy(subcommand).into_owned(),
+                    options.len(),
+                    arguments.len(),
+                ),
+                other => panic!("expected a table command, got {other:?}"),
+            })
+            .collect();
+        assert_eq!(
+            tables,
+            [("set".to_string(), 4, 3), ("lookup".to_string(), 0, 1)]
+        );
+    }
+
+    #[test]
+    fn parses_log_words() {
+        let source = "log -noname 10.0.0.1:514 local0.warning \"a  {b\"\nlog local0. $msg\nlog \"only a message\"\n";
+        let Ast::Block(statements) = parse(source).unwrap() else {
+            panic!("expected a block");
+        };
+        let text = |word: &crate::ast::Text| String::from_utf8_lossy(word).into_owned();
+        let logs: Vec<_> = statements
+            .iter()
+            .map(|ast| match ast {
+                Ast::Statement(Statement::Log {
+                    options,
+                    destination,
+                    target,
+                    message,
+                }) => (
+                    options.iter().map(text).collect::<Vec<_>>(),
+                    destination.as_ref().map(text),
+                    target.as_ref().map(text),
+                    message.iter().map(text).collect::<Vec<_>>(),
+                ),
+                other => panic!("expected a log, got {other:?}"),
+            })
+            .collect();
+        let some = |s: &str| Some(s.to_string());
+        assert_eq!(
+            logs,
+            [
+                (
+                    vec!["-noname".to_string()],
+                    some("10.0.0.1:514"),
+                    some("local0.warning"),
+                    vec!["\"a  {b\"".to_string()]
+                ),
+                (vec![], None, some("local0."), vec!["$msg".to_string()]),
+                (vec![], None, None, vec!["\"only a message\"".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_switch_options_and_patterns() {
+        let source = "switch -glob -- [HTTP::path] {\n\"/a b*\" -\n{/c[0-9]} {\n    pool p\n}\n/d/* {\n}\ndefault {\n}\n}\n";
+        let Ast::Block(statements) = parse(source).unwrap() else {
+            panic!("expected a block");
+        };
+        let [Ast::Switch {
+            condition,
+            value_block_or_fallthrough_vec: arms,
+        }] = &statements[..]
+        else {
+            panic!("expected a switch, got {statements:?}");
+        };
+        assert_eq!(&condition[..], b"-glob -- [HTTP::path]");
+        let arms: Vec<_> = arms
+            .iter()
+            .map(|(value, body)| (String::from_utf8_lossy(value).into_owned(), body.is_some()))
+            .collect();
+        let arm = |value: &str, body| (value.to_string(), body);
+        assert_eq!(
+            arms,
+            [
+                arm("\"/a b*\"", false),
+                arm("{/c[0-9]}", true),
+                arm("/d/*", true),
+                arm("default", true)
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_trailing_comments_from_their_statement() {
+        let ast = parse("set a 1 ;# one\n# own line\nset b 2\n").unwrap();
+        let Ast::Block(statements) = ast else {
+            panic!("expected a block");
+        };
+        let kinds: Vec<_> = statements
+            .iter()
+            .map(|ast| match ast {
+                Ast::TrailingComment(text) => format!("trailing {}", String::from_utf8_lossy(text)),
+                Ast::Comment(text) => format!("comment {}", String::from_utf8_lossy(text)),
+                Ast::Statement(_) => "statement".to_string(),
+                other => format!("{other:?}"),
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            ["statement", "trailing one", "comment own line", "statement"]
+        );
     }
 }
@@ -0,0 +1,776 @@
+// Named AST transformations, run in the configured order before formatting.
+// Passes are opt-in: users list them under `passes` in `.tclfmt.toml`.
+use std::borrow::Cow;
+
+use crate::{
+    ast::{split_words, word_len, Ast, Condition, Statement, Text},
+    comments::{attach, Attached},
+    keep_sorted::sort_arms,
+    visit::{walk_if_mut, walk_switch_mut, VisitMut},
+};
+
+#[derive(Debug)]
+pub enum PassFail {
+    Unknown(String), // no pass registered under this name
+}
+type Result<T> = std::result::Result<T, PassFail>;
+
+impl std::fmt::Display for PassFail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unknown(name) => write!(f, "unknown pass `{name}`"),
+        }
+    }
+}
+
+pub type PassFn = fn(&mut Ast);
+
+pub const PASSES: &[(&str, PassFn)] = &[
+    ("collapse-elseif", collapse_elseif),
+    ("normalize-expressions", normalize_expressions),
+    ("brace-conditions", brace_conditions),
+    ("brace-literals", brace_literals),
+    ("quote-literals", quote_literals),
+    ("sort-package-requires", sort_package_requires),
+    ("normalize-class-options", normalize_class_options),
+    ("sort-switch-arms", sort_switch_arms),
+];
+
+pub fn lookup(name: &str) -> Option<PassFn> {
+    PASSES.iter().find(|(n, _)| *n == name).map(|(_, f)| *f)
+}
+
+// all names are resolved before anything runs, so a typo leaves the AST untouched
+pub fn run(ast: &mut Ast, names: &[String]) -> Result<()> {
+    let passes = names
+        .iter()
+        .map(|name| lookup(name).ok_or_else(|| PassFail::Unknown(name.clone())))
+        .collect::<Result<Vec<_>>>()?;
+    for pass in passes {
+        pass(ast);
+    }
+    Ok(())
+}
+
+// else { if { a } { ... } } => elseif { a } { ... }
+pub fn collapse_elseif(ast: &mut Ast) {
+    struct CollapseElseIf;
+    impl VisitMut for CollapseElseIf {
+        fn visit_if_mut<'src>(
+            &mut self,
+            condition_body_clauses: &mut Vec<(Condition<'src>, Ast<'src>)>,
+            maybe_block_if_false: &mut Option<Box<Ast<'src>>>,
+        ) {
+            // children first, so a nested chain is already flat when lifted
+            walk_if_mut(self, condition_body_clauses, maybe_block_if_false);
+
+            let Some(block) = maybe_block_if_false.take() else {
+                return;
+            };
+            match *block {
+                Ast::Block(mut trees) if trees.len() == 1 && matches!(trees[0], Ast::If { .. }) => {
+                    let Some(Ast::If {
+                        condition_body_clauses: clauses,
+                        maybe_block_if_false: block_if_false,
+                    }) = trees.pop()
+                    else {
+                        unreachable!();
+                    };
+                    condition_body_clauses.extend(clauses);
+                    *maybe_block_if_false = block_if_false;
+                }
+                block => *maybe_block_if_false = Some(Box::new(block)),
+            }
+        }
+    }
+    CollapseElseIf.visit_ast_mut(ast);
+}
+
+// if $x {...} => if { $x } {...}, and [expr $x + 1] => [expr {$x + 1}]
+pub fn brace_conditions(ast: &mut Ast) {
+    struct BraceConditions;
+    impl VisitMut for BraceConditions {
+        fn visit_statement_mut(&mut self, statement: &mut Statement) {
+            for value in substituted_words(statement) {
+                if let Some(braced) = rewrite_substitutions(value, &mut brace_expr) {
+                    *value = Cow::Owned(braced);
+                }
+            }
+        }
+
+        fn visit_if_mut<'src>(
+            &mut self,
+            condition_body_clauses: &mut Vec<(Condition<'src>, Ast<'src>)>,
+            maybe_block_if_false: &mut Option<Box<Ast<'src>>>,
+        ) {
+            for (condition, _) in condition_body_clauses.iter_mut() {
+                if condition.braced {
+                    continue;
+                }
+                if let Some(range) = braceable(&condition.text) {
+                    condition.text = match std::mem::take(&mut condition.text) {
+                        Cow::Borrowed(word) => Cow::Borrowed(&word[range]),
+                        Cow::Owned(word) => Cow::Owned(word[range].to_vec()),
+                    };
+                    condition.braced = true;
+                }
+            }
+            walk_if_mut(self, condition_body_clauses, maybe_block_if_false);
+        }
+    }
+    BraceConditions.visit_ast_mut(ast);
+}
+
+// The words of `set`, `return` and unparsed commands that can hold command
+// substitutions to rewrite
+fn substituted_words<'a, 'src>(statement: &'a mut Statement<'src>) -> Vec<&'a mut Text<'src>> {
+    match statement {
+        Statement::Set { value, .. } | Statement::Other { data: value } => vec![value],
+        Statement::Return { options, value } => options
+            .iter_mut()
+            .map(|(_, value)| value)
+            .chain(value.as_mut())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// An `expr` command with its arguments in one braced word, if they weren't
+// and bracing them keeps the meaning
+fn brace_expr(command: &[u8]) -> Option<Vec<u8>> {
+    let arguments = unbraced_expr(command)?;
+    let arguments: Vec<Vec<u8>> = arguments
+        .iter()
+        .map(|word| rewrite_substitutions(word, &mut brace_expr).unwrap_or_else(|| word.to_vec()))
+        .collect();
+    let joined = arguments.join(&b' ');
+    let range = braceable(&joined)?;
+    let mut out = b"expr {".to_vec();
+    out.extend_from_slice(&joined[range]);
+    out.push(b'}');
+    Some(out)
+}
+
+// The arguments of an `expr` command, unless they are one braced word
+pub(crate) fn unbraced_expr(command: &[u8]) -> Option<Vec<&[u8]>> {
+    let words = split_words(command);
+    match &words[..] {
+        [b"expr", argument] if argument.starts_with(b"{") => None,
+        [b"expr", arguments @ ..] if !arguments.is_empty() => Some(arguments.to_vec()),
+        _ => None,
+    }
+}
+
+// The part of an unbraced condition word that goes inside the braces, if
+// bracing it keeps the meaning: the word is substituted once either way, by
+// the parser or by `expr`, so it must not hold backslash escapes or braces,
+// whose handling differs between the two, and a quoted word must be one
+// string with no quotes inside.
+pub(crate) fn braceable(word: &[u8]) -> Option<std::ops::Range<usize>> {
+    if word.iter().any(|c| matches!(c, b'\\' | b'{' | b'}')) {
+        return None;
+    }
+    let range = match word {
+        [b'"', inner @ .., b'"'] => 1..inner.len() + 1,
+        _ => 0..word.len(),
+    };
+    if word[range.clone()].contains(&b'"') || range.is_empty() {
+        return None;
+    }
+    Some(range)
+}
+
+// set x "hello" => set x {hello}
+pub fn brace_literals(ast: &mut Ast) {
+    RewriteLiterals {
+        open: b'{',
+        close: b'}',
+    }
+    .visit_ast_mut(ast);
+}
+
+// set x {hello} => set x "hello"
+pub fn quote_literals(ast: &mut Ast) {
+    RewriteLiterals {
+        open: b'"',
+        close: b'"',
+    }
+    .visit_ast_mut(ast);
+}
+
+// Rewrites `set` and `return` values that are a literal in quotes or braces
+// to use the given delimiters
+struct RewriteLiterals {
+    open: u8,
+    close: u8,
+}
+
+impl VisitMut for RewriteLiterals {
+    fn visit_statement_mut(&mut self, statement: &mut Statement) {
+        let value = match statement {
+            Statement::Set { value, .. }
+            | Statement::Return {
+                value: Some(value), ..
+            } => value,
+            _ => return,
+        };
+        if value.first() == Some(&self.open) {
+            return;
+        }
+        if let Some(literal) = literal(value) {
+            let mut rewritten = Vec::with_capacity(literal.len() + 2);
+            rewritten.push(self.open);
+            rewritten.extend_from_slice(literal);
+            rewritten.push(self.close);
+            *value = Cow::Owned(rewritten);
+        }
+    }
+}
+
+// The content of a word in quotes or braces, if it means the same in either:
+// no substitutions or escapes, which only quotes process, no delimiters, and
+// no whitespace but spaces.
+fn literal(word: &[u8]) -> Option<&[u8]> {
+    let inner = match word {
+        [b'"', inner @ .., b'"'] | [b'{', inner @ .., b'}'] => inner,
+        _ => return None,
+    };
+    let plain = !inner.iter().any(|c| {
+        matches!(c, b'$' | b'[' | b'\\' | b'"' | b'{' | b'}')
+            || (c.is_ascii_whitespace() && *c != b' ')
+    });
+    plain.then_some(inner)
+}
+
+// Moves the top-level `package require` lines, and the comments attached to
+// them, into one sorted group below the comments heading the file, dropping
+// exact duplicates
+pub fn sort_package_requires(ast: &mut Ast) {
+    let Ast::Block(trees) = ast else {
+        return;
+    };
+    // heading comments stay where they are, even right above a require
+    let heading = trees
+        .iter()
+        .take_while(|tree| matches!(tree, Ast::Comment(_) | Ast::Verbatim(_) | Ast::EmptyLine))
+        .count();
+    let mut requires = Vec::new();
+    for group in attach(trees.split_off(heading)) {
+        match group.node {
+            Ast::Statement(Statement::PackageRequire { .. }) => requires.push(group),
+            _ => trees.extend(group.into_trees()),
+        }
+    }
+    if requires.is_empty() {
+        return;
+    }
+
+    let key = |group: &Attached| match &group.node {
+        Ast::Statement(Statement::PackageRequire { arguments }) => arguments.to_vec(),
+        _ => Vec::new(),
+    };
+    requires.sort_by_cached_key(key);
+    requires.dedup_by(|a, b| key(a) == key(b) && !a.has_comments());
+
+    let mut header = trees
+        .iter()
+        .take_while(|tree| matches!(tree, Ast::Comment(_) | Ast::Verbatim(_) | Ast::EmptyLine))
+        .count();
+    // comments that were below a require document the tree after them
+    if header < trees.len() {
+        while header > heading && matches!(trees[header - 1], Ast::Comment(_)) {
+            header -= 1;
+        }
+    }
+    // an empty line between the heading comments and the group, and after it
+    let header_end = match trees[..header]
+        .iter()
+        .rposition(|t| matches!(t, Ast::Comment(_) | Ast::Verbatim(_)))
+    {
+        Some(last_comment) => last_comment + 1,
+        None => 0,
+    };
+    let mut group = Vec::new();
+    if header_end > 0 {
+        group.push(Ast::EmptyLine);
+    }
+    group.extend(requires.into_iter().flat_map(Attached::into_trees));
+    if header_end < trees.len() {
+        group.push(Ast::EmptyLine);
+    }
+    trees.splice(header_end..header, group);
+}
+
+// Sorts the arms of `switch` commands by pattern, `default` last. The first
+// matching arm wins, so `-regexp` switches, and `-glob` switches with
+// wildcards in their patterns, are left in the order written.
+pub fn sort_switch_arms(ast: &mut Ast) {
+    struct SortSwitchArms;
+    impl VisitMut for SortSwitchArms {
+        fn visit_switch_mut<'src>(
+            &mut self,
+            condition: &mut Text<'src>,
+            arms: &mut Vec<(Text<'src>, Option<Ast<'src>>)>,
+        ) {
+            walk_switch_mut(self, condition, arms);
+
+            let options = split_words(condition);
+            let options = options.iter().take_while(|word| word.starts_with(b"-"));
+            let mut glob = false;
+            for option in options {
+                match *option {
+                    b"-regexp" => return,
+                    b"-glob" => glob = true,
+                    _ => {}
+                }
+            }
+            let wildcards = |value: &Text| {
+                value
+                    .iter()
+                    .any(|c| matches!(c, b'*' | b'?' | b'[' | b'\\'))
+            };
+            if glob && arms.iter().any(|(value, _)| wildcards(value)) {
+                return;
+            }
+            sort_arms(arms);
+        }
+    }
+    SortSwitchArms.visit_ast_mut(ast);
+}
+
+// class match -value -nocase $x eq dg => class match -nocase -value -- $x eq dg
+pub fn normalize_class_options(ast: &mut Ast) {
+    struct NormalizeClassOptions;
+    impl VisitMut for NormalizeClassOptions {
+        fn visit_if_mut<'src>(
+            &mut self,
+            condition_body_clauses: &mut Vec<(Condition<'src>, Ast<'src>)>,
+            maybe_block_if_false: &mut Option<Box<Ast<'src>>>,
+        ) {
+            for (condition, _) in condition_body_clauses.iter_mut() {
+                if let Some(normalized) = normalize_class_commands(condition) {
+                    condition.text = Cow::Owned(normalized);
+                }
+            }
+            walk_if_mut(self, condition_body_clauses, maybe_block_if_false);
+        }
+
+        fn visit_statement_mut(&mut self, statement: &mut Statement) {
+            let words = match statement {
+                Statement::Class {
+                    subcommand,
+                    options,
+                    arguments,
+                } => {
+                    let first = arguments.first().map(|word| &word[..]);
+                    *options = class_options(subcommand, options, first);
+                    arguments.iter_mut().collect()
+                }
+                statement => substituted_words(statement),
+            };
+            for word in words {
+                if let Some(normalized) = normalize_class_commands(word) {
+                    *word = Cow::Owned(normalized);
+                }
+            }
+        }
+    }
+    NormalizeClassOptions.visit_ast_mut(ast);
+}
+
+// in the order `class` documents them; others keep theirs, after these
+const CLASS_OPTIONS: &[&[u8]] = &[
+    b"-all",
+    b"-nocase",
+    b"-index",
+    b"-name",
+    b"-value",
+    b"-element",
+];
+
+// The options of a class command in canonical order, without duplicates,
+// ending in `--` exactly when the first argument could be taken for an
+// option: it starts with `-`, or is substituted and so could. `class lookup`
+// takes no options, so its `--` is kept as written.
+fn class_options<'src>(
+    subcommand: &[u8],
+    options: &[Text<'src>],
+    first: Option<&[u8]>,
+) -> Vec<Text<'src>> {
+    let mut ordered: Vec<Text> = Vec::with_capacity(options.len() + 1);
+    for option in options {
+        if option[..] != b"--"[..] && !ordered.contains(option) {
+            ordered.push(option.clone());
+        }
+    }
+    ordered.sort_by_key(|option| {
+        CLASS_OPTIONS
+            .iter()
+            .position(|known| *known == &option[..])
+            .unwrap_or(CLASS_OPTIONS.len())
+    });
+    let ambiguous = first.is_some_and(|word| match word {
+        [b'{', inner @ .., b'}'] => inner.starts_with(b"-"),
+        [b'"', inner @ .., b'"'] => {
+            inner.starts_with(b"-") || inner.contains(&b'$') || inner.contains(&b'[')
+        }
+        word => word.starts_with(b"-") || word.starts_with(b"$") || word.starts_with(b"["),
+    });
+    let separator = match subcommand {
+        b"lookup" => options.iter().any(|option| option[..] == b"--"[..]),
+        _ => ambiguous,
+    };
+    if separator {
+        ordered.push(Cow::Borrowed(b"--"));
+    }
+    ordered
+}
+
+// `source` with the options of each `[class ...]` substitution in it
+// normalized, or None if there is none to change
+fn normalize_class_commands(source: &[u8]) -> Option<Vec<u8>> {
+    rewrite_substitutions(source, &mut normalize_class_command)
+}
+
+// `source` with each `[command]` substitution in it rewritten by `rewrite`,
+// or None if there is none to change. Substitutions `rewrite` leaves alone
+// are searched for nested ones. Braced words are literal, so substitutions in
+// them are left alone.
+pub(crate) fn rewrite_substitutions(
+    source: &[u8],
+    rewrite: &mut dyn FnMut(&[u8]) -> Option<Vec<u8>>,
+) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(source.len());
+    let mut idx = 0;
+    while idx < source.len() {
+        let rest = &source[idx..];
+        let len = match rest[0] {
+            b'\\' => 2.min(rest.len()),
+            b'{' => word_len(rest, b'{', b'}'),
+            b'[' => {
+                let len = word_len(rest, b'[', b']');
+                match rest[..len].strip_suffix(b"]") {
+                    Some(command) if !command.contains(&b'\n') => {
+                        out.push(b'[');
+                        let inner = &command[1..];
+                        match rewrite(inner) {
+                            Some(rewritten) => out.extend_from_slice(&rewritten),
+                            None => out.extend_from_slice(
+                                &rewrite_substitutions(inner, rewrite)
+                                    .unwrap_or_else(|| inner.to_vec()),
+                            ),
+                        }
+                        out.push(b']');
+                        idx += len;
+                        continue;
+                    }
+                    _ => len,
+                }
+            }
+            _ => 1,
+        };
+        out.extend_from_slice(&rest[..len]);
+        idx += len;
+    }
+    (out != source).then_some(out)
+}
+
+// a `class match|search|lookup ...` command, rebuilt with normalized options
+fn normalize_class_command(command: &[u8]) -> Option<Vec<u8>> {
+    let words = split_words(command);
+    let (b"class", b"match" | b"search" | b"lookup") = (*words.first()?, *words.get(1)?) else {
+        return None;
+    };
+    let options_len = words[2..]
+        .iter()
+        .take_while(|word| word.starts_with(b"-") && **word != b"--")
+        .count();
+    let separator = words.get(2 + options_len) == Some(&&b"--"[..]);
+    let options: Vec<Text> = words[2..2 + options_len + separator as usize]
+        .iter()
+        .map(|word| Cow::Borrowed(*word))
+        .collect();
+    let arguments = &words[2 + options.len()..];
+
+    let mut out = b"class ".to_vec();
+    out.extend_from_slice(words[1]);
+    for option in class_options(words[1], &options, arguments.first().copied()) {
+        out.push(b' ');
+        out.extend_from_slice(&option);
+    }
+    for argument in arguments {
+        out.push(b' ');
+        match normalize_class_commands(argument) {
+            Some(normalized) => out.extend_from_slice(&normalized),
+            None => out.extend_from_slice(argument),
+        }
+    }
+    Some(out)
+}
+
+// single spaces around binary operators and between words of if conditions
+pub fn normalize_expressions(ast: &mut Ast) {
+    struct NormalizeExpressions;
+    impl VisitMut for NormalizeExpressions {
+        fn visit_if_mut<'src>(
+            &mut self,
+            condition_body_clauses: &mut Vec<(Condition<'src>, Ast<'src>)>,
+            maybe_block_if_false: &mut Option<Box<Ast<'src>>>,
+        ) {
+            for (condition, _) in condition_body_clauses.iter_mut() {
+                let normalized = normalize_expression(condition);
+                if condition.braced && normalized != *condition.text {
+                    condition.text = Cow::Owned(normalized);
+                }
+            }
+            walk_if_mut(self, condition_body_clauses, maybe_block_if_false);
+        }
+    }
+    NormalizeExpressions.visit_ast_mut(ast);
+}
+
+// longest first, so `<<` is never split into two `<`
+const BINARY_OPERATORS: &[&[u8]] = &[
+    b"**", b"<<", b">>", b"==", b"!=", b"<=", b">=", b"&&", b"||", b"<", b">", b"&", b"|",
+];
+
+// quoted strings, [command substitutions] and {braced words} are kept verbatim
+pub fn normalize_expression(expression: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(expression.len());
+    let mut pending_space = false;
+    let mut idx = 0;
+    while idx < expression.len() {
+        let rest = &expression[idx..];
+        if let Some(op) = BINARY_OPERATORS.iter().find(|op| rest.starts_with(op)) {
+            if !out.is_empty() {
+                out.push(b' ');
+            }
+            out.extend_from_slice(op);
+            pending_space = true;
+            idx += op.len();
+            continue;
+        }
+        match rest[0] {
+            b' ' | b'\t' => {
+                pending_space = !out.is_empty();
+                idx += 1;
+                continue;
+            }
+            _ => {}
+        }
+        if pending_space {
+            out.push(b' ');
+            pending_space = false;
+        }
+        let len = match rest[0] {
+            b'"' => word_len(rest, b'"', b'"'),
+            b'[' => word_len(rest, b'[', b']'),
+            b'{' => word_len(rest, b'{', b'}'),
+            _ => 1,
+        };
+        out.extend_from_slice(&rest[..len]);
+        idx += len;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize_expression, run, PassFail};
+    use crate::{ast::Ast, formatter::Formatter, lexer::Lexer, parser::Parser};
+
+    // `source` formatted with the default options after running `passes`
+    fn rewrite(source: &str, passes: &[&str]) -> String {
+        let (tokens, spans) = Lexer::new().lex_with_spans(source.as_bytes()).unwrap();
+        let mut ast = Parser::new(source.as_bytes(), &tokens, &spans)
+            .parse()
+            .unwrap();
+        let names: Vec<String> = passes.iter().map(|name| name.to_string()).collect();
+        run(&mut ast, &names).unwrap();
+        String::from_utf8(Formatter::new().format(ast)).unwrap()
+    }
+
+    fn normalize(expression: &str) -> String {
+        String::from_utf8(normalize_expression(expression.as_bytes())).unwrap()
+    }
+
+    #[test]
+    fn spaces_single_character_operators() {
+        assert_eq!(normalize("$a<$b"), "$a < $b");
+        assert_eq!(normalize("$a>$b"), "$a > $b");
+        assert_eq!(normalize("$a&$b"), "$a & $b");
+        assert_eq!(normalize("$a|$b"), "$a | $b");
+    }
+
+    #[test]
+    fn keeps_multi_character_operators_whole() {
+        for op in ["**", "<<", ">>", "==", "!=", "<=", ">=", "&&", "||"] {
+            let expected = format!("$a {op} 2");
+            assert_eq!(normalize(&format!("$a{op}2")), expected);
+            assert_eq!(normalize(&format!("$a  {op}  2")), expected);
+        }
+    }
+
+    #[test]
+    fn shifts_are_not_split() {
+        assert_eq!(normalize("$a<<2"), "$a << 2");
+        assert_eq!(normalize("$y>>1"), "$y >> 1");
+    }
+
+    #[test]
+    fn keeps_quoted_and_bracketed_words_verbatim() {
+        assert_eq!(normalize("\"a<<b\"==[f x<<y]"), "\"a<<b\" == [f x<<y]");
+        assert_eq!(normalize("{a  <  b}<$c"), "{a  <  b} < $c");
+    }
+
+    #[test]
+    fn unknown_passes_fail_before_any_runs() {
+        let mut ast = Ast::Block(Vec::new());
+        let names = ["collapse-elseif".to_string(), "minify".to_string()];
+        let e = run(&mut ast, &names).unwrap_err();
+        assert!(matches!(&e, PassFail::Unknown(name) if name == "minify"));
+        assert_eq!(e.to_string(), "unknown pass `minify`");
+        assert!(run(&mut ast, &names[..1]).is_ok());
+    }
+
+    #[test]
+    fn runs_passes_in_the_configured_order() {
+        let source = "if {$a} {\n    set b 1\n} else {\n    if {$c==1} {\n        set b 2\n    } else {\n        set b 3\n    }\n}\n";
+        let expected = "if { $a } {\n    set b 1\n}\nelseif { $c == 1 } {\n    set b 2\n}\nelse {\n    set b 3\n}\n";
+        assert_eq!(
+            rewrite(source, &["collapse-elseif", "normalize-expressions"]),
+            expected
+        );
+        assert_eq!(
+            rewrite(source, &["normalize-expressions", "collapse-elseif"]),
+            expected
+        );
+        assert!(rewrite(source, &[]).contains("else {\n    if { $c==1 } {"));
+    }
+
+    #[test]
+    fn collapses_only_else_blocks_holding_a_lone_if() {
+        let commented = "if {$a} {\n    set b 1\n} else {\n    # keep\n    if {$c} {\n        set b 2\n    }\n}\n";
+        assert!(!rewrite(commented, &["collapse-elseif"]).contains("elseif"));
+        let more = "if {$a} {\n    set b 1\n} else {\n    if {$c} {\n        set b 2\n    }\n    set d 1\n}\n";
+        assert!(!rewrite(more, &["collapse-elseif"]).contains("elseif"));
+    }
+
+    #[test]
+    fn braces_conditions_and_expr_substitutions() {
+        let source = "if $a {\n    set b [expr $c + 1]\n}\nreturn [expr {$d}]\n";
+        assert_eq!(
+            rewrite(source, &["brace-conditions"]),
+            "if { $a } {\n    set b [expr {$c + 1}]\n}\nreturn [expr {$d}]\n"
+        );
+        // bracing would stop the substitution of the quoted brace
+        let source = "set g [expr \"$h{\"]\n";
+        assert_eq!(rewrite(source, &["brace-conditions"]), source);
+    }
+
+    #[test]
+    fn rewrites_return_options_and_result_apart() {
+        let source = "return  -code   error \"done\"\n";
+        assert_eq!(
+            rewrite(source, &["brace-literals"]),
+            "return -code error {done}\n"
+        );
+        let source = "return -level [expr $l + 1] [expr $v]\n";
+        assert_eq!(
+            rewrite(source, &["brace-conditions"]),
+            "return -level [expr {$l + 1}] [expr {$v}]\n"
+        );
+    }
+
+    #[test]
+    fn rewrites_literal_delimiters() {
+        let source = "set a \"hello world\"\nset b {x}\nreturn \"done\"\n";
+        assert_eq!(
+            rewrite(source, &["brace-literals"]),
+            "set a {hello world}\nset b {x}\nreturn {done}\n"
+        );
+        assert_eq!(
+            rewrite(source, &["quote-literals"]),
+            "set a \"hello world\"\nset b \"x\"\nreturn \"done\"\n"
+        );
+    }
+
+    #[test]
+    fn keeps_words_that_differ_between_delimiters() {
+        for value in [
+            "\"$name\"",
+            "\"[clock seconds]\"",
+            "\"a\\tb\"",
+            "{$name}",
+            "{a\tb}",
+            "{{}}",
+            "plain",
+        ] {
+            let source = format!("set a {value}\n");
+            assert_eq!(rewrite(&source, &["brace-literals"]), source);
+            assert_eq!(rewrite(&source, &["quote-literals"]), source);
+        }
+    }
+
+    #[test]
+    fn groups_package_requires_below_the_header() {
+        let source = "# header\n\npackage require http\nset a 1\npackage require -exact json 1.3 ;# pinned\npackage require http\n";
+        assert_eq!(
+            rewrite(source, &["sort-package-requires"]),
+            "# header\n\npackage require -exact json 1.3 ;# pinned\npackage require http\n\nset a 1\n"
+        );
+    }
+
+    #[test]
+    fn orders_class_options() {
+        let source = "class match -value -nocase -value $x eq dg\nclass search -- abc starts_with dg\nclass lookup -- k dg\n";
+        assert_eq!(
+            rewrite(source, &["normalize-class-options"]),
+            "class match -nocase -value -- $x eq dg\nclass search abc starts_with dg\nclass lookup -- k dg\n"
+        );
+    }
+
+    #[test]
+    fn normalizes_class_substitutions() {
+        let source = "if {[class match -value [HTTP::uri] starts_with dg]} {\n    set a [class search -name -all \"-x\" eq dg]\n}\nset b {[class match -value $x eq dg]}\n";
+        assert_eq!(
+            rewrite(source, &["normalize-class-options"]),
+            "if { [class match -value -- [HTTP::uri] starts_with dg] } {\n    set a [class search -all -name -- \"-x\" eq dg]\n}\nset b {[class match -value $x eq dg]}\n"
+        );
+    }
+
+    // the arm patterns of each switch in `source` after `sort-switch-arms`
+    fn sorted_patterns(source: &str) -> Vec<String> {
+        rewrite(source, &["sort-switch-arms"])
+            .lines()
+            .filter(|line| line.starts_with("    ") && !line.starts_with("        "))
+            .filter(|line| line.trim() != "}")
+            .map(|line| line.trim().trim_end_matches(['{', '-']).trim().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn sorts_switch_arms() {
+        let source = "switch -exact -- $a {\ndefault {\npool d\n}\nb -\na {\npool ab\n}\n\"c\" {\npool c\n}\n}\n";
+        assert_eq!(sorted_patterns(source), ["a", "b", "\"c\"", "default"]);
+    }
+
+    #[test]
+    fn keeps_the_order_of_pattern_switches() {
+        let regexp = "switch -regexp $a {\nz {\npool z\n}\na {\npool a\n}\n}\n";
+        assert_eq!(sorted_patterns(regexp), ["z", "a"]);
+        let glob = "switch -glob $a {\n/z* {\npool z\n}\n/a {\npool a\n}\n}\n";
+        assert_eq!(sorted_patterns(glob), ["/z*", "/a"]);
+        let literal_glob = "switch -glob $a {\n/z {\npool z\n}\n/a {\npool a\n}\n}\n";
+        assert_eq!(sorted_patterns(literal_glob), ["/a", "/z"]);
+    }
+
+    #[test]
+    fn moves_comments_with_their_package_require() {
+        let source = "package require json\n# for the API client\npackage require http\n";
+        assert_eq!(
+            rewrite(source, &["sort-package-requires"]),
+            "# for the API client\npackage require http\npackage require json\n"
+        );
+    }
+}
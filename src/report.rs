@@ -0,0 +1,293 @@
+// A self-contained HTML page summing up a run: how many files formatting
+// changes, the lint findings grouped by rule, and the diff of each changed
+// file, for reviews that happen outside of pull requests.
+use crate::{
+    diagnostic::{Diagnostic, Severity, RULES},
+    diff::UnifiedDiff,
+};
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; vertical-align: top; }
+th { background: #f3f3f3; }
+pre.diff { background: #fafafa; border: 1px solid #ddd; padding: 0.5em; overflow-x: auto; }
+.header { font-weight: bold; }
+.hunk { color: #0a6b8a; }
+.deleted { background: #fde8e8; color: #a11; }
+.inserted { background: #e6f6e6; color: #161; }
+.error { color: #a11; }
+.warning { color: #8a6500; }
+";
+
+#[derive(Debug, Default, Clone)]
+pub struct HtmlReport {
+    files: Vec<(String, Vec<u8>)>, // name and diff of each formatted file, empty if unchanged
+    diagnostics: Vec<(String, Diagnostic)>,
+}
+
+impl HtmlReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // a file that was formatted, changed or not
+    pub fn file(&mut self, name: &str, original: &[u8], formatted: &[u8]) {
+        let diff = UnifiedDiff::new().render(name, original, formatted);
+        self.files.push((name.to_string(), diff));
+    }
+
+    pub fn diagnostic(&mut self, file: &str, d: Diagnostic) {
+        self.diagnostics.push((file.to_string(), d));
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n");
+        out.push_str(&format!(
+            "<title>{} report</title>\n<style>\n{STYLE}</style>\n</head>\n<body>\n",
+            env!("CARGO_PKG_NAME")
+        ));
+        out.push_str(&format!(
+            "<h1>{} {} report</h1>\n",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        ));
+        self.render_summary(&mut out);
+        self.render_findings(&mut out);
+        self.render_diffs(&mut out);
+        out.push_str("</body>\n</html>\n");
+        out
+    }
+
+    fn render_summary(&self, out: &mut String) {
+        let changed: Vec<&(String, Vec<u8>)> = self
+            .files
+            .iter()
+            .filter(|(_, diff)| !diff.is_empty())
+            .collect();
+        // files that were not formatted are those an error stopped
+        let mut failed: Vec<&str> = self
+            .diagnostics
+            .iter()
+            .filter(|(file, d)| {
+                d.severity == Severity::Error && self.files.iter().all(|(name, _)| name != file)
+            })
+            .map(|(file, _)| file.as_str())
+            .collect();
+        failed.sort_unstable();
+        failed.dedup();
+        let (mut deleted, mut inserted) = (0, 0);
+        for (_, diff) in &changed {
+            for line in diff.split(|&c| c == b'\n') {
+                match line {
+                    [b'-', b'-', b'-', ..] | [b'+', b'+', b'+', ..] => {}
+                    [b'-', ..] => deleted += 1,
+                    [b'+', ..] => inserted += 1,
+                    _ => {}
+                }
+            }
+        }
+        let count = |severity| {
+            self.diagnostics
+                .iter()
+                .filter(|(_, d)| d.severity == severity)
+                .count()
+        };
+        let rows = [
+            ("files formatted", self.files.len()),
+            ("files changed", changed.len()),
+            ("files unchanged", self.files.len() - changed.len()),
+            ("files failed", failed.len()),
+            ("lines removed", deleted),
+            ("lines added", inserted),
+            ("errors", count(Severity::Error)),
+            ("warnings", count(Severity::Warning)),
+        ];
+        out.push_str("<h2>Summary</h2>\n<table>\n");
+        for (label, value) in rows {
+            out.push_str(&format!("<tr><th>{label}</th><td>{value}</td></tr>\n"));
+        }
+        out.push_str("</table>\n");
+    }
+
+    fn render_findings(&self, out: &mut String) {
+        out.push_str("<h2>Findings</h2>\n");
+        if self.diagnostics.is_empty() {
+            out.push_str("<p>None.</p>\n");
+            return;
+        }
+        // in the order of `RULES`, then any other rule
+        let mut rules: Vec<&str> = RULES.iter().map(|(id, _)| *id).collect();
+        for (_, d) in &self.diagnostics {
+            if !rules.contains(&d.rule) {
+                rules.push(d.rule);
+            }
+        }
+        for rule in rules {
+            let findings: Vec<&(String, Diagnostic)> = self
+                .diagnostics
+                .iter()
+                .filter(|(_, d)| d.rule == rule)
+                .collect();
+            if findings.is_empty() {
+                continue;
+            }
+            out.push_str(&format!(
+                "<h3 id=\"{rule}\">{rule} ({})</h3>\n",
+                findings.len()
+            ));
+            if let Some((_, description)) = RULES.iter().find(|(id, _)| *id == rule) {
+                out.push_str(&format!("<p>{}</p>\n", escape(description)));
+            }
+            out.push_str(
+                "<table>\n<tr><th>file</th><th>line</th><th>severity</th><th>message</th></tr>\n",
+            );
+            for (file, d) in findings {
+                let line = d
+                    .span
+                    .map_or(String::new(), |span| span.start.line.to_string());
+                let severity = d.severity.as_str();
+                out.push_str(&format!(
+                    "<tr><td>{}</td><td>{line}</td><td class=\"{severity}\">{severity}</td><td>{}</td></tr>\n",
+                    escape(file),
+                    escape(&d.message),
+                ));
+            }
+            out.push_str("</table>\n");
+        }
+    }
+
+    fn render_diffs(&self, out: &mut String) {
+        out.push_str("<h2>Changes</h2>\n");
+        let mut any = false;
+        for (name, diff) in self.files.iter().filter(|(_, diff)| !diff.is_empty()) {
+            any = true;
+            out.push_str(&format!("<h3>{}</h3>\n<pre class=\"diff\">", escape(name)));
+            let text = String::from_utf8_lossy(diff);
+            for line in text.lines() {
+                let class = match line.as_bytes() {
+                    [b'-', b'-', b'-', ..] | [b'+', b'+', b'+', ..] => "header",
+                    [b'@', b'@', ..] => "hunk",
+                    [b'-', ..] => "deleted",
+                    [b'+', ..] => "inserted",
+                    _ => "",
+                };
+                match class {
+                    "" => out.push_str(&escape(line)),
+                    class => {
+                        out.push_str(&format!("<span class=\"{class}\">{}</span>", escape(line)))
+                    }
+                }
+                out.push('\n');
+            }
+            out.push_str("</pre>\n");
+        }
+        if !any {
+            out.push_str("<p>None.</p>\n");
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape, HtmlReport};
+    use crate::diagnostic::{Diagnostic, Position, Severity, Span};
+
+    fn diagnostic(
+        rule: &'static str,
+        severity: Severity,
+        message: &str,
+        line: usize,
+    ) -> Diagnostic {
+        let position = Position { line, column: 1 };
+        Diagnostic {
+            rule,
+            severity,
+            message: message.to_string(),
+            span: Some(Span {
+                start: position,
+                end: position,
+            }),
+            snippet: None,
+            fix: None,
+        }
+    }
+
+    #[test]
+    fn escapes_markup() {
+        assert_eq!(
+            escape("if {$a < \"b\" && $c > 1}"),
+            "if {$a &lt; &quot;b&quot; &amp;&amp; $c &gt; 1}"
+        );
+    }
+
+    #[test]
+    fn sums_up_files_and_findings() {
+        let mut report = HtmlReport::new();
+        report.file("a.tcl", b"set a 1\n", b"set a 1\n");
+        report.file("b.tcl", b"set  b 2\nset c 3\n", b"set b 2\nset c 3\n");
+        report.diagnostic(
+            "c.tcl",
+            diagnostic("parse-error", Severity::Error, "unexpected `}`", 3),
+        );
+        report.diagnostic(
+            "b.tcl",
+            diagnostic(
+                "unbraced-expr",
+                Severity::Warning,
+                "unbraced condition `$a < 1`",
+                7,
+            ),
+        );
+        let html = report.render();
+        for row in [
+            "<tr><th>files formatted</th><td>2</td></tr>",
+            "<tr><th>files changed</th><td>1</td></tr>",
+            "<tr><th>files unchanged</th><td>1</td></tr>",
+            "<tr><th>files failed</th><td>1</td></tr>",
+            "<tr><th>lines removed</th><td>1</td></tr>",
+            "<tr><th>lines added</th><td>1</td></tr>",
+            "<tr><th>errors</th><td>1</td></tr>",
+            "<tr><th>warnings</th><td>1</td></tr>",
+        ] {
+            assert!(html.contains(row), "no {row} in {html}");
+        }
+        // grouped in the order of `RULES`, with the messages escaped
+        let parse_error = html
+            .find("<h3 id=\"parse-error\">parse-error (1)</h3>")
+            .unwrap();
+        let unbraced = html
+            .find("<h3 id=\"unbraced-expr\">unbraced-expr (1)</h3>")
+            .unwrap();
+        assert!(parse_error < unbraced);
+        assert!(html.contains(
+            "<tr><td>b.tcl</td><td>7</td><td class=\"warning\">warning</td><td>unbraced condition `$a &lt; 1`</td></tr>"
+        ));
+        assert!(html.contains("<span class=\"deleted\">-set  b 2</span>\n<span class=\"inserted\">+set b 2</span>\n set c 3\n"));
+        assert!(!html.contains("<h3>a.tcl</h3>"));
+    }
+
+    #[test]
+    fn says_when_there_is_nothing_to_show() {
+        let mut report = HtmlReport::new();
+        report.file("a.tcl", b"set a 1\n", b"set a 1\n");
+        let html = report.render();
+        assert_eq!(html.matches("<p>None.</p>").count(), 2);
+        assert!(html.ends_with("</body>\n</html>\n"));
+    }
+}
@@ -0,0 +1,110 @@
+// SARIF 2.1.0 export of diagnostics, for GitHub code scanning and other
+// static analysis dashboards.
+use crate::{
+    diagnostic::{Diagnostic, RULES},
+    json,
+};
+
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+// (file, diagnostic) pairs into one SARIF log with a single run
+pub fn render(results: &[(String, Diagnostic)]) -> String {
+    let rules: Vec<String> = RULES
+        .iter()
+        .map(|(id, description)| {
+            format!(
+                "{{\"id\":{},\"shortDescription\":{{\"text\":{}}}}}",
+                json::string(id),
+                json::string(description)
+            )
+        })
+        .collect();
+    let results: Vec<String> = results
+        .iter()
+        .map(|(file, d)| render_result(file, d))
+        .collect();
+    format!(
+        "{{\"$schema\":{},\"version\":\"2.1.0\",\"runs\":[{{\"tool\":{{\"driver\":{{\"name\":{},\"version\":{},\"rules\":[{}]}}}},\"results\":[{}]}}]}}\n",
+        json::string(SCHEMA),
+        json::string(env!("CARGO_PKG_NAME")),
+        json::string(env!("CARGO_PKG_VERSION")),
+        rules.join(","),
+        results.join(","),
+    )
+}
+
+fn render_result(file: &str, d: &Diagnostic) -> String {
+    let region = match d.span {
+        Some(span) => format!(
+            ",\"region\":{{\"startLine\":{},\"startColumn\":{},\"endLine\":{},\"endColumn\":{}}}",
+            span.start.line, span.start.column, span.end.line, span.end.column
+        ),
+        None => String::new(),
+    };
+    let rule_index = match RULES.iter().position(|(id, _)| *id == d.rule) {
+        Some(idx) => format!(",\"ruleIndex\":{idx}"),
+        None => String::new(),
+    };
+    format!(
+        "{{\"ruleId\":{}{rule_index},\"level\":\"{}\",\"message\":{{\"text\":{}}},\"locations\":[{{\"physicalLocation\":{{\"artifactLocation\":{{\"uri\":{}}}{region}}}}}]}}",
+        json::string(d.rule),
+        d.severity.as_str(),
+        json::string(&d.message),
+        json::string(&uri(file)),
+    )
+}
+
+// relative URI reference for a path: `/` separators, everything outside the
+// unreserved set percent-encoded
+fn uri(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for &c in path.replace('\\', "/").as_bytes() {
+        match c {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                out.push(c as char)
+            }
+            c => out.push_str(&format!("%{c:02X}")),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render, uri};
+    use crate::diagnostic::{Diagnostic, Severity, Span, RULES};
+
+    #[test]
+    fn encodes_paths_as_relative_uris() {
+        assert_eq!(uri("lib/a.tcl"), "lib/a.tcl");
+        assert_eq!(uri("lib\\my file.tcl"), "lib/my%20file.tcl");
+        assert_eq!(uri("ü.tcl"), "%C3%BC.tcl");
+    }
+
+    #[test]
+    fn rule_ids_are_unique() {
+        for (i, (id, _)) in RULES.iter().enumerate() {
+            assert!(!RULES[..i].iter().any(|(other, _)| other == id), "{id}");
+        }
+    }
+
+    #[test]
+    fn renders_results_against_rules() {
+        let d = Diagnostic {
+            rule: "parse-error",
+            severity: Severity::Error,
+            message: "unbalanced braces".to_string(),
+            span: Some(Span::line(3)),
+            snippet: None,
+            fix: None,
+        };
+        let log = render(&[("a b.tcl".to_string(), d)]);
+        assert!(log.ends_with("]}]}\n"));
+        assert!(log.contains(r#""version":"2.1.0""#));
+        assert_eq!(log.matches(r#"{"id":"#).count(), RULES.len());
+        assert!(log.contains(
+            r#"{"ruleId":"parse-error","ruleIndex":1,"level":"error","message":{"text":"unbalanced braces"},"locations":[{"physicalLocation":{"artifactLocation":{"uri":"a%20b.tcl"},"region":{"startLine":3,"startColumn":1,"endLine":3,"endColumn":1}}}]}"#
+        ));
+        assert!(render(&[]).contains(r#""results":[]"#));
+    }
+}
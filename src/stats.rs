@@ -0,0 +1,307 @@
+// Per-file code metrics, for auditing large iRule estates.
+use crate::{
+    ast::{Ast, Condition, Statement, Text},
+    complexity::complexity,
+    json,
+    namespace::{namespace, namespaced_commands},
+    visit::{walk_ast, walk_if, walk_proc, walk_switch, walk_when, Visit},
+    width,
+};
+
+#[derive(Debug, Default, Clone)]
+pub struct Stats {
+    pub procs: usize,
+    pub whens: usize,
+    pub statements: usize,
+    pub comments: usize,
+    pub max_depth: usize,
+    pub longest_proc: Option<(String, usize)>, // name, number of statements
+    pub complexity: Vec<(String, usize)>,      // per proc and `when` block
+    pub namespaces: Vec<(String, usize)>,      // calls of namespaced commands, by namespace
+}
+
+impl Stats {
+    pub fn collect(ast: &Ast) -> Self {
+        let mut collector = Collector {
+            stats: Stats::default(),
+            depth: 0,
+        };
+        // the file itself is a block, but not a level of nesting
+        match ast {
+            Ast::Block(trees) => trees.iter().for_each(|t| collector.visit_ast(t)),
+            ast => collector.visit_ast(ast),
+        }
+        collector.stats.complexity = complexity(ast);
+        collector.stats.namespaces.sort();
+        collector.stats
+    }
+
+    pub fn max_complexity(&self) -> Option<&(String, usize)> {
+        self.complexity.iter().max_by_key(|(_, value)| *value)
+    }
+
+    // comments per comment-or-statement
+    pub fn comment_ratio(&self) -> f64 {
+        match self.comments + self.statements {
+            0 => 0.0,
+            n => self.comments as f64 / n as f64,
+        }
+    }
+}
+
+struct Collector {
+    stats: Stats,
+    depth: usize,
+}
+
+impl Visit for Collector {
+    fn visit_ast(&mut self, ast: &Ast) {
+        // every nested body is a block
+        if let Ast::Block(_) = ast {
+            self.depth += 1;
+            self.stats.max_depth = self.stats.max_depth.max(self.depth);
+            walk_ast(self, ast);
+            self.depth -= 1;
+        } else {
+            walk_ast(self, ast);
+        }
+    }
+
+    fn visit_comment(&mut self, _text: &[u8]) {
+        self.stats.comments += 1;
+    }
+
+    fn visit_proc(&mut self, name: &[u8], parameters: &[Text], body: &Ast) {
+        self.stats.procs += 1;
+        let before = self.stats.statements;
+        walk_proc(self, name, parameters, body);
+        let length = self.stats.statements - before;
+        if self
+            .stats
+            .longest_proc
+            .as_ref()
+            .is_none_or(|(_, l)| length > *l)
+        {
+            let name = String::from_utf8_lossy(name).into_owned();
+            self.stats.longest_proc = Some((name, length));
+        }
+    }
+
+    fn visit_when(&mut self, event_name: &[u8], body: &Ast) {
+        self.stats.whens += 1;
+        walk_when(self, event_name, body);
+    }
+
+    fn visit_if(
+        &mut self,
+        condition_body_clauses: &[(Condition, Ast)],
+        block_if_false: Option<&Ast>,
+    ) {
+        for (condition, _) in condition_body_clauses {
+            self.count_namespaces(condition);
+        }
+        walk_if(self, condition_body_clauses, block_if_false);
+    }
+
+    fn visit_switch(&mut self, condition: &[u8], arms: &[(Text, Option<Ast>)]) {
+        self.count_namespaces(condition);
+        walk_switch(self, condition, arms);
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        self.stats.statements += 1;
+        self.count_namespaces(&statement.words().join(&b' '));
+    }
+}
+
+impl Collector {
+    fn count_namespaces(&mut self, source: &[u8]) {
+        for command in namespaced_commands(source) {
+            let Some(namespace) = namespace(command) else {
+                continue;
+            };
+            let namespace = String::from_utf8_lossy(namespace);
+            match self
+                .stats
+                .namespaces
+                .iter_mut()
+                .find(|(n, _)| *n == namespace)
+            {
+                Some((_, count)) => *count += 1,
+                None => self.stats.namespaces.push((namespace.into_owned(), 1)),
+            }
+        }
+    }
+}
+
+const COLUMNS: [&str; 8] = [
+    "file",
+    "procs",
+    "whens",
+    "statements",
+    "max depth",
+    "longest proc",
+    "max complexity",
+    "comment ratio",
+];
+
+pub fn render_table(rows: &[(String, Stats)]) -> String {
+    let cells: Vec<[String; 8]> = rows
+        .iter()
+        .map(|(file, s)| {
+            [
+                file.clone(),
+                s.procs.to_string(),
+                s.whens.to_string(),
+                s.statements.to_string(),
+                s.max_depth.to_string(),
+                match &s.longest_proc {
+                    Some((name, length)) => format!("{name} ({length})"),
+                    None => "-".to_string(),
+                },
+                match s.max_complexity() {
+                    Some((name, value)) => format!("{name} ({value})"),
+                    None => "-".to_string(),
+                },
+                format!("{:.2}", s.comment_ratio()),
+            ]
+        })
+        .collect();
+    let widths: Vec<usize> = (0..COLUMNS.len())
+        .map(|i| {
+            cells
+                .iter()
+                .map(|row| width::width(row[i].as_bytes()))
+                .chain([COLUMNS[i].len()])
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let mut out = String::new();
+    let header = COLUMNS.map(String::from);
+    for row in [&header].into_iter().chain(cells.iter()) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &columns)| width::pad(cell, columns))
+            .collect();
+        out.push_str(line.join("  ").trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+pub fn render_json(rows: &[(String, Stats)]) -> String {
+    let objects: Vec<String> = rows
+        .iter()
+        .map(|(file, s)| {
+            let longest_proc = match &s.longest_proc {
+                Some((name, length)) => {
+                    format!("{{\"name\":{},\"statements\":{length}}}", json::string(name))
+                }
+                None => "null".to_string(),
+            };
+            let complexity: Vec<String> = s
+                .complexity
+                .iter()
+                .map(|(name, value)| {
+                    format!("{{\"name\":{},\"complexity\":{value}}}", json::string(name))
+                })
+                .collect();
+            let namespaces: Vec<String> = s
+                .namespaces
+                .iter()
+                .map(|(name, calls)| format!("{}:{calls}", json::string(name)))
+                .collect();
+            format!(
+                "{{\"file\":{},\"procs\":{},\"whens\":{},\"statements\":{},\"comments\":{},\"max_depth\":{},\"longest_proc\":{},\"complexity\":[{}],\"comment_ratio\":{:.4},\"namespaces\":{{{}}}}}",
+                json::string(file),
+                s.procs,
+                s.whens,
+                s.statements,
+                s.comments,
+                s.max_depth,
+                longest_proc,
+                complexity.join(","),
+                s.comment_ratio(),
+                namespaces.join(","),
+            )
+        })
+        .collect();
+    format!("[{}]\n", objects.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_json, render_table, Stats};
+    use crate::{ast::Ast, lexer::Lexer, parser::Parser};
+
+    fn parse(source: &[u8]) -> Ast<'_> {
+        let (tokens, spans) = Lexer::new().lex_with_spans(source).unwrap();
+        Parser::new(source, &tokens, &spans).parse().unwrap()
+    }
+
+    const SOURCE: &[u8] = b"# helpers
+proc short {} {
+    return 1
+}
+proc long {a} {
+    set b 1
+    if {$a} {
+        set c [HTTP::host]
+    }
+    return $b
+}
+when HTTP_REQUEST {
+    # route
+    HTTP::redirect /x
+    pool p
+}
+";
+
+    #[test]
+    fn counts_definitions_and_statements() {
+        let stats = Stats::collect(&parse(SOURCE));
+        assert_eq!((stats.procs, stats.whens), (2, 1));
+        assert_eq!((stats.statements, stats.comments), (6, 2));
+        assert_eq!(stats.max_depth, 2);
+        assert_eq!(stats.longest_proc, Some(("long".to_string(), 3)));
+        assert_eq!(stats.max_complexity(), Some(&("proc long".to_string(), 2)));
+        assert_eq!(stats.namespaces, [("HTTP".to_string(), 2)]);
+        assert!((stats.comment_ratio() - 2.0 / 8.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn renders_a_table_and_json() {
+        let rows = [
+            ("a.tcl".to_string(), Stats::collect(&parse(SOURCE))),
+            ("empty.tcl".to_string(), Stats::default()),
+        ];
+        let table = render_table(&rows);
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[0].starts_with("file"));
+        assert!(lines[1].starts_with("a.tcl      2"));
+        assert!(lines[1].contains("long (3)") && lines[1].contains("proc long (2)"));
+        let empty: Vec<&str> = lines[2].split_whitespace().collect();
+        assert_eq!(empty, ["empty.tcl", "0", "0", "0", "0", "-", "-", "0.00"]);
+        let json = render_json(&rows);
+        assert!(json.starts_with("[{\"file\":\"a.tcl\",\"procs\":2,\"whens\":1,\"statements\":6,"));
+        assert!(json.contains("\"namespaces\":{\"HTTP\":2}"));
+        assert!(json.ends_with("\"longest_proc\":null,\"complexity\":[],\"comment_ratio\":0.0000,\"namespaces\":{}}]\n"));
+    }
+
+    #[test]
+    fn counts_calls_per_namespace() {
+        let source = b"when HTTP_REQUEST {\n    if {[HTTP::host] eq \"a\"} {\n        set u [HTTP::uri]\n    }\n    switch [IP::client_addr] {\n        x {\n            mylib::log [HTTP::path]\n        }\n    }\n}\n";
+        let stats = Stats::collect(&parse(source));
+        assert_eq!(
+            stats.namespaces,
+            [
+                ("HTTP".to_string(), 3),
+                ("IP".to_string(), 1),
+                ("mylib".to_string(), 1)
+            ]
+        );
+    }
+}
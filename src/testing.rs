@@ -0,0 +1,252 @@
+// Golden-file tests for code embedding the formatter, behind the `testing`
+// feature. A corpus is a directory of cases, each a directory holding an
+// `input.tcl` and the `expected.tcl` it formats to, plus optionally a
+// `.tclfmt.toml` whose options and passes apply to that case alone. Running
+// a corpus after upgrading the crate shows how the output changed as diffs.
+use std::path::{Path, PathBuf};
+
+use crate::{
+    config::{Config, ConfigFail, CONFIG_FILE_NAME},
+    diff::UnifiedDiff,
+    directive,
+    editorconfig::EditorConfig,
+    formatter::{FormatFail, Formatter, FormatterOptions},
+    lexer::Lexer,
+    parser::Parser,
+    passes::{self, PassFail},
+};
+
+pub const INPUT_FILE_NAME: &str = "input.tcl";
+pub const EXPECTED_FILE_NAME: &str = "expected.tcl";
+
+// Why a case failed
+#[derive(Debug)]
+pub enum CaseFail {
+    Io(std::io::Error), // reading the case, or writing it when blessing
+    Config(ConfigFail), // its .tclfmt.toml or a directive in its input
+    Pass(PassFail),
+    Format(FormatFail),
+    Mismatch(String), // the diff from `expected.tcl` to the output
+}
+type Result<T> = std::result::Result<T, CaseFail>;
+
+impl std::fmt::Display for CaseFail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Config(e) => write!(f, "invalid config: {e}"),
+            Self::Pass(e) => write!(f, "{e:?}"),
+            Self::Format(e) => write!(f, "input does not format: {e:?}"),
+            Self::Mismatch(diff) => write!(f, "output differs from {EXPECTED_FILE_NAME}:\n{diff}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for CaseFail {
+    fn from(e: std::io::Error) -> Self {
+        CaseFail::Io(e)
+    }
+}
+
+pub struct Corpus {
+    dir: PathBuf,
+    options: FormatterOptions, // what the config and directives of a case apply over
+    bless: bool,
+}
+
+impl Corpus {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            options: FormatterOptions::default(),
+            bless: false,
+        }
+    }
+
+    pub fn options(mut self, options: FormatterOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    // write the output to `expected.tcl` instead of comparing, to accept the
+    // changes of an upgrade once reviewed
+    pub fn bless(mut self, bless: bool) -> Self {
+        self.bless = bless;
+        self
+    }
+
+    // The name and failure of each failing case, in name order. Every
+    // directory in the corpus with an `input.tcl` is a case.
+    pub fn run(&self) -> std::io::Result<Vec<(String, CaseFail)>> {
+        let mut cases: Vec<PathBuf> = std::fs::read_dir(&self.dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<_>>()?;
+        cases.retain(|case| case.join(INPUT_FILE_NAME).is_file());
+        cases.sort();
+        let failures = cases
+            .iter()
+            .filter_map(|case| {
+                let name = case.file_name()?.to_string_lossy().into_owned();
+                self.run_case(case).err().map(|e| (name, e))
+            })
+            .collect();
+        Ok(failures)
+    }
+
+    // `run`, panicking with every failure, for use in a `#[test]`
+    pub fn check(&self) {
+        let failures = match self.run() {
+            Ok(failures) => failures,
+            Err(e) => panic!("could not read corpus {}: {e}", self.dir.display()),
+        };
+        if failures.is_empty() {
+            return;
+        }
+        let mut message = format!(
+            "{} case(s) failed in {}",
+            failures.len(),
+            self.dir.display()
+        );
+        for (name, e) in &failures {
+            message.push_str(&format!("\n\n{name}: {e}"));
+        }
+        panic!("{message}");
+    }
+
+    fn run_case(&self, case: &Path) -> Result<()> {
+        let input = std::fs::read(case.join(INPUT_FILE_NAME))?;
+        let config_path = case.join(CONFIG_FILE_NAME);
+        let mut config = match config_path.is_file() {
+            true => Config::load(&config_path).map_err(CaseFail::Config)?,
+            false => Config::default(),
+        };
+        directive::configure(&mut config, &input).map_err(CaseFail::Config)?;
+        // a preset replaces the corpus' options, single options go over them
+        let mut options = match config.style {
+            Some(_) => config
+                .formatter_options(None, &EditorConfig::default())
+                .map_err(CaseFail::Config)?,
+            None => self.options.clone(),
+        };
+        config.apply_options(&mut options);
+        let output = format(&input, &config.passes, options)?;
+        let expected_path = case.join(EXPECTED_FILE_NAME);
+        if self.bless {
+            std::fs::write(expected_path, output)?;
+            return Ok(());
+        }
+        let expected = std::fs::read(expected_path)?;
+        match diff(&expected, &output) {
+            Some(diff) => Err(CaseFail::Mismatch(diff)),
+            None => Ok(()),
+        }
+    }
+}
+
+// Panics with a diff unless `input` formats to `expected` with `options`
+pub fn assert_formats(input: &[u8], expected: &[u8], options: FormatterOptions) {
+    let output = match format(input, &[], options) {
+        Ok(output) => output,
+        Err(e) => panic!("{e}"),
+    };
+    if let Some(diff) = diff(expected, &output) {
+        panic!("output differs from the expected text:\n{diff}");
+    }
+}
+
+fn format(input: &[u8], passes: &[String], options: FormatterOptions) -> Result<Vec<u8>> {
+    let (tokens, spans) = Lexer::new()
+        .lex_with_spans(input)
+        .map_err(|e| CaseFail::Format(FormatFail::Lex(e)))?;
+    let mut ast = Parser::new(input, &tokens, &spans)
+        .parse()
+        .map_err(|e| CaseFail::Format(FormatFail::Parse(e)))?;
+    passes::run(&mut ast, passes).map_err(CaseFail::Pass)?;
+    Ok(Formatter::with_options(options).format(ast))
+}
+
+// the unified diff from `expected` to `output`, None if they are equal
+fn diff(expected: &[u8], output: &[u8]) -> Option<String> {
+    let diff = UnifiedDiff::new().render(EXPECTED_FILE_NAME, expected, output);
+    (!diff.is_empty()).then(|| String::from_utf8_lossy(&diff).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{assert_formats, CaseFail, Corpus, EXPECTED_FILE_NAME, INPUT_FILE_NAME};
+    use crate::{config::CONFIG_FILE_NAME, formatter::FormatterOptions};
+
+    fn case(corpus: &Path, name: &str, input: &str, expected: &str) {
+        let dir = corpus.join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(INPUT_FILE_NAME), input).unwrap();
+        std::fs::write(dir.join(EXPECTED_FILE_NAME), expected).unwrap();
+    }
+
+    #[test]
+    fn runs_and_blesses_corpora() {
+        let dir = std::env::temp_dir().join(format!("tclfmt-corpus-{}", std::process::id()));
+        case(&dir, "a-ok", "set a  1\n", "set a 1\n");
+        case(
+            &dir,
+            "b-stale",
+            "if {$a} {\npool p\n}\n",
+            "if {$a} {\npool p\n}\n",
+        );
+        case(&dir, "c-config", "set a \"x\"\n", "set a {x}\n");
+        std::fs::write(
+            dir.join("c-config").join(CONFIG_FILE_NAME),
+            "passes = [\"brace-literals\"]\n",
+        )
+        .unwrap();
+        case(&dir, "d-broken", "if {$a} {\n", "");
+        // not a case
+        std::fs::create_dir_all(dir.join("e-empty")).unwrap();
+
+        let failures = Corpus::new(&dir).run().unwrap();
+        let names: Vec<&str> = failures.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["b-stale", "d-broken"]);
+        assert!(matches!(&failures[0].1, CaseFail::Mismatch(diff) if diff.contains("+    pool p")));
+        assert!(matches!(failures[1].1, CaseFail::Format(_)));
+
+        Corpus::new(&dir).bless(true).run().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.join("b-stale").join(EXPECTED_FILE_NAME)).unwrap(),
+            "if { $a } {\n    pool p\n}\n"
+        );
+        let failures = Corpus::new(&dir).run().unwrap();
+        assert_eq!(failures.len(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "1 case(s) failed")]
+    fn check_panics_with_the_failures() {
+        let dir = std::env::temp_dir().join(format!("tclfmt-check-{}", std::process::id()));
+        case(&dir, "stale", "set a  1\n", "set a  1\n");
+        let result = std::panic::catch_unwind(|| Corpus::new(&dir).check());
+        std::fs::remove_dir_all(&dir).unwrap();
+        if let Err(panic) = result {
+            std::panic::resume_unwind(panic);
+        }
+    }
+
+    #[test]
+    fn asserts_formatting_with_options() {
+        let options = FormatterOptions {
+            indent_width: 2,
+            ..FormatterOptions::default()
+        };
+        assert_formats(
+            b"if {$a} {\npool p\n}\n",
+            b"if { $a } {\n  pool p\n}\n",
+            options,
+        );
+        let result = std::panic::catch_unwind(|| {
+            assert_formats(b"set a  1\n", b"set a  1\n", FormatterOptions::default())
+        });
+        assert!(result.is_err());
+    }
+}
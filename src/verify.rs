@@ -0,0 +1,297 @@
+// Semantic round-trip check of formatted output. Both the input and the
+// output are lexed and compared token by token, ignoring what only affects
+// layout: line breaks, backslash-newline continuations, and whitespace
+// between words. Whitespace inside quoted words is part of their value, and
+// so is the lack of it between operator characters (`<<` is not `< <`), so
+// both are compared too. Where commands end is compared at the top level,
+// a newline and a `;` alike, and braced words other than the scripts,
+// expressions and lists of `SCRIPT_COMMANDS` are compared byte for byte.
+use crate::lexer::{Lexer, LexerFail, Token};
+
+#[derive(Debug)]
+pub enum VerifyFail {
+    Lex(LexerFail), // of the formatted output; the input lexed before formatting
+    Mismatch {
+        offset: usize,  // byte offset in the input where the meaning changes
+        input: String,  // what the input has there, empty at its end
+        output: String, // what the output has instead, empty at its end
+    },
+}
+type Result<T> = std::result::Result<T, VerifyFail>;
+
+// passes if `output` holds the same significant tokens as `input`
+pub fn verify(input: &[u8], output: &[u8]) -> Result<()> {
+    let before = significant(input)?;
+    let after = significant(output)?;
+    let mut pairs = before.iter().zip(after.iter());
+    let mismatch = match pairs.find(|((a, _), (b, _))| a != b) {
+        Some(((a, offset), (b, _))) => Some((a.as_slice(), *offset, b.as_slice())),
+        None if before.len() > after.len() => {
+            let (a, offset) = &before[after.len()];
+            Some((a.as_slice(), *offset, &b""[..]))
+        }
+        None if before.len() < after.len() => {
+            Some((&b""[..], input.len(), after[before.len()].0.as_slice()))
+        }
+        None => None,
+    };
+    match mismatch {
+        Some((a, offset, b)) => Err(VerifyFail::Mismatch {
+            offset,
+            input: String::from_utf8_lossy(a).into_owned(),
+            output: String::from_utf8_lossy(b).into_owned(),
+        }),
+        None => Ok(()),
+    }
+}
+
+// Commands whose braced words are scripts, expressions or lists, the layout
+// of which the formatter may change
+const SCRIPT_COMMANDS: &[&[u8]] = &[
+    b"if", b"elseif", b"else", b"proc", b"when", b"switch", b"eval", b"uplevel", b"after", b"expr",
+    b"while", b"for", b"foreach", b"catch", b"array",
+];
+
+// what a top-level command separator, a newline or a `;`, compares as
+const SEPARATOR: &[u8] = b";";
+
+// a script, or the body of a switch, being read
+struct Frame {
+    braced: bool,             // closed by `}` rather than `]`
+    switch: bool,             // the arms of a switch, whose braced words are scripts
+    command: Option<Vec<u8>>, // the first word of the command being read
+}
+
+impl Frame {
+    fn new(braced: bool, switch: bool) -> Self {
+        Frame {
+            braced,
+            switch,
+            command: None,
+        }
+    }
+
+    // whether a braced word starting here is a script, expression or list
+    fn takes_scripts(&self) -> bool {
+        self.switch
+            || self
+                .command
+                .as_deref()
+                .is_some_and(|command| SCRIPT_COMMANDS.contains(&command))
+    }
+}
+
+// the tokens of `source` that carry meaning, as written, with their offsets
+fn significant(source: &[u8]) -> Result<Vec<(Vec<u8>, usize)>> {
+    let (tokens, spans) = Lexer::new()
+        .lex_with_spans(source)
+        .map_err(VerifyFail::Lex)?;
+    let mut significant: Vec<(Vec<u8>, usize)> = Vec::with_capacity(tokens.len());
+    let mut quoted = false;
+    let mut previous: Option<(&Token, usize)> = None; // last token and where it ended
+    let mut frames = vec![Frame::new(false, false)];
+    let mut verbatim: Option<(usize, usize)> = None; // start and depth of a braced word
+    let mut last: Option<(&Token, usize)> = None; // like `previous`, across lines
+    let mut continued = false; // whether the last token continued its line
+    for (idx, (token, span)) in tokens.iter().zip(&spans).enumerate() {
+        let escaped = matches!(last, Some((Token::Other(b"\\"), end)) if end == span.start);
+        let gap = &source[last.map_or(0, |(_, end)| end)..span.start];
+        last = Some((token, span.end));
+        if let Some((start, depth)) = verbatim.as_mut() {
+            match token {
+                Token::LCurlyBracket if !escaped => *depth += 1,
+                Token::RCurlyBracket if !escaped => *depth -= 1,
+                _ => {}
+            }
+            if *depth == 0 {
+                significant.push((source[*start..span.end].to_vec(), *start));
+                previous = Some((token, span.end));
+                verbatim = None;
+            }
+            continue;
+        }
+        // a backslash-newline continuation is a line break too
+        let continuation = matches!(token, Token::Other(b"\\"))
+            && matches!(tokens.get(idx + 1), Some(Token::Newline))
+            && spans
+                .get(idx + 1)
+                .is_some_and(|next| next.start == span.end);
+        if continuation && !quoted {
+            continued = true;
+            continue;
+        }
+        let line_break = matches!(token, Token::Newline) && !std::mem::take(&mut continued);
+        let top_level = frames.len() == 1;
+        let frame = frames.last_mut().expect("the top-level script");
+        let semicolon = gap.iter().position(|&c| c == b';');
+        if !quoted && (semicolon.is_some() || line_break) {
+            frame.command = None;
+            let after_command = significant
+                .last()
+                .is_some_and(|(text, _)| text != SEPARATOR);
+            if top_level && after_command {
+                let offset = semicolon.map_or(span.start, |pos| span.start - gap.len() + pos);
+                significant.push((SEPARATOR.to_vec(), offset));
+            }
+        }
+        if matches!(token, Token::Newline) && !quoted {
+            previous = None;
+            continue;
+        }
+        if let (true, Some((_, end))) = (quoted, previous) {
+            let gap = &source[end..span.start];
+            if !gap.is_empty() {
+                significant.push((gap.to_vec(), end));
+            }
+        }
+        if !quoted {
+            let starts_word = frame.command.is_none() || !gap.is_empty();
+            match token {
+                Token::LCurlyBracket if !escaped => {
+                    if starts_word && frame.command.is_some() && !frame.takes_scripts() {
+                        verbatim = Some((span.start, 1));
+                        continue;
+                    }
+                    let switch = frame.command.as_deref() == Some(b"switch".as_slice());
+                    frame.command.get_or_insert_with(Vec::new);
+                    frames.push(Frame::new(true, switch));
+                }
+                Token::LSquareBracket if !escaped => {
+                    frame.command.get_or_insert_with(Vec::new);
+                    frames.push(Frame::new(false, false));
+                }
+                Token::RCurlyBracket | Token::RSquareBracket if !escaped && !top_level => {
+                    let braced = matches!(token, Token::RCurlyBracket);
+                    if frame.braced == braced {
+                        frames.pop();
+                    }
+                }
+                _ => {
+                    frame.command.get_or_insert_with(|| Vec::from(token));
+                }
+            }
+        }
+        // adjacent operator characters form one operator
+        let joined = matches!(previous, Some((before, end)) if end == span.start
+            && operator(before) && operator(token));
+        if let (true, Some((last, _))) = (joined && !quoted, significant.last_mut()) {
+            last.extend_from_slice(&Vec::from(token));
+            previous = Some((token, span.end));
+            continue;
+        }
+        if matches!(token, Token::Quote) && !escaped {
+            quoted = !quoted;
+        }
+        significant.push((Vec::from(token), span.start));
+        previous = Some((token, span.end));
+    }
+    if significant
+        .last()
+        .is_some_and(|(text, _)| text == SEPARATOR)
+    {
+        significant.pop();
+    }
+    Ok(significant)
+}
+
+fn operator(token: &Token) -> bool {
+    let text = Vec::from(token);
+    !text.is_empty() && text.iter().all(|c| b"<>=!&|*".contains(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify, VerifyFail};
+
+    fn mismatch(input: &str, output: &str) -> bool {
+        matches!(
+            verify(input.as_bytes(), output.as_bytes()),
+            Err(VerifyFail::Mismatch { .. })
+        )
+    }
+
+    #[test]
+    fn accepts_layout_changes() {
+        assert!(verify(b"set a  1\n", b"set a 1\n").is_ok());
+        assert!(verify(
+            b"if {$a<$b} {\nset c 1}\n",
+            b"if {$a < $b} {\n    set c 1\n}\n"
+        )
+        .is_ok());
+        assert!(verify(b"set a \\\n    1\n", b"set a 1\n").is_ok());
+        assert!(verify(b"if {$a<<2} {}\n", b"if {$a << 2} {}\n").is_ok());
+    }
+
+    #[test]
+    fn rejects_split_operators() {
+        assert!(mismatch("if {$a<<2} {}\n", "if {$a < < 2} {}\n"));
+        assert!(mismatch("if {$y>>1} {}\n", "if {$y > > 1} {}\n"));
+        assert!(mismatch("if {$a==1} {}\n", "if {$a = = 1} {}\n"));
+        assert!(mismatch("if {$a&&$b} {}\n", "if {$a & & $b} {}\n"));
+        assert!(mismatch("if {$a < $b} {}\n", "if {$a << $b} {}\n"));
+    }
+
+    #[test]
+    fn rejects_changed_quoted_whitespace() {
+        assert!(mismatch("set a \"x  y\"\n", "set a \"x y\"\n"));
+        assert!(mismatch("set a \"x\n  y\"\n", "set a \"x\n    y\"\n"));
+        assert!(mismatch("set a \"x\n  y\"\n", "set a \"x\ny\"\n"));
+    }
+
+    #[test]
+    fn rejects_joined_and_split_commands() {
+        assert!(mismatch("set a 1\nset b 2\n", "set a 1 set b 2\n"));
+        assert!(mismatch("set a 1; set b 2\n", "set a 1 set b 2\n"));
+        assert!(mismatch(
+            "if {$a} {\n} else {\n}\n",
+            "if {$a} {\n}\nelse {\n}\n"
+        ));
+        assert!(verify(b"set a 1; set b 2\n", b"set a 1\n\nset b 2").is_ok());
+        assert!(verify(
+            b"when X {\nset a 1; set b 2\n}\n",
+            b"when X {\n    set a 1\n    set b 2\n}\n"
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn rejects_changed_braced_whitespace() {
+        assert!(mismatch("set a {a  b}\n", "set a {a b}\n"));
+        assert!(mismatch(
+            "when X {\n    set a {a  b}\n}\n",
+            "when X {\n    set a {a b}\n}\n"
+        ));
+        assert!(verify(
+            b"proc p {a  b} {set c {x  y}}\n",
+            b"proc p { a b } {\n    set c {x  y}\n}\n"
+        )
+        .is_ok());
+        assert!(verify(
+            b"switch $a {\nx {pool p}\n}\n",
+            b"switch $a {\n    x {\n        pool p\n    }\n}\n"
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn reports_the_input_offset() {
+        match verify(b"set a \"x  y\"\n", b"set a \"x y\"\n") {
+            Err(VerifyFail::Mismatch {
+                offset,
+                input,
+                output,
+            }) => {
+                assert_eq!(offset, 8);
+                assert_eq!(input, "  ");
+                assert_eq!(output, " ");
+            }
+            other => panic!("expected a mismatch, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn accepts_continued_lines() {
+        assert!(verify(b"proc p {a b} {\n}\n", b"proc p {a \\\n        b} {\n}\n").is_ok());
+        assert!(mismatch("set a \"x \\\ny\"\n", "set a \"x y\"\n"));
+    }
+}
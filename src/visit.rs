@@ -0,0 +1,361 @@
+// Recursive traversal of `Ast`, shared by lints, rewrites and analyzers.
+//
+// Every `visit_*` method defaults to the matching `walk_*` function, so an
+// implementor only overrides the nodes it cares about and calls `walk_*`
+// itself when it still wants to descend into the children.
+use crate::ast::{Ast, Condition, Statement, Text};
+
+pub trait Visit {
+    fn visit_ast(&mut self, ast: &Ast) {
+        walk_ast(self, ast);
+    }
+
+    fn visit_block(&mut self, trees: &[Ast]) {
+        walk_block(self, trees);
+    }
+
+    fn visit_comment(&mut self, _text: &[u8]) {}
+
+    fn visit_proc(&mut self, name: &[u8], parameters: &[Text], body: &Ast) {
+        walk_proc(self, name, parameters, body);
+    }
+
+    fn visit_if(
+        &mut self,
+        condition_body_clauses: &[(Condition, Ast)],
+        block_if_false: Option<&Ast>,
+    ) {
+        walk_if(self, condition_body_clauses, block_if_false);
+    }
+
+    fn visit_switch(&mut self, condition: &[u8], arms: &[(Text, Option<Ast>)]) {
+        walk_switch(self, condition, arms);
+    }
+
+    fn visit_when(&mut self, event_name: &[u8], body: &Ast) {
+        walk_when(self, event_name, body);
+    }
+
+    fn visit_script(&mut self, command: &[u8], arguments: &[Text], body: &Ast) {
+        walk_script(self, command, arguments, body);
+    }
+
+    fn visit_statement(&mut self, _statement: &Statement) {}
+
+    fn visit_empty_line(&mut self) {}
+}
+
+pub fn walk_ast<V: Visit + ?Sized>(v: &mut V, ast: &Ast) {
+    match ast {
+        Ast::Block(trees) => v.visit_block(trees),
+        Ast::Comment(text) | Ast::TrailingComment(text) | Ast::Verbatim(text) => {
+            v.visit_comment(text)
+        }
+        Ast::Procedure {
+            name,
+            parameters,
+            body,
+        } => v.visit_proc(name, parameters, body),
+        Ast::If {
+            condition_body_clauses,
+            maybe_block_if_false,
+        } => v.visit_if(condition_body_clauses, maybe_block_if_false.as_deref()),
+        Ast::Switch {
+            condition,
+            value_block_or_fallthrough_vec,
+        } => v.visit_switch(condition, value_block_or_fallthrough_vec),
+        Ast::When { event_name, body } => v.visit_when(event_name, body),
+        Ast::Script {
+            command,
+            arguments,
+            body,
+        } => v.visit_script(command, arguments, body),
+        Ast::Statement(s) => v.visit_statement(s),
+        Ast::EmptyLine => v.visit_empty_line(),
+        Ast::Semicolon => {}
+    }
+}
+
+pub fn walk_block<V: Visit + ?Sized>(v: &mut V, trees: &[Ast]) {
+    for tree in trees {
+        v.visit_ast(tree);
+    }
+}
+
+pub fn walk_proc<V: Visit + ?Sized>(v: &mut V, _name: &[u8], _parameters: &[Text], body: &Ast) {
+    v.visit_ast(body);
+}
+
+pub fn walk_if<V: Visit + ?Sized>(
+    v: &mut V,
+    condition_body_clauses: &[(Condition, Ast)],
+    block_if_false: Option<&Ast>,
+) {
+    for (_, body) in condition_body_clauses {
+        v.visit_ast(body);
+    }
+    if let Some(body) = block_if_false {
+        v.visit_ast(body);
+    }
+}
+
+pub fn walk_switch<V: Visit + ?Sized>(v: &mut V, _condition: &[u8], arms: &[(Text, Option<Ast>)]) {
+    for body in arms.iter().filter_map(|(_, body)| body.as_ref()) {
+        v.visit_ast(body);
+    }
+}
+
+pub fn walk_when<V: Visit + ?Sized>(v: &mut V, _event_name: &[u8], body: &Ast) {
+    v.visit_ast(body);
+}
+
+pub fn walk_script<V: Visit + ?Sized>(v: &mut V, _command: &[u8], _arguments: &[Text], body: &Ast) {
+    v.visit_ast(body);
+}
+
+// Mutable twin of `Visit`: nodes are handed out by `&mut` so rewrites can
+// edit them in place, including replacing whole subtrees via `visit_ast_mut`.
+pub trait VisitMut {
+    fn visit_ast_mut(&mut self, ast: &mut Ast) {
+        walk_ast_mut(self, ast);
+    }
+
+    fn visit_block_mut(&mut self, trees: &mut Vec<Ast>) {
+        walk_block_mut(self, trees);
+    }
+
+    fn visit_comment_mut(&mut self, _text: &mut Text) {}
+
+    fn visit_proc_mut<'src>(
+        &mut self,
+        name: &mut Text<'src>,
+        parameters: &mut Vec<Text<'src>>,
+        body: &mut Ast<'src>,
+    ) {
+        walk_proc_mut(self, name, parameters, body);
+    }
+
+    fn visit_if_mut<'src>(
+        &mut self,
+        condition_body_clauses: &mut Vec<(Condition<'src>, Ast<'src>)>,
+        maybe_block_if_false: &mut Option<Box<Ast<'src>>>,
+    ) {
+        walk_if_mut(self, condition_body_clauses, maybe_block_if_false);
+    }
+
+    fn visit_switch_mut<'src>(
+        &mut self,
+        condition: &mut Text<'src>,
+        arms: &mut Vec<(Text<'src>, Option<Ast<'src>>)>,
+    ) {
+        walk_switch_mut(self, condition, arms);
+    }
+
+    fn visit_when_mut<'src>(&mut self, event_name: &mut Text<'src>, body: &mut Ast<'src>) {
+        walk_when_mut(self, event_name, body);
+    }
+
+    fn visit_script_mut<'src>(
+        &mut self,
+        command: &mut Text<'src>,
+        arguments: &mut Vec<Text<'src>>,
+        body: &mut Ast<'src>,
+    ) {
+        walk_script_mut(self, command, arguments, body);
+    }
+
+    fn visit_statement_mut(&mut self, _statement: &mut Statement) {}
+
+    fn visit_empty_line_mut(&mut self) {}
+}
+
+pub fn walk_ast_mut<V: VisitMut + ?Sized>(v: &mut V, ast: &mut Ast) {
+    match ast {
+        Ast::Block(trees) => v.visit_block_mut(trees),
+        Ast::Comment(text) | Ast::TrailingComment(text) | Ast::Verbatim(text) => {
+            v.visit_comment_mut(text)
+        }
+        Ast::Procedure {
+            name,
+            parameters,
+            body,
+        } => v.visit_proc_mut(name, parameters, body),
+        Ast::If {
+            condition_body_clauses,
+            maybe_block_if_false,
+        } => v.visit_if_mut(condition_body_clauses, maybe_block_if_false),
+        Ast::Switch {
+            condition,
+            value_block_or_fallthrough_vec,
+        } => v.visit_switch_mut(condition, value_block_or_fallthrough_vec),
+        Ast::When { event_name, body } => v.visit_when_mut(event_name, body),
+        Ast::Script {
+            command,
+            arguments,
+            body,
+        } => v.visit_script_mut(command, arguments, body),
+        Ast::Statement(s) => v.visit_statement_mut(s),
+        Ast::EmptyLine => v.visit_empty_line_mut(),
+        Ast::Semicolon => {}
+    }
+}
+
+pub fn walk_block_mut<V: VisitMut + ?Sized>(v: &mut V, trees: &mut [Ast]) {
+    for tree in trees {
+        v.visit_ast_mut(tree);
+    }
+}
+
+pub fn walk_proc_mut<V: VisitMut + ?Sized>(
+    v: &mut V,
+    _name: &mut Text,
+    _parameters: &mut [Text],
+    body: &mut Ast,
+) {
+    v.visit_ast_mut(body);
+}
+
+pub fn walk_if_mut<V: VisitMut + ?Sized>(
+    v: &mut V,
+    condition_body_clauses: &mut [(Condition, Ast)],
+    maybe_block_if_false: &mut Option<Box<Ast>>,
+) {
+    for (_, body) in condition_body_clauses {
+        v.visit_ast_mut(body);
+    }
+    if let Some(body) = maybe_block_if_false {
+        v.visit_ast_mut(body);
+    }
+}
+
+pub fn walk_switch_mut<V: VisitMut + ?Sized>(
+    v: &mut V,
+    _condition: &mut Text,
+    arms: &mut [(Text, Option<Ast>)],
+) {
+    for body in arms.iter_mut().filter_map(|(_, body)| body.as_mut()) {
+        v.visit_ast_mut(body);
+    }
+}
+
+pub fn walk_when_mut<V: VisitMut + ?Sized>(v: &mut V, _event_name: &mut Text, body: &mut Ast) {
+    v.visit_ast_mut(body);
+}
+
+pub fn walk_script_mut<V: VisitMut + ?Sized>(
+    v: &mut V,
+    _command: &mut Text,
+    _arguments: &mut [Text],
+    body: &mut Ast,
+) {
+    v.visit_ast_mut(body);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::{walk_ast_mut, walk_proc, Visit, VisitMut};
+    use crate::{
+        ast::{Ast, Statement, Text},
+        lexer::Lexer,
+        parser::Parser,
+    };
+
+    fn parse(source: &[u8]) -> Ast<'_> {
+        let (tokens, spans) = Lexer::new().lex_with_spans(source).unwrap();
+        Parser::new(source, &tokens, &spans).parse().unwrap()
+    }
+
+    const SOURCE: &[u8] = b"# top
+proc p {a} {
+    if {$a} {
+        set b 1
+    } else {
+        switch $a {
+            x {
+                set c 2
+            }
+        }
+    }
+}
+when HTTP_REQUEST {
+    eval {
+        set d 3
+    }
+}
+";
+
+    #[derive(Default)]
+    struct Counter {
+        comments: usize,
+        procs: Vec<String>,
+        statements: usize,
+        skip_procs: bool,
+    }
+
+    impl Visit for Counter {
+        fn visit_comment(&mut self, _text: &[u8]) {
+            self.comments += 1;
+        }
+
+        fn visit_proc(&mut self, name: &[u8], parameters: &[Text], body: &Ast) {
+            self.procs.push(String::from_utf8_lossy(name).into_owned());
+            if !self.skip_procs {
+                walk_proc(self, name, parameters, body);
+            }
+        }
+
+        fn visit_statement(&mut self, _statement: &Statement) {
+            self.statements += 1;
+        }
+    }
+
+    #[test]
+    fn visits_every_nested_node() {
+        let mut counter = Counter::default();
+        counter.visit_ast(&parse(SOURCE));
+        assert_eq!(counter.comments, 1);
+        assert_eq!(counter.procs, ["p"]);
+        assert_eq!(counter.statements, 3);
+    }
+
+    #[test]
+    fn overrides_decide_whether_to_descend() {
+        let mut counter = Counter {
+            skip_procs: true,
+            ..Counter::default()
+        };
+        counter.visit_ast(&parse(SOURCE));
+        assert_eq!(counter.procs, ["p"]);
+        assert_eq!(counter.statements, 1);
+    }
+
+    struct Uppercase;
+
+    impl VisitMut for Uppercase {
+        fn visit_comment_mut(&mut self, text: &mut Text) {
+            *text = Cow::Owned(text.to_ascii_uppercase());
+        }
+
+        fn visit_ast_mut(&mut self, ast: &mut Ast) {
+            if matches!(ast, Ast::EmptyLine) {
+                *ast = Ast::Comment(Cow::Borrowed(b"was empty"));
+            }
+            walk_ast_mut(self, ast);
+        }
+    }
+
+    #[test]
+    fn rewrites_nodes_in_place() {
+        let mut ast = parse(b"# one\nwhen X {\n    # two\n\n    set a 1\n}\n");
+        Uppercase.visit_ast_mut(&mut ast);
+        let mut counter = Counter::default();
+        counter.visit_ast(&ast);
+        assert_eq!(counter.comments, 3);
+        let Ast::Block(trees) = &ast else {
+            panic!("expected a block");
+        };
+        assert!(matches!(&trees[0], Ast::Comment(text) if &text[..] == b"ONE"));
+    }
+}
@@ -0,0 +1,130 @@
+// Polling file watcher. Saves are picked up by comparing modification times
+// between scans, and reported once the files have stopped changing for
+// `QUIET`, so an editor writing a file in several steps triggers one event.
+//
+// This stands in for a filesystem notification backend (inotify, FSEvents,
+// ReadDirectoryChangesW), which would need a dependency the crate can't take.
+// Polling has limits those don't: a save is seen up to `POLL` plus `QUIET`
+// late, every scan walks the whole tree, so its cost grows with the number
+// of files, and a write that leaves the modification time as it was, as on
+// filesystems with coarse timestamps when a file is saved twice within one
+// tick, goes unnoticed.
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::{Duration, SystemTime};
+
+use crate::is_tcl;
+
+const POLL: Duration = Duration::from_millis(250);
+const QUIET: Duration = Duration::from_millis(100);
+
+pub struct Watcher {
+    dir: PathBuf,
+    seen: HashMap<PathBuf, SystemTime>,
+}
+
+impl Watcher {
+    pub fn new(dir: &Path) -> io::Result<Self> {
+        let mut seen = HashMap::new();
+        scan(dir, &mut seen)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            seen,
+        })
+    }
+
+    // Blocks until files were created or modified, returning them sorted
+    pub fn wait(&mut self) -> io::Result<Vec<PathBuf>> {
+        let mut changed = Vec::new();
+        loop {
+            sleep(if changed.is_empty() { POLL } else { QUIET });
+            let fresh = self.changes()?;
+            if fresh.is_empty() && !changed.is_empty() {
+                changed.sort();
+                changed.dedup();
+                return Ok(changed);
+            }
+            changed.extend(fresh);
+        }
+    }
+
+    // Records the current modification time of a file we wrote ourselves
+    pub fn refresh(&mut self, path: &Path) -> io::Result<()> {
+        let modified = std::fs::metadata(path)?.modified()?;
+        self.seen.insert(path.to_path_buf(), modified);
+        Ok(())
+    }
+
+    fn changes(&mut self) -> io::Result<Vec<PathBuf>> {
+        let mut current = HashMap::new();
+        scan(&self.dir, &mut current)?;
+        let changed = current
+            .iter()
+            .filter(|(path, modified)| self.seen.get(*path) != Some(modified))
+            .map(|(path, _)| path.clone())
+            .collect();
+        self.seen = current;
+        Ok(changed)
+    }
+}
+
+// Tcl files under `dir`, skipping hidden directories such as .git
+fn scan(dir: &Path, found: &mut HashMap<PathBuf, SystemTime>) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            if !entry.file_name().to_string_lossy().starts_with('.') {
+                scan(&path, found)?;
+            }
+        } else if file_type.is_file() && is_tcl(&path) {
+            // files can vanish between listing and stat
+            if let Ok(modified) = entry.metadata().and_then(|m| m.modified()) {
+                found.insert(path, modified);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::Watcher;
+
+    fn touch(path: &std::path::Path, seconds_ago: u64) {
+        let modified = SystemTime::now() - Duration::from_secs(seconds_ago);
+        let file = std::fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(modified).unwrap();
+    }
+
+    #[test]
+    fn reports_created_and_modified_tcl_files() {
+        let dir = std::env::temp_dir().join(format!("tclfmt-watch-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("old.tcl"), "set a 1\n").unwrap();
+        let mut watcher = Watcher::new(&dir).unwrap();
+        assert!(watcher.changes().unwrap().is_empty());
+
+        std::fs::write(dir.join("lib/new.tcl"), "set a 1\n").unwrap();
+        std::fs::write(dir.join("notes.txt"), "x\n").unwrap();
+        std::fs::write(dir.join(".git/hidden.tcl"), "set a 1\n").unwrap();
+        assert_eq!(watcher.changes().unwrap(), [dir.join("lib/new.tcl")]);
+
+        touch(&dir.join("old.tcl"), 60);
+        assert_eq!(watcher.wait().unwrap(), [dir.join("old.tcl")]);
+
+        // our own writes are not reported back
+        touch(&dir.join("old.tcl"), 30);
+        watcher.refresh(&dir.join("old.tcl")).unwrap();
+        assert!(watcher.changes().unwrap().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,174 @@
+// Display width of source text, for column math in layout decisions and
+// diagnostics. Widths count terminal columns, not bytes: wide CJK and emoji
+// characters take two, combining marks and zero-width characters none, and
+// each byte of invalid UTF-8 one, as it is shown as a replacement character.
+
+// Columns taken by `text` on one line
+pub fn width(text: &[u8]) -> usize {
+    // the common case, a column per character but NUL
+    if text.is_ascii() {
+        return text.iter().filter(|&&c| c != 0).count();
+    }
+    text.utf8_chunks()
+        .map(|chunk| chunk.valid().chars().map(char_width).sum::<usize>() + chunk.invalid().len())
+        .sum()
+}
+
+// Length in bytes of the longest prefix of `text` that fits in `max` columns,
+// never ending inside a multi-byte character
+pub fn fit(text: &[u8], max: usize) -> usize {
+    let mut columns = 0;
+    let mut len = 0;
+    for chunk in text.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            columns += char_width(c);
+            if columns > max {
+                return len;
+            }
+            len += c.len_utf8();
+        }
+        for _ in chunk.invalid() {
+            columns += 1;
+            if columns > max {
+                return len;
+            }
+            len += 1;
+        }
+    }
+    len
+}
+
+// 1-based column of the byte at `offset` in `line`
+pub fn column(line: &[u8], offset: usize) -> usize {
+    width(&line[..offset.min(line.len())]) + 1
+}
+
+// `text` followed by spaces up to `columns` wide
+pub fn pad(text: &str, columns: usize) -> String {
+    let mut padded = text.to_string();
+    let used = width(text.as_bytes());
+    padded.extend(std::iter::repeat_n(' ', columns.saturating_sub(used)));
+    padded
+}
+
+pub fn char_width(c: char) -> usize {
+    match c as u32 {
+        0 => 0,
+        cp if is_zero_width(cp) => 0,
+        cp if is_wide(cp) => 2,
+        _ => 1,
+    }
+}
+
+fn is_zero_width(cp: u32) -> bool {
+    matches!(
+        cp,
+        0x0300..=0x036F   // combining diacritical marks
+            | 0x0483..=0x0489
+            | 0x0591..=0x05BD
+            | 0x0610..=0x061A
+            | 0x064B..=0x065F
+            | 0x0E31 | 0x0E34..=0x0E3A | 0x0E47..=0x0E4E
+            | 0x1AB0..=0x1AFF
+            | 0x1DC0..=0x1DFF
+            | 0x200B..=0x200F // zero width space, joiners, direction marks
+            | 0x2028..=0x202E
+            | 0x2060..=0x2064
+            | 0x20D0..=0x20FF
+            | 0xFE00..=0xFE0F // variation selectors
+            | 0xFE20..=0xFE2F
+            | 0xFEFF          // byte order mark
+            | 0xE0100..=0xE01EF
+    )
+}
+
+// East Asian Wide and Fullwidth blocks, and emoji presentation
+fn is_wide(cp: u32) -> bool {
+    matches!(
+        cp,
+        0x1100..=0x115F       // Hangul Jamo
+            | 0x231A..=0x231B
+            | 0x2329..=0x232A
+            | 0x23E9..=0x23EC
+            | 0x23F0 | 0x23F3
+            | 0x25FD..=0x25FE
+            | 0x2614..=0x2615
+            | 0x2648..=0x2653
+            | 0x267F | 0x2693 | 0x26A1 | 0x26AA..=0x26AB
+            | 0x26BD..=0x26BE
+            | 0x26C4..=0x26C5
+            | 0x26CE | 0x26D4 | 0x26EA | 0x26F2..=0x26F3 | 0x26F5 | 0x26FA | 0x26FD
+            | 0x2705 | 0x270A..=0x270B | 0x2728 | 0x274C | 0x274E
+            | 0x2753..=0x2755 | 0x2757 | 0x2795..=0x2797 | 0x27B0 | 0x27BF
+            | 0x2B1B..=0x2B1C | 0x2B50 | 0x2B55
+            | 0x2E80..=0x303E     // CJK radicals, punctuation
+            | 0x3041..=0x33FF     // kana, CJK compatibility
+            | 0x3400..=0x4DBF     // CJK extension A
+            | 0x4E00..=0x9FFF     // CJK unified ideographs
+            | 0xA000..=0xA4CF     // Yi
+            | 0xA960..=0xA97F
+            | 0xAC00..=0xD7A3     // Hangul syllables
+            | 0xF900..=0xFAFF     // CJK compatibility ideographs
+            | 0xFE10..=0xFE19
+            | 0xFE30..=0xFE6F
+            | 0xFF00..=0xFF60     // fullwidth forms
+            | 0xFFE0..=0xFFE6
+            | 0x16FE0..=0x16FE4
+            | 0x17000..=0x18CFF   // Tangut
+            | 0x1B000..=0x1B2FF   // kana supplement
+            | 0x1F004 | 0x1F0CF | 0x1F18E
+            | 0x1F191..=0x1F19A
+            | 0x1F200..=0x1F251
+            | 0x1F300..=0x1F64F   // pictographs, emoticons
+            | 0x1F680..=0x1F6FF   // transport and map symbols
+            | 0x1F7E0..=0x1F7EB
+            | 0x1F90C..=0x1F9FF   // supplemental symbols and pictographs
+            | 0x1FA70..=0x1FAFF
+            | 0x20000..=0x3FFFD   // CJK extensions B and beyond
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{char_width, column, fit, pad, width};
+
+    #[test]
+    fn counts_columns_not_bytes() {
+        assert_eq!(width(b"set a 1"), 7);
+        assert_eq!(width("héllo".as_bytes()), 5);
+        assert_eq!(width("e\u{301}".as_bytes()), 1); // combining acute accent
+        assert_eq!(width("日本語".as_bytes()), 6);
+        assert_eq!(width("🚀!".as_bytes()), 3);
+        assert_eq!(width("a\u{200b}b".as_bytes()), 2);
+        assert_eq!(width(b"a\xffb"), 3); // invalid UTF-8
+    }
+
+    #[test]
+    fn counts_ascii_like_any_other_text() {
+        for c in 0..=0x7f_u8 {
+            assert_eq!(width(&[c]), char_width(c as char), "byte {c:#04x}");
+        }
+        assert_eq!(width(b"a\0b\tc"), 4);
+        assert_eq!(width(b"a\0b\tc\xff"), 5);
+    }
+
+    #[test]
+    fn fits_whole_characters() {
+        let text = "ab日本".as_bytes();
+        assert_eq!(fit(text, 0), 0);
+        assert_eq!(fit(text, 3), 2); // `日` would straddle the limit
+        assert_eq!(fit(text, 4), 5);
+        assert_eq!(fit(text, 100), text.len());
+        assert_eq!(fit(b"a\xff\xfeb", 2), 2);
+    }
+
+    #[test]
+    fn columns_and_padding() {
+        let line = "日本 x".as_bytes();
+        assert_eq!(column(line, 0), 1);
+        assert_eq!(column(line, 7), 6);
+        assert_eq!(column(line, 100), 7);
+        assert_eq!(pad("日本", 6), "日本  ");
+        assert_eq!(pad("long", 2), "long");
+    }
+}